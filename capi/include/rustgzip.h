@@ -0,0 +1,158 @@
+#ifndef RUSTGZIP_H
+#define RUSTGZIP_H
+
+/* Generated by cbindgen from the rustgzip-capi crate, do not edit by hand. */
+
+#include <stdint.h>
+
+#define Z_OK 0
+
+#define Z_STREAM_END 1
+
+#define Z_STREAM_ERROR -2
+
+#define Z_DATA_ERROR -3
+
+#define Z_BUF_ERROR -5
+
+#define Z_NO_FLUSH 0
+
+#define Z_PARTIAL_FLUSH 1
+
+#define Z_SYNC_FLUSH 2
+
+#define Z_FULL_FLUSH 3
+
+#define Z_FINISH 4
+
+#define Z_BLOCK 5
+
+#define Z_DEFAULT_COMPRESSION -1
+
+#define Z_DEFAULT_STRATEGY 0
+
+#define Z_FILTERED 1
+
+#define Z_HUFFMAN_ONLY 2
+
+#define Z_RLE 3
+
+#define Z_FIXED 4
+
+#define Z_DEFLATED 8
+
+#define MAX_WBITS 15
+
+// The stream state shared with the caller, with the same layout as zlib's `z_stream`
+typedef struct {
+  const uint8_t *next_in;
+  unsigned int avail_in;
+  unsigned long total_in;
+  uint8_t *next_out;
+  unsigned int avail_out;
+  unsigned long total_out;
+  // The message of the last error, or null
+  const char *msg;
+  void *state;
+  // Ignored
+  void *(*zalloc)(void*, unsigned int, unsigned int);
+  // Ignored
+  void (*zfree)(void*, void*);
+  void *opaque;
+  int data_type;
+  unsigned long adler;
+  unsigned long reserved;
+} z_stream;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Initialize `strm` for compression.
+//
+// `window_bits` is 8 to 15 for a zlib stream, -8 to -15 for a raw deflate stream, or 24 to 31 (16 added) for a gzip stream.
+// `level` is 0 to 9, or -1 for the default level 6. `strategy` is one of the `Z_*` strategies of zlib.
+//
+// # Safety
+//
+// `strm` must point to a `z_stream`, which must be ended with `deflateEnd`.
+int deflateInit2(z_stream *strm,
+                 int level,
+                 int method,
+                 int window_bits,
+                 int mem_level,
+                 int strategy);
+
+// Compress the available input into the available output, see `Compressor::compress`.
+// Return `Z_STREAM_END` once the stream is finished and written out, `Z_BUF_ERROR` if no progress was possible.
+//
+// # Safety
+//
+// `strm` must have been initialized with `deflateInit2`, and its buffers must be valid for their lengths.
+int deflate(z_stream *strm,
+            int flush);
+
+// The largest compressed size of `source_len` bytes, in any of the three formats, when the stream is finished without flushes
+unsigned long compressBound(unsigned long source_len);
+
+// Same as `compressBound`, for a stream initialized with `deflateInit2`
+//
+// # Safety
+//
+// `strm` is not used, it may be null.
+unsigned long deflateBound(z_stream *_strm, unsigned long source_len);
+
+// Free the state of a compression stream
+//
+// # Safety
+//
+// `strm` must have been initialized with `deflateInit2`.
+int deflateEnd(z_stream *strm);
+
+// Initialize `strm` for decompression.
+//
+// `window_bits` is 8 to 15 for a zlib stream, -8 to -15 for a raw deflate stream, 24 to 31 (16 added) for a gzip stream,
+// or 40 to 47 (32 added) to detect zlib or gzip from the header. 0 is a zlib stream with the window size of its header.
+//
+// # Safety
+//
+// `strm` must point to a `z_stream`, which must be ended with `inflateEnd`.
+int inflateInit2(z_stream *strm,
+                 int window_bits);
+
+// Decompress the available input into the available output, see `Decompressor::decompress`.
+// Return `Z_STREAM_END` once the end of the stream is decoded and written out, `Z_BUF_ERROR` if no progress was possible,
+// and `Z_DATA_ERROR` with `msg` set if the input is not valid. The flush mode is checked but ignored.
+//
+// # Safety
+//
+// `strm` must have been initialized with `inflateInit2`, and its buffers must be valid for their lengths.
+int inflate(z_stream *strm,
+            int flush);
+
+// Free the state of a decompression stream
+//
+// # Safety
+//
+// `strm` must have been initialized with `inflateInit2`.
+int inflateEnd(z_stream *strm);
+
+// Update the CRC32 `crc` with `len` bytes at `buf`. A null `buf` returns the initial value, 0.
+//
+// # Safety
+//
+// `buf` must be null or valid for `len` bytes.
+unsigned long crc32(unsigned long crc, const uint8_t *buf, unsigned int len);
+
+// Update the Adler-32 `adler` with `len` bytes at `buf`. A null `buf` returns the initial value, 1.
+//
+// # Safety
+//
+// `buf` must be null or valid for `len` bytes.
+unsigned long adler32(unsigned long adler, const uint8_t *buf, unsigned int len);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* RUSTGZIP_H */
@@ -0,0 +1,323 @@
+//! A zlib-compatible C interface to the streaming compressor and decompressor.
+//!
+//! The functions have the names and semantics of their zlib counterparts, so that C code written against zlib
+//! can link this library instead. The header is `include/rustgzip.h`, generated with `cbindgen` from this crate.
+//! The allocation functions of `z_stream` are ignored, the memory is allocated by Rust.
+
+use std::ffi::{c_char, c_int, c_uint, c_ulong, c_void, CString};
+use std::ptr;
+use std::slice;
+use rustgzip::{CompressOptions, Compressor, Decompressor, Flush, Format, Result, State, Status, Strategy};
+
+pub const Z_OK: c_int = 0;
+pub const Z_STREAM_END: c_int = 1;
+pub const Z_STREAM_ERROR: c_int = -2;
+pub const Z_DATA_ERROR: c_int = -3;
+pub const Z_BUF_ERROR: c_int = -5;
+
+pub const Z_NO_FLUSH: c_int = 0;
+pub const Z_PARTIAL_FLUSH: c_int = 1;
+pub const Z_SYNC_FLUSH: c_int = 2;
+pub const Z_FULL_FLUSH: c_int = 3;
+pub const Z_FINISH: c_int = 4;
+pub const Z_BLOCK: c_int = 5;
+
+// only used by C callers, through the header
+#[allow(dead_code)]
+pub const Z_DEFAULT_COMPRESSION: c_int = -1;
+pub const Z_DEFAULT_STRATEGY: c_int = 0;
+pub const Z_FILTERED: c_int = 1;
+pub const Z_HUFFMAN_ONLY: c_int = 2;
+pub const Z_RLE: c_int = 3;
+pub const Z_FIXED: c_int = 4;
+pub const Z_DEFLATED: c_int = 8;
+#[allow(dead_code)]
+pub const MAX_WBITS: c_int = 15;
+
+/// The stream state shared with the caller, with the same layout as zlib's `z_stream`
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub struct z_stream {
+    pub next_in: *const u8,
+    pub avail_in: c_uint,
+    pub total_in: c_ulong,
+    pub next_out: *mut u8,
+    pub avail_out: c_uint,
+    pub total_out: c_ulong,
+    /// The message of the last error, or null
+    pub msg: *const c_char,
+    state: *mut c_void,
+    /// Ignored
+    pub zalloc: Option<unsafe extern "C" fn(*mut c_void, c_uint, c_uint) -> *mut c_void>,
+    /// Ignored
+    pub zfree: Option<unsafe extern "C" fn(*mut c_void, *mut c_void)>,
+    pub opaque: *mut c_void,
+    pub data_type: c_int,
+    pub adler: c_ulong,
+    pub reserved: c_ulong,
+}
+
+enum Engine {
+    Deflate(Compressor),
+    /// No decompressor yet when the format, zlib or gzip, is detected from the first byte
+    Inflate(Option<Decompressor>),
+}
+
+impl Engine {
+    /// What `z_stream.adler` holds: the checksum of the uncompressed data so far
+    fn checksum(&self) -> c_ulong {
+        match self {
+            Engine::Deflate(compressor) => compressor.checksum() as c_ulong,
+            Engine::Inflate(Some(decompressor)) => decompressor.checksum() as c_ulong,
+            Engine::Inflate(None) => 1,
+        }
+    }
+}
+
+/// What `z_stream.state` points to
+struct StreamState {
+    engine: Engine,
+    /// Owns the string `z_stream.msg` points to
+    msg: Option<CString>,
+}
+
+/// Initialize `strm` for compression.
+///
+/// `window_bits` is 8 to 15 for a zlib stream, -8 to -15 for a raw deflate stream, or 24 to 31 (16 added) for a gzip stream.
+/// `level` is 0 to 9, or -1 for the default level 6. `strategy` is one of the `Z_*` strategies of zlib.
+///
+/// # Safety
+///
+/// `strm` must point to a `z_stream`, which must be ended with `deflateEnd`.
+#[no_mangle]
+pub unsafe extern "C" fn deflateInit2(strm: *mut z_stream, level: c_int, method: c_int, window_bits: c_int,
+                                      mem_level: c_int, strategy: c_int) -> c_int {
+    let (format, window_bits) = match window_bits {
+        8..=15 => (Format::Zlib, window_bits),
+        -15..=-8 => (Format::Raw, -window_bits),
+        24..=31 => (Format::Gzip, window_bits - 16),
+        _ => return Z_STREAM_ERROR
+    };
+    let strategy = match strategy {
+        Z_FILTERED => Strategy::Filtered,
+        Z_HUFFMAN_ONLY => Strategy::HuffmanOnly,
+        Z_RLE => Strategy::Rle,
+        Z_FIXED => Strategy::Fixed,
+        Z_DEFAULT_STRATEGY => Strategy::Default,
+        _ => return Z_STREAM_ERROR
+    };
+    if !(-1..=9).contains(&level) || !(1..=9).contains(&mem_level) || method != Z_DEFLATED {
+        return Z_STREAM_ERROR;
+    }
+    let options = CompressOptions {
+        level: u8::try_from(level).ok(),
+        window_bits: Some(window_bits as u8),
+        mem_level: Some(mem_level as u8),
+        strategy,
+        ..Default::default()
+    };
+    match Compressor::with_options(format, &options) {
+        Ok(compressor) => init(strm, Engine::Deflate(compressor)),
+        Err(_) => Z_STREAM_ERROR
+    }
+}
+
+/// Compress the available input into the available output, see `Compressor::compress`.
+/// Return `Z_STREAM_END` once the stream is finished and written out, `Z_BUF_ERROR` if no progress was possible.
+///
+/// # Safety
+///
+/// `strm` must have been initialized with `deflateInit2`, and its buffers must be valid for their lengths.
+#[no_mangle]
+pub unsafe extern "C" fn deflate(strm: *mut z_stream, flush: c_int) -> c_int {
+    let flush = match flush {
+        Z_NO_FLUSH => Flush::None,
+        Z_PARTIAL_FLUSH => Flush::Partial,
+        Z_SYNC_FLUSH => Flush::Sync,
+        Z_FULL_FLUSH => Flush::Full,
+        Z_FINISH => Flush::Finish,
+        _ => return Z_STREAM_ERROR
+    };
+    run(strm, Z_STREAM_ERROR, |engine, input, output| match engine {
+        Engine::Deflate(compressor) => Some(compressor.compress(input, output, flush)),
+        Engine::Inflate(_) => None
+    })
+}
+
+/// The largest compressed size of `source_len` bytes, in any of the three formats, when the stream is finished without flushes
+#[no_mangle]
+pub extern "C" fn compressBound(source_len: c_ulong) -> c_ulong {
+    rustgzip::compress_bound(source_len as usize) as c_ulong
+}
+
+/// Same as `compressBound`, for a stream initialized with `deflateInit2`
+///
+/// # Safety
+///
+/// `strm` is not used, it may be null.
+#[no_mangle]
+pub unsafe extern "C" fn deflateBound(_strm: *mut z_stream, source_len: c_ulong) -> c_ulong {
+    compressBound(source_len)
+}
+
+/// Free the state of a compression stream
+///
+/// # Safety
+///
+/// `strm` must have been initialized with `deflateInit2`.
+#[no_mangle]
+pub unsafe extern "C" fn deflateEnd(strm: *mut z_stream) -> c_int {
+    end(strm)
+}
+
+/// Initialize `strm` for decompression.
+///
+/// `window_bits` is 8 to 15 for a zlib stream, -8 to -15 for a raw deflate stream, 24 to 31 (16 added) for a gzip stream,
+/// or 40 to 47 (32 added) to detect zlib or gzip from the header. 0 is a zlib stream with the window size of its header.
+///
+/// # Safety
+///
+/// `strm` must point to a `z_stream`, which must be ended with `inflateEnd`.
+#[no_mangle]
+pub unsafe extern "C" fn inflateInit2(strm: *mut z_stream, window_bits: c_int) -> c_int {
+    let format = match window_bits {
+        0 | 8..=15 => Some(Format::Zlib),
+        -15..=-8 => Some(Format::Raw),
+        24..=31 => Some(Format::Gzip),
+        40..=47 => None,
+        _ => return Z_STREAM_ERROR
+    };
+    init(strm, Engine::Inflate(format.map(Decompressor::new)))
+}
+
+/// Decompress the available input into the available output, see `Decompressor::decompress`.
+/// Return `Z_STREAM_END` once the end of the stream is decoded and written out, `Z_BUF_ERROR` if no progress was possible,
+/// and `Z_DATA_ERROR` with `msg` set if the input is not valid. The flush mode is checked but ignored.
+///
+/// # Safety
+///
+/// `strm` must have been initialized with `inflateInit2`, and its buffers must be valid for their lengths.
+#[no_mangle]
+pub unsafe extern "C" fn inflate(strm: *mut z_stream, flush: c_int) -> c_int {
+    if !(Z_NO_FLUSH..=Z_BLOCK).contains(&flush) {
+        return Z_STREAM_ERROR;
+    }
+    // a truncated stream is reported as no progress, as in zlib, even with Z_FINISH
+    run(strm, Z_DATA_ERROR, |engine, input, output| match engine {
+        Engine::Inflate(decompressor) => {
+            if decompressor.is_none() {
+                let Some(&first) = input.first() else {
+                    return Some(Ok(Status { consumed: 0, produced: 0, state: State::BufError }));
+                };
+                *decompressor = Some(Decompressor::new(if first == 0x1f { Format::Gzip } else { Format::Zlib }));
+            }
+            decompressor.as_mut().map(|decompressor| decompressor.decompress(input, output, Flush::None))
+        }
+        Engine::Deflate(_) => None
+    })
+}
+
+/// Free the state of a decompression stream
+///
+/// # Safety
+///
+/// `strm` must have been initialized with `inflateInit2`.
+#[no_mangle]
+pub unsafe extern "C" fn inflateEnd(strm: *mut z_stream) -> c_int {
+    end(strm)
+}
+
+/// Update the CRC32 `crc` with `len` bytes at `buf`. A null `buf` returns the initial value, 0.
+///
+/// # Safety
+///
+/// `buf` must be null or valid for `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn crc32(crc: c_ulong, buf: *const u8, len: c_uint) -> c_ulong {
+    if buf.is_null() {
+        return 0;
+    }
+    let mut hasher = crc32fast::Hasher::new_with_initial(crc as u32);
+    hasher.update(slice::from_raw_parts(buf, len as usize));
+    hasher.finalize() as c_ulong
+}
+
+/// Update the Adler-32 `adler` with `len` bytes at `buf`. A null `buf` returns the initial value, 1.
+///
+/// # Safety
+///
+/// `buf` must be null or valid for `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn adler32(adler: c_ulong, buf: *const u8, len: c_uint) -> c_ulong {
+    if buf.is_null() {
+        return 1;
+    }
+    let data = slice::from_raw_parts(buf, len as usize);
+    rustgzip::adler32_combine(adler as u32, rustgzip::adler32(data), data.len() as u64) as c_ulong
+}
+
+unsafe fn init(strm: *mut z_stream, engine: Engine) -> c_int {
+    let Some(strm) = strm.as_mut() else {
+        return Z_STREAM_ERROR;
+    };
+    strm.total_in = 0;
+    strm.total_out = 0;
+    strm.msg = ptr::null();
+    strm.adler = engine.checksum();
+    strm.state = Box::into_raw(Box::new(StreamState { engine, msg: None })).cast();
+    Z_OK
+}
+
+unsafe fn end(strm: *mut z_stream) -> c_int {
+    let Some(strm) = strm.as_mut().filter(|strm| !strm.state.is_null()) else {
+        return Z_STREAM_ERROR;
+    };
+    drop(Box::from_raw(strm.state.cast::<StreamState>()));
+    strm.state = ptr::null_mut();
+    Z_OK
+}
+
+/// Run `step` on the buffers of the stream and move them past the consumed input and the produced output.
+/// `step` returns `None` if the stream was initialized for the other direction.
+unsafe fn run<F>(strm: *mut z_stream, error_code: c_int, step: F) -> c_int
+where
+    F: FnOnce(&mut Engine, &[u8], &mut [u8]) -> Option<Result<Status>>,
+{
+    let Some(strm) = strm.as_mut() else {
+        return Z_STREAM_ERROR;
+    };
+    let Some(state) = strm.state.cast::<StreamState>().as_mut() else {
+        return Z_STREAM_ERROR;
+    };
+    let input = match strm.avail_in {
+        0 => &[][..],
+        len => slice::from_raw_parts(strm.next_in, len as usize),
+    };
+    let output = match strm.avail_out {
+        0 => &mut [][..],
+        len => slice::from_raw_parts_mut(strm.next_out, len as usize),
+    };
+
+    match step(&mut state.engine, input, output) {
+        None => Z_STREAM_ERROR,
+        Some(Ok(status)) => {
+            strm.next_in = strm.next_in.wrapping_add(status.consumed);
+            strm.avail_in -= status.consumed as c_uint;
+            strm.total_in += status.consumed as c_ulong;
+            strm.next_out = strm.next_out.wrapping_add(status.produced);
+            strm.avail_out -= status.produced as c_uint;
+            strm.total_out += status.produced as c_ulong;
+            strm.adler = state.engine.checksum();
+            match status.state {
+                State::Ok => Z_OK,
+                State::BufError => Z_BUF_ERROR,
+                State::StreamEnd => Z_STREAM_END,
+            }
+        }
+        Some(Err(err)) => {
+            let msg = state.msg.insert(CString::new(err.to_string()).unwrap_or_default());
+            strm.msg = msg.as_ptr();
+            error_code
+        }
+    }
+}
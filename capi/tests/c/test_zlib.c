@@ -0,0 +1,142 @@
+/* Exercises the zlib-compatible C interface: built and run by test_capi.rs */
+#include <stdio.h>
+#include <string.h>
+#include "rustgzip.h"
+
+#define CHECK(cond) do { if (!(cond)) { fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, #cond); return 1; } } while (0)
+
+/* What z_stream.adler should hold after `len` bytes of uncompressed data: the CRC32 for gzip, the Adler-32 for zlib, and 1 for raw deflate */
+static unsigned long checksum(int gzip, int raw, const uint8_t *data, unsigned len) {
+    if (gzip) {
+        return crc32(crc32(0, NULL, 0), data, len);
+    }
+    return raw ? 1 : adler32(adler32(0, NULL, 0), data, len);
+}
+
+/* Compress `len` bytes with the given level, windowBits and strategy, with a small output buffer, and return the compressed length */
+static int compress_with(int level, int window_bits, int strategy, const uint8_t *src, unsigned len, uint8_t *dst, unsigned dst_len, unsigned long *out_len) {
+    z_stream strm;
+    memset(&strm, 0, sizeof(strm));
+    CHECK(deflateInit2(&strm, level, Z_DEFLATED, window_bits, 8, strategy) == Z_OK);
+
+    strm.next_in = src;
+    strm.avail_in = len;
+    int ret;
+    do {
+        strm.next_out = dst + strm.total_out;
+        strm.avail_out = dst_len - strm.total_out < 100 ? dst_len - strm.total_out : 100;
+        ret = deflate(&strm, Z_FINISH);
+        CHECK(ret == Z_OK || ret == Z_STREAM_END);
+    } while (ret != Z_STREAM_END);
+
+    CHECK(strm.total_in == len);
+    CHECK(strm.adler == checksum(window_bits > 15, window_bits < 0, src, len));
+    *out_len = strm.total_out;
+    CHECK(deflateEnd(&strm) == Z_OK);
+    return 0;
+}
+
+static int compress(int window_bits, const uint8_t *src, unsigned len, uint8_t *dst, unsigned dst_len, unsigned long *out_len) {
+    return compress_with(Z_DEFAULT_COMPRESSION, window_bits, Z_DEFAULT_STRATEGY, src, len, dst, dst_len, out_len);
+}
+
+/* Decompress with the given windowBits, feeding the input 10 bytes at a time, and check the output */
+static int decompress(int window_bits, const uint8_t *src, unsigned long len, const uint8_t *expected, unsigned expected_len) {
+    static uint8_t dst[1 << 16];
+    z_stream strm;
+    memset(&strm, 0, sizeof(strm));
+    CHECK(inflateInit2(&strm, window_bits) == Z_OK);
+
+    strm.next_out = dst;
+    strm.avail_out = sizeof(dst);
+    int ret;
+    do {
+        strm.next_in = src + strm.total_in;
+        strm.avail_in = len - strm.total_in < 10 ? len - strm.total_in : 10;
+        ret = inflate(&strm, Z_NO_FLUSH);
+        CHECK(ret == Z_OK || ret == Z_STREAM_END);
+    } while (ret != Z_STREAM_END);
+
+    CHECK(strm.total_in == len);
+    CHECK(strm.total_out == expected_len);
+    CHECK(memcmp(dst, expected, expected_len) == 0);
+    CHECK(strm.adler == checksum(src[0] == 0x1f && window_bits > 15, window_bits < 0, expected, expected_len));
+    CHECK(inflateEnd(&strm) == Z_OK);
+    return 0;
+}
+
+int main(void) {
+    static uint8_t src[20000];
+    static uint8_t compressed[1 << 16];
+    unsigned long compressed_len;
+    for (unsigned i = 0; i < sizeof(src); i++) {
+        src[i] = "Hello, world!\n"[i % 14] + (i / 1000 % 3);
+    }
+
+    /* zlib, raw deflate and gzip, and zlib or gzip detected from the header */
+    int formats[][2] = { { 15, 15 }, { -15, -15 }, { 31, 31 }, { 15, 47 }, { 31, 47 }, { 9, 15 }, { -10, -15 }, { 24, 31 } };
+    for (unsigned i = 0; i < sizeof(formats) / sizeof(formats[0]); i++) {
+        CHECK(compress(formats[i][0], src, sizeof(src), compressed, sizeof(compressed), &compressed_len) == 0);
+        CHECK(compressed_len < sizeof(src) / 4);
+        /* CINFO reflects the window size */
+        CHECK(formats[i][0] < 8 || formats[i][0] > 15 || compressed[0] >> 4 == formats[i][0] - 8);
+        CHECK(decompress(formats[i][1], compressed, compressed_len, src, sizeof(src)) == 0);
+    }
+
+    int strategies[] = { Z_FILTERED, Z_HUFFMAN_ONLY, Z_RLE, Z_FIXED };
+    for (unsigned i = 0; i < sizeof(strategies) / sizeof(strategies[0]); i++) {
+        CHECK(compress_with(Z_DEFAULT_COMPRESSION, 15, strategies[i], src, sizeof(src), compressed, sizeof(compressed), &compressed_len) == 0);
+        CHECK(decompress(15, compressed, compressed_len, src, sizeof(src)) == 0);
+    }
+
+    /* incompressible data stays within the bound */
+    static uint8_t noise[20000];
+    uint32_t x = 1;
+    for (unsigned i = 0; i < sizeof(noise); i++) {
+        x = x * 1103515245 + 12345;
+        noise[i] = x >> 24;
+    }
+    CHECK(compress(31, noise, sizeof(noise), compressed, sizeof(compressed), &compressed_len) == 0);
+    CHECK(compressed_len <= compressBound(sizeof(noise)) && compressBound(sizeof(noise)) == deflateBound(NULL, sizeof(noise)));
+    CHECK(decompress(31, compressed, compressed_len, noise, sizeof(noise)) == 0);
+
+    /* FLEVEL reflects the level, and level 0 stores the data */
+    int levels[][2] = { { 0, 0 }, { 1, 0 }, { 5, 1 }, { 6, 2 }, { 9, 3 } };
+    for (unsigned i = 0; i < sizeof(levels) / sizeof(levels[0]); i++) {
+        CHECK(compress_with(levels[i][0], 15, Z_DEFAULT_STRATEGY, src, sizeof(src), compressed, sizeof(compressed), &compressed_len) == 0);
+        CHECK(compressed[1] >> 6 == levels[i][1]);
+        CHECK(levels[i][0] != 0 || compressed_len > sizeof(src));
+        CHECK(decompress(15, compressed, compressed_len, src, sizeof(src)) == 0);
+    }
+
+    /* the gzip trailer holds the CRC32 */
+    CHECK(compress(31, src, sizeof(src), compressed, sizeof(compressed), &compressed_len) == 0);
+    unsigned long crc = crc32(crc32(0, NULL, 0), src, sizeof(src));
+    CHECK(memcmp(compressed + compressed_len - 8, &(uint32_t){ crc }, 4) == 0);
+
+    /* a corrupted stream */
+    compressed[compressed_len - 5] ^= 1;
+    z_stream strm;
+    memset(&strm, 0, sizeof(strm));
+    static uint8_t dst[1 << 16];
+    CHECK(inflateInit2(&strm, 31) == Z_OK);
+    strm.next_in = compressed;
+    strm.avail_in = compressed_len;
+    strm.next_out = dst;
+    strm.avail_out = sizeof(dst);
+    CHECK(inflate(&strm, Z_FINISH) == Z_DATA_ERROR);
+    CHECK(strm.msg != NULL && strstr(strm.msg, "crc32 mismatch") != NULL);
+    CHECK(inflateEnd(&strm) == Z_OK);
+
+    CHECK(crc32(0, (const uint8_t *)"123456789", 9) == 0xcbf43926);
+    CHECK(adler32(adler32(0, NULL, 0), (const uint8_t *)"Wikipedia", 9) == 0x11e60398);
+    CHECK(deflateInit2(&strm, 10, Z_DEFLATED, 15, 8, Z_DEFAULT_STRATEGY) == Z_STREAM_ERROR);
+    CHECK(deflateInit2(&strm, Z_DEFAULT_COMPRESSION, Z_DEFLATED, 7, 8, Z_DEFAULT_STRATEGY) == Z_STREAM_ERROR);
+    CHECK(deflateInit2(&strm, Z_DEFAULT_COMPRESSION, Z_DEFLATED, 15, 10, Z_DEFAULT_STRATEGY) == Z_STREAM_ERROR);
+    CHECK(deflateInit2(&strm, Z_DEFAULT_COMPRESSION, Z_DEFLATED, 15, 0, Z_DEFAULT_STRATEGY) == Z_STREAM_ERROR);
+    CHECK(deflateInit2(&strm, Z_DEFAULT_COMPRESSION, Z_DEFLATED, 15, -1, Z_DEFAULT_STRATEGY) == Z_STREAM_ERROR);
+    CHECK(deflateInit2(&strm, Z_DEFAULT_COMPRESSION, Z_DEFLATED, 15, 8, 5) == Z_STREAM_ERROR);
+
+    printf("ok\n");
+    return 0;
+}
@@ -0,0 +1,25 @@
+use std::env;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Build tests/c/test_zlib.c against the C library (the cdylib, built next to the test binaries) and run it
+#[test]
+fn test_c_program() {
+    // target/<profile>/deps/test_capi-<hash>
+    let target_dir = env::current_exe().unwrap().parent().unwrap().parent().unwrap().to_path_buf();
+    let exe = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("test_zlib");
+
+    let status = Command::new(env::var("CC").unwrap_or_else(|_| "cc".to_string()))
+        .args(["tests/c/test_zlib.c", "-Wall", "-Werror", "-Iinclude", "-o"])
+        .arg(&exe)
+        .arg("-L").arg(&target_dir)
+        .arg(format!("-Wl,-rpath,{}", target_dir.display()))
+        .arg("-lrustgzip_capi")
+        .status()
+        .expect("a C compiler is needed to test the C library (set CC)");
+    assert!(status.success(), "failed to build the C test program");
+
+    let output = Command::new(&exe).output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(output.stdout, b"ok\n");
+}
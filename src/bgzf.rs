@@ -0,0 +1,364 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use crate::deflate::{deflate, deflate_stored, inflate};
+use crate::error::{Error, Result};
+use crate::gzip::write_member;
+use crate::member::{check_trailer, parse_header, Header, FEXTRA};
+
+/// The uncompressed data of a block. A bit less than 64 KiB, so that the block still fits in 64 KiB when the data cannot be compressed.
+const BLOCK_DATA_SIZE: usize = 0xff00;
+
+/// The largest block, BSIZE is 16 bits
+const MAX_BLOCK_SIZE: usize = 0x10000;
+
+/// The header of a block: the fixed fields, XLEN, and the BC subfield
+const BLOCK_HEADER_SIZE: usize = 18;
+
+/// An empty block, which marks the end of a BGZF file
+const EOF_MARKER: [u8; 28] = [
+    0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02, 0x00,
+    0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// The OS field of BGZF blocks: unknown
+const OS_UNKNOWN: u8 = 255;
+
+/// Writes BGZF (blocked gzip, as specified in the SAM/BAM format specification, Section 4.1).
+///
+/// The data is split into gzip members of at most 64 KiB, each with a `BC` extra subfield holding the size of the block.
+/// The output is a valid multi-member gzip file. `finish` must be called to write the last block and the EOF marker.
+pub struct BgzfWriter<W: Write> {
+    inner: W,
+    /// The uncompressed data of the block being filled
+    buf: Vec<u8>,
+    /// The number of compressed bytes written so far, the offset of the next block
+    offset: u64,
+}
+
+impl<W: Write> BgzfWriter<W> {
+    pub fn new(inner: W) -> Self {
+        BgzfWriter {
+            inner,
+            buf: Vec::with_capacity(BLOCK_DATA_SIZE),
+            offset: 0,
+        }
+    }
+
+    /// The virtual offset of the next byte written: the offset of its block in the compressed file
+    /// in the upper 48 bits, and its offset in the uncompressed data of the block in the lower 16 bits.
+    pub fn virtual_offset(&self) -> u64 {
+        self.offset << 16 | self.buf.len() as u64
+    }
+
+    /// Write the last block and the EOF marker, and return the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.buf.is_empty() {
+            self.write_block()?;
+        }
+        self.inner.write_all(&EOF_MARKER)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn write_block(&mut self) -> io::Result<()> {
+        let block = encode_block(&self.buf)?;
+        self.inner.write_all(&block)?;
+        self.offset += block.len() as u64;
+        self.buf.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for BgzfWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        // a full block is only written when more data comes, so that `virtual_offset` points into it until then
+        if self.buf.len() == BLOCK_DATA_SIZE {
+            self.write_block()?;
+        }
+        let len = data.len().min(BLOCK_DATA_SIZE - self.buf.len());
+        self.buf.extend_from_slice(&data[..len]);
+        Ok(len)
+    }
+
+    /// Write the data buffered so far as a block, which may be shorter than the others
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            self.write_block()?;
+        }
+        self.inner.flush()
+    }
+}
+
+/// Compress the data into a single block. Data that does not compress is stored, so the block never exceeds 64 KiB.
+fn encode_block(data: &[u8]) -> Result<Vec<u8>> {
+    debug_assert!(data.len() <= BLOCK_DATA_SIZE);
+    let mut deflated = deflate(data)?;
+    if BLOCK_HEADER_SIZE + deflated.len() + 8 > MAX_BLOCK_SIZE {
+        deflated = deflate_stored(data);
+    }
+
+    // BSIZE is the size of the whole block minus one
+    let bsize = (BLOCK_HEADER_SIZE + deflated.len() + 8 - 1) as u16;
+    let [lo, hi] = bsize.to_le_bytes();
+    let header = Header { mtime: 0, extra: Some(&[b'B', b'C', 2, 0, lo, hi]), name: None, comment: None, os: OS_UNKNOWN };
+
+    let mut block = Vec::with_capacity(bsize as usize + 1);
+    write_member(&mut block, &header, &deflated, crc32fast::hash(data), data.len())?;
+    Ok(block)
+}
+
+
+/// Reads BGZF, one block at a time.
+///
+/// Positions in the uncompressed data are given as virtual offsets (see `BgzfWriter::virtual_offset`).
+/// When the inner reader implements `Seek`, the reader can move to a virtual offset with `seek_virtual`.
+pub struct BgzfReader<R: Read> {
+    inner: R,
+    /// The offset of the current block in the compressed file
+    block_offset: u64,
+    /// The offset of the next block in the compressed file
+    next_offset: u64,
+    /// The uncompressed data of the current block
+    block: Vec<u8>,
+    /// The position in `block`
+    pos: usize,
+}
+
+impl<R: Read> BgzfReader<R> {
+    /// Read BGZF from the start of `inner`
+    pub fn new(inner: R) -> Self {
+        BgzfReader {
+            inner,
+            block_offset: 0,
+            next_offset: 0,
+            block: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    /// The virtual offset of the next byte read
+    pub fn virtual_offset(&self) -> u64 {
+        self.block_offset << 16 | self.pos as u64
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Read and decompress the block at `next_offset`. Return false at the end of the input.
+    fn read_block(&mut self) -> Result<bool> {
+        let offset = self.next_offset;
+
+        // the fixed fields and XLEN
+        let mut block = vec![0; 12];
+        match read_full(&mut self.inner, &mut block)? {
+            0 => return Ok(false),
+            12 => (),
+            len => return Err(Error::TruncatedInput { bit_offset: (offset + len as u64) * 8 })
+        }
+        if block[0..2] != [0x1f, 0x8b] {
+            return Err(Error::BadMagic { offset });
+        }
+        if block[3] & FEXTRA == 0 {
+            return Err(Error::NotBgzf { offset });
+        }
+        let xlen = u16::from_le_bytes([block[10], block[11]]) as usize;
+        block.resize(12 + xlen, 0);
+        self.read_exact_at(&mut block[12..], offset + 12)?;
+
+        // the BC subfield holds the block size
+        let bsize = find_block_size(&block[12..]).ok_or(Error::NotBgzf { offset })?;
+        let size = bsize as usize + 1;
+        if size < block.len() + 8 {
+            return Err(Error::NotBgzf { offset });
+        }
+        block.resize(size, 0);
+        let start = 12 + xlen;
+        self.read_exact_at(&mut block[start..], offset + start as u64)?;
+
+        let (_, header_len) = parse_header(&block).map_err(|err| err.offset_by(offset))?;
+        let (data, _) = inflate(&block[header_len..size - 8]).map_err(|err| err.offset_by(offset + header_len as u64))?;
+        check_trailer(&block[size - 8..], crc32fast::hash(&data), data.len() as u64).map_err(|err| err.offset_by(offset + size as u64 - 8))?;
+
+        self.block = data;
+        self.pos = 0;
+        self.block_offset = offset;
+        self.next_offset = offset + size as u64;
+        Ok(true)
+    }
+
+    fn read_exact_at(&mut self, buf: &mut [u8], offset: u64) -> Result<()> {
+        self.inner.read_exact(buf).map_err(|err| match err.kind() {
+            io::ErrorKind::UnexpectedEof => Error::TruncatedInput { bit_offset: offset * 8 },
+            _ => err.into()
+        })
+    }
+}
+
+impl<R: Read + Seek> BgzfReader<R> {
+    /// Move to a virtual offset, as given by `virtual_offset` or by an index.
+    pub fn seek_virtual(&mut self, virtual_offset: u64) -> Result<()> {
+        let block_offset = virtual_offset >> 16;
+        let pos = (virtual_offset & 0xffff) as usize;
+
+        self.inner.seek(SeekFrom::Start(block_offset))?;
+        self.next_offset = block_offset;
+        if !self.read_block()? {
+            // the end of the file
+            self.block.clear();
+            self.block_offset = block_offset;
+        }
+
+        if pos > self.block.len() {
+            return Err(Error::InvalidVirtualOffset { virtual_offset });
+        }
+        self.pos = pos;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for BgzfReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // empty blocks, such as the EOF marker, are skipped
+        while self.pos == self.block.len() {
+            if !self.read_block()? {
+                return Ok(0);
+            }
+        }
+
+        let len = buf.len().min(self.block.len() - self.pos);
+        buf[..len].copy_from_slice(&self.block[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+/// Find the BSIZE of the BC subfield in the extra field (See RFC 1952, Section 2.3.1.1)
+fn find_block_size(mut extra: &[u8]) -> Option<u16> {
+    while extra.len() >= 4 {
+        let len = u16::from_le_bytes([extra[2], extra[3]]) as usize;
+        let data = extra.get(4..4 + len)?;
+        if extra[0..2] == [b'B', b'C'] && len == 2 {
+            return Some(u16::from_le_bytes([data[0], data[1]]));
+        }
+        extra = &extra[4 + len..];
+    }
+    None
+}
+
+/// Fill `buf` unless the reader ends first. Return the number of bytes read.
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(len) => filled += len,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err)
+        }
+    }
+    Ok(filled)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len).flat_map(|i| format!("{}\n", i % 9973).into_bytes()).take(len).collect()
+    }
+
+    /// The sizes of the blocks, read from their BC subfields
+    fn block_sizes(data: &[u8]) -> Vec<usize> {
+        let mut sizes = Vec::new();
+        let mut pos = 0;
+        while pos < data.len() {
+            let size = find_block_size(&data[pos + 12..pos + 18]).unwrap() as usize + 1;
+            sizes.push(size);
+            pos += size;
+        }
+        sizes
+    }
+
+    #[test]
+    fn test_eof_marker() {
+        assert_eq!(encode_block(&[]).unwrap(), EOF_MARKER);
+    }
+
+    #[test]
+    fn test_bgzf() {
+        let data = sample(300_000);
+        let mut writer = BgzfWriter::new(Vec::new());
+        for chunk in data.chunks(10_000) {
+            writer.write_all(chunk).unwrap();
+        }
+        let compressed = writer.finish().unwrap();
+
+        assert!(compressed.ends_with(&EOF_MARKER));
+        let sizes = block_sizes(&compressed);
+        assert_eq!(sizes.len(), 300_000usize.div_ceil(BLOCK_DATA_SIZE) + 1);
+        assert!(sizes.iter().all(|&size| size <= MAX_BLOCK_SIZE));
+
+        let mut restored = Vec::new();
+        BgzfReader::new(Cursor::new(&compressed)).read_to_end(&mut restored).unwrap();
+        assert_eq!(restored, data);
+    }
+
+    #[test]
+    fn test_incompressible() {
+        // a linear congruential generator
+        let mut state = 1u32;
+        let data: Vec<u8> = (0..BLOCK_DATA_SIZE).map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 16) as u8
+        }).collect();
+
+        let block = encode_block(&data).unwrap();
+        assert!(block.len() <= MAX_BLOCK_SIZE);
+        assert_eq!(block_sizes(&block), [block.len()]);
+
+        let mut restored = Vec::new();
+        BgzfReader::new(Cursor::new(&block)).read_to_end(&mut restored).unwrap();
+        assert_eq!(restored, data);
+    }
+
+    #[test]
+    fn test_seek_virtual() {
+        let data = sample(200_000);
+        let mut writer = BgzfWriter::new(Vec::new());
+        let mut offsets = Vec::new();
+        for (i, chunk) in data.chunks(7_000).enumerate() {
+            offsets.push((i * 7_000, writer.virtual_offset()));
+            writer.write_all(chunk).unwrap();
+        }
+        let compressed = writer.finish().unwrap();
+
+        let mut reader = BgzfReader::new(Cursor::new(&compressed));
+        for &(pos, virtual_offset) in offsets.iter().rev() {
+            reader.seek_virtual(virtual_offset).unwrap();
+            assert_eq!(reader.virtual_offset(), virtual_offset);
+            let mut buf = [0; 100];
+            reader.read_exact(&mut buf).unwrap();
+            assert_eq!(buf, data[pos..pos + 100]);
+        }
+
+        assert!(matches!(reader.seek_virtual(0xffff), Err(Error::InvalidVirtualOffset { virtual_offset: 0xffff })));
+    }
+
+    #[test]
+    fn test_not_bgzf() {
+        let mut member = Vec::new();
+        let header = Header { mtime: 0, extra: None, name: None, comment: None, os: 3 };
+        write_member(&mut member, &header, &deflate(b"abc").unwrap(), crc32fast::hash(b"abc"), 3).unwrap();
+
+        let mut reader = BgzfReader::new(Cursor::new(&member));
+        assert!(matches!(reader.read_block(), Err(Error::NotBgzf { offset: 0 })));
+
+        let mut reader = BgzfReader::new(Cursor::new(&EOF_MARKER[..20]));
+        assert!(matches!(reader.read_block(), Err(Error::TruncatedInput { .. })));
+    }
+}
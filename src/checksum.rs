@@ -0,0 +1,78 @@
+use crc32fast::Hasher;
+
+/// The largest prime below 2^16 (See RFC 1950, Section 9)
+const ADLER_MOD: u32 = 65521;
+
+/// The number of bytes that can be summed before the Adler-32 sums have to be reduced to stay within a `u32`
+const ADLER_NMAX: usize = 5552;
+
+/// Combine the CRC32 `crc1` of a first piece of data with the CRC32 `crc2` of a second piece of `len2` bytes,
+/// into the CRC32 of the two pieces concatenated.
+pub fn crc32_combine(crc1: u32, crc2: u32, len2: u64) -> u32 {
+    let mut hasher = Hasher::new_with_initial_len(crc1, 0);
+    hasher.combine(&Hasher::new_with_initial_len(crc2, len2));
+    hasher.finalize()
+}
+
+/// The Adler-32 checksum of the data (See RFC 1950, Section 8)
+pub fn adler32(data: &[u8]) -> u32 {
+    let mut a = 1;
+    let mut b = 0;
+    for chunk in data.chunks(ADLER_NMAX) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= ADLER_MOD;
+        b %= ADLER_MOD;
+    }
+    b << 16 | a
+}
+
+/// Combine the Adler-32 `adler1` of a first piece of data with the Adler-32 `adler2` of a second piece of `len2` bytes,
+/// into the Adler-32 of the two pieces concatenated.
+pub fn adler32_combine(adler1: u32, adler2: u32, len2: u64) -> u32 {
+    let modulus = ADLER_MOD as u64;
+    let rem = len2 % modulus;
+    let (a1, b1) = ((adler1 & 0xffff) as u64, (adler1 >> 16) as u64);
+    let (a2, b2) = ((adler2 & 0xffff) as u64, (adler2 >> 16) as u64);
+
+    // both sums of the second piece start from a = 1 instead of a1, and every byte of it adds a to b
+    let a = (a1 + a2 + modulus - 1) % modulus;
+    let b = (b1 + b2 + rem * a1 + modulus - rem) % modulus;
+    (b << 16 | a) as u32
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_adler32() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+        // longer than ADLER_NMAX, all bytes 0xff
+        assert_eq!(adler32(&[0xff; 100_000]), adler32_combine(adler32(&[0xff; 50_000]), adler32(&[0xff; 50_000]), 50_000));
+    }
+
+    #[test]
+    fn test_crc32_combine() {
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i * 31 % 257) as u8).collect();
+        for split in [0, 1, 1000, 65521, 131_072, data.len()] {
+            let (first, second) = data.split_at(split);
+            let crc = crc32_combine(crc32fast::hash(first), crc32fast::hash(second), second.len() as u64);
+            assert_eq!(crc, crc32fast::hash(&data), "split at {}", split);
+        }
+    }
+
+    #[test]
+    fn test_adler32_combine() {
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i * 31 % 257) as u8).collect();
+        for split in [0, 1, 1000, 65521, 131_072, data.len()] {
+            let (first, second) = data.split_at(split);
+            let adler = adler32_combine(adler32(first), adler32(second), second.len() as u64);
+            assert_eq!(adler, adler32(&data), "split at {}", split);
+        }
+    }
+}
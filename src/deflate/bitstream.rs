@@ -0,0 +1,299 @@
+use alloc::vec::Vec;
+use crate::error::{Error, Result};
+
+/// Reads the compressed data LSB first (See RFC 1951, Section 3.1.1) through a 64-bit bit buffer.
+///
+/// The buffer is refilled a whole word at a time, so a huffman code and its extra bits can be peeked
+/// and consumed without going back to the input for every bit.
+pub(crate) struct BitReader<'a> {
+    data: &'a [u8],
+    /// The next byte of `data` to be loaded into the bit buffer
+    pos: usize,
+    bitbuf: u64,
+    bitcount: u32,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        BitReader {
+            data,
+            pos: 0,
+            bitbuf: 0,
+            bitcount: 0,
+        }
+    }
+
+    /// Load as many bytes as fit into the bit buffer. After a refill, at least 56 bits are available unless the input ends.
+    #[inline]
+    pub fn refill(&mut self) {
+        if self.pos + 8 <= self.data.len() {
+            let word = u64::from_le_bytes(self.data[self.pos..self.pos + 8].try_into().unwrap());
+            self.bitbuf |= word << self.bitcount;
+            // only whole bytes are taken, the bits that did not fit are loaded again next time
+            let bytes = (63 - self.bitcount) / 8;
+            self.pos += bytes as usize;
+            self.bitcount += bytes * 8;
+            self.bitbuf &= (1 << self.bitcount) - 1;
+        } else {
+            while self.bitcount <= 56 && self.pos < self.data.len() {
+                self.bitbuf |= (self.data[self.pos] as u64) << self.bitcount;
+                self.pos += 1;
+                self.bitcount += 8;
+            }
+        }
+    }
+
+    /// The bits in the buffer, without consuming them. Bits past the end of the input read as zeros.
+    #[inline]
+    pub fn peek(&self) -> u64 {
+        self.bitbuf
+    }
+
+    /// Drop `bits` bits from the buffer. Fails if the input doesn't have that many bits left.
+    #[inline]
+    pub fn consume(&mut self, bits: u32) -> Result<()> {
+        if bits > self.bitcount {
+            return Err(Error::TruncatedInput { bit_offset: self.bit_offset() });
+        }
+        self.bitbuf >>= bits;
+        self.bitcount -= bits;
+        Ok(())
+    }
+
+    /// Read a value of `bits` bits (at most 32)
+    #[inline]
+    pub fn read_bits(&mut self, bits: u32) -> Result<u32> {
+        if self.bitcount < bits {
+            self.refill();
+        }
+        let value = (self.bitbuf & ((1 << bits) - 1)) as u32;
+        self.consume(bits)?;
+        Ok(value)
+    }
+
+    /// Skip to the next byte boundary, and give the whole bytes left in the bit buffer back to the input
+    pub fn align_to_byte(&mut self) {
+        let partial = self.bitcount % 8;
+        self.bitbuf >>= partial;
+        self.bitcount -= partial;
+
+        self.pos -= (self.bitcount / 8) as usize;
+        self.bitbuf = 0;
+        self.bitcount = 0;
+    }
+
+    /// Read `len` bytes. The reader must be byte-aligned (see `align_to_byte`).
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        debug_assert_eq!(self.bitcount, 0);
+        match self.data.get(self.pos..self.pos + len) {
+            Some(bytes) => {
+                self.pos += len;
+                Ok(bytes)
+            },
+            None => Err(Error::TruncatedInput { bit_offset: self.data.len() as u64 * 8 })
+        }
+    }
+
+    /// The number of bits consumed so far
+    pub fn bit_offset(&self) -> u64 {
+        self.pos as u64 * 8 - self.bitcount as u64
+    }
+
+    /// The number of whole bytes consumed so far. Only meaningful after `align_to_byte`.
+    pub fn byte_offset(&self) -> usize {
+        self.pos
+    }
+}
+
+
+/// Where a `BitWriter` puts the whole bytes it has written
+pub(crate) trait ByteSink {
+    /// The number of bytes put so far
+    fn len(&self) -> usize;
+
+    fn put(&mut self, bytes: &[u8]);
+}
+
+impl ByteSink for Vec<u8> {
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+
+    fn put(&mut self, bytes: &[u8]) {
+        self.extend_from_slice(bytes);
+    }
+}
+
+/// A fixed buffer provided by the caller, filled from the start.
+/// The bytes that do not fit are dropped, and still counted in `len`, so that the writer can tell when it ran out.
+pub(crate) struct SliceSink<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl ByteSink for SliceSink<'_> {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn put(&mut self, bytes: &[u8]) {
+        if let Some(dst) = self.buf.get_mut(self.len..self.len + bytes.len()) {
+            dst.copy_from_slice(bytes);
+        }
+        self.len += bytes.len();
+    }
+}
+
+/// Writes the compressed data LSB first through a 64-bit bit buffer, which is flushed 32 bits at a time.
+pub(crate) struct BitWriter<S: ByteSink = Vec<u8>> {
+    out: S,
+    bitbuf: u64,
+    bitcount: u32,
+}
+
+impl BitWriter {
+    pub fn new() -> Self {
+        BitWriter::with_capacity(0)
+    }
+
+    /// A writer that does not reallocate until `capacity` bytes are written
+    pub fn with_capacity(capacity: usize) -> Self {
+        BitWriter {
+            out: Vec::with_capacity(capacity),
+            bitbuf: 0,
+            bitcount: 0,
+        }
+    }
+
+    /// Move the whole bytes written so far to the end of `dst`, keeping the buffer of the writer
+    pub fn move_bytes_to(&mut self, dst: &mut Vec<u8>) {
+        dst.extend_from_slice(&self.out);
+        self.out.clear();
+    }
+
+    /// Take the whole bytes written so far. Up to 31 bits may be left in the bit buffer.
+    pub fn take_bytes(&mut self) -> Vec<u8> {
+        core::mem::take(&mut self.out)
+    }
+
+    /// Drop everything written so far, keeping the buffer
+    pub fn reset(&mut self) {
+        self.out.clear();
+        self.bitbuf = 0;
+        self.bitcount = 0;
+    }
+
+    /// Pad the stream to a byte boundary and return it
+    pub fn finish(mut self) -> Vec<u8> {
+        self.align_to_byte();
+        self.out
+    }
+}
+
+impl<'a> BitWriter<SliceSink<'a>> {
+    /// A writer that fills `buf` from the start, see `SliceSink`
+    pub fn to_slice(buf: &'a mut [u8]) -> Self {
+        BitWriter {
+            out: SliceSink { buf, len: 0 },
+            bitbuf: 0,
+            bitcount: 0,
+        }
+    }
+
+    /// Whether more bytes were written than fit in the buffer
+    pub fn overflowed(&self) -> bool {
+        self.out.len > self.out.buf.len()
+    }
+
+    /// Pad the stream to a byte boundary and return its length, or fail with `BufferTooSmall` if it does not fit in the buffer
+    pub fn finish_slice(mut self) -> Result<usize> {
+        self.align_to_byte();
+        if self.overflowed() {
+            return Err(Error::BufferTooSmall);
+        }
+        Ok(self.out.len)
+    }
+}
+
+impl<S: ByteSink> BitWriter<S> {
+    /// Write the lowest `bits` bits of `value` (at most 32). The higher bits of `value` must be zero.
+    #[inline]
+    pub fn write_bits(&mut self, value: u32, bits: u32) {
+        debug_assert!(bits <= 32 && (bits == 32 || value >> bits == 0));
+        self.bitbuf |= (value as u64) << self.bitcount;
+        self.bitcount += bits;
+        if self.bitcount >= 32 {
+            self.out.put(&(self.bitbuf as u32).to_le_bytes());
+            self.bitbuf >>= 32;
+            self.bitcount -= 32;
+        }
+    }
+
+    /// The number of bits written past the last byte boundary
+    pub fn unaligned_bits(&self) -> u32 {
+        self.bitcount % 8
+    }
+
+    /// The number of whole bytes written so far and not taken yet
+    pub fn byte_len(&self) -> usize {
+        self.out.len()
+    }
+
+    /// Pad the last byte with zero bits and flush it
+    pub fn align_to_byte(&mut self) {
+        let bytes = self.bitcount.div_ceil(8);
+        self.out.put(&self.bitbuf.to_le_bytes()[..bytes as usize]);
+        self.bitbuf = 0;
+        self.bitcount = 0;
+    }
+
+    /// Write whole bytes. The writer must be byte-aligned (see `align_to_byte`).
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        debug_assert_eq!(self.bitcount, 0);
+        self.out.put(bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_bits() {
+        let data = [0b1010_1100, 0xff, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08];
+        let mut reader = BitReader::new(&data);
+
+        assert_eq!(reader.read_bits(3).unwrap(), 0b100);
+        assert_eq!(reader.read_bits(5).unwrap(), 0b10101);
+        assert_eq!(reader.read_bits(12).unwrap(), 0x1ff);
+        assert_eq!(reader.bit_offset(), 20);
+
+        reader.align_to_byte();
+        assert_eq!(reader.byte_offset(), 3);
+        assert_eq!(reader.read_bytes(3).unwrap(), &[0x02, 0x03, 0x04]);
+        assert_eq!(reader.read_bits(32).unwrap(), 0x08070605);
+        assert!(matches!(reader.read_bits(1), Err(Error::TruncatedInput { bit_offset: 80 })));
+    }
+
+    #[test]
+    fn test_write_bits() {
+        let mut writer = BitWriter::new();
+        writer.write_bits(0b100, 3);
+        writer.write_bits(0b10101, 5);
+        writer.write_bits(0x1ff, 12);
+        writer.align_to_byte();
+        writer.write_bytes(&[0x02, 0x03]);
+        writer.write_bits(0x08070605, 32);
+        writer.write_bits(0b1, 1);
+
+        assert_eq!(writer.finish(), vec![0b1010_1100, 0xff, 0x01, 0x02, 0x03, 0x05, 0x06, 0x07, 0x08, 0x01]);
+
+        let mut buf = [0; 4];
+        let mut writer = BitWriter::to_slice(&mut buf);
+        writer.write_bits(0x08070605, 32);
+        assert_eq!(writer.byte_len(), 4);
+        writer.write_bits(0b1, 1);
+        assert!(matches!(writer.finish_slice(), Err(Error::BufferTooSmall)));
+        assert_eq!(buf, [0x05, 0x06, 0x07, 0x08]);
+    }
+}
@@ -0,0 +1,505 @@
+use alloc::borrow::Cow;
+use alloc::vec::Vec;
+use crate::deflate::bitstream::BitReader;
+use crate::deflate::output::Output;
+use crate::error::{Error, Result};
+use super::{MAX_BITS, CODELEN_ORDER, FIXED_HUFFMAN_BITS, reverse_bits};
+
+/// Base lengths for the length codes 257-285
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258
+];
+
+/// Number of extra bits for the length codes 257-285
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0
+];
+
+/// Base distances for the distance codes 0-29
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577
+];
+
+/// Number of extra bits for the distance codes 0-29
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13
+];
+
+/// The most literal/length and distance codes a dynamic block may have: the codes 286-287 and 30-31 never occur
+pub(crate) const MAX_LITERAL_CODES: usize = 286;
+pub(crate) const MAX_DISTANCE_CODES: usize = 30;
+
+/// Number of bits looked up at once in the primary table of each alphabet
+const LITERAL_TABLE_BITS: u32 = 10;
+const DISTANCE_TABLE_BITS: u32 = 9;
+const CODELEN_TABLE_BITS: u32 = 7;
+
+/// A table entry is packed into a u32:
+/// - bits 0-3: the number of bits to consume
+/// - bits 4-7: the number of extra bits following the code, or the index bits of a subtable
+/// - bits 8-10: the kind of the entry
+/// - bits 16-31: the literal, the base length/distance, or the offset of a subtable
+const KIND_LITERAL: u32 = 0;
+const KIND_BASE: u32 = 1;
+const KIND_END_OF_BLOCK: u32 = 2;
+const KIND_SUBTABLE: u32 = 3;
+const KIND_INVALID: u32 = 4;
+
+const INVALID_ENTRY: u32 = KIND_INVALID << 8;
+
+#[inline]
+fn entry_len(entry: u32) -> u32 { entry & 0xf }
+#[inline]
+fn entry_extra(entry: u32) -> u32 { (entry >> 4) & 0xf }
+#[inline]
+fn entry_kind(entry: u32) -> u32 { (entry >> 8) & 0x7 }
+#[inline]
+fn entry_value(entry: u32) -> u32 { entry >> 16 }
+
+/// The alphabets of RFC 1951, which decide what the entries of a table hold
+#[derive(Debug, Clone, Copy)]
+enum Alphabet {
+    LiteralLength,
+    Distance,
+    CodeLength,
+}
+
+impl Alphabet {
+    /// The entry of `symbol`, without the code length
+    const fn entry(self, symbol: usize) -> u32 {
+        match (self, symbol) {
+            (Alphabet::LiteralLength, 0..=255) => KIND_LITERAL << 8 | (symbol as u32) << 16,
+            (Alphabet::LiteralLength, 256) => KIND_END_OF_BLOCK << 8,
+            (Alphabet::LiteralLength, 257..=285) => {
+                let idx = symbol - 257;
+                KIND_BASE << 8 | (LENGTH_EXTRA[idx] as u32) << 4 | (LENGTH_BASE[idx] as u32) << 16
+            },
+            (Alphabet::Distance, 0..=29) => KIND_BASE << 8 | (DIST_EXTRA[symbol] as u32) << 4 | (DIST_BASE[symbol] as u32) << 16,
+            (Alphabet::CodeLength, 0..=18) => KIND_LITERAL << 8 | (symbol as u32) << 16,
+            // symbols 286-287 and distances 30-31 have codes, but never occur in valid data
+            _ => INVALID_ENTRY
+        }
+    }
+}
+
+/// The tables of the fixed codes, built at compile time. The codes are short enough to need no subtables.
+static FIXED_LITERAL_TABLE: DecodeTable = DecodeTable {
+    entries: Cow::Borrowed(&primary_table::<{ 1 << LITERAL_TABLE_BITS }>(&FIXED_HUFFMAN_BITS, Alphabet::LiteralLength)),
+    table_bits: LITERAL_TABLE_BITS,
+};
+
+/// Fixed distance codes are all 5 bits long. Codes 30-31 never occur in the compressed data.
+static FIXED_DISTANCE_TABLE: DecodeTable = DecodeTable {
+    entries: Cow::Borrowed(&primary_table::<{ 1 << DISTANCE_TABLE_BITS }>(&[5; 32], Alphabet::Distance)),
+    table_bits: DISTANCE_TABLE_BITS,
+};
+
+/// The primary table of `N` = 2^table_bits entries for codes of at most `table_bits` bits, which need no subtables.
+/// The codes are assigned as in `DecodeTable::build`.
+const fn primary_table<const N: usize>(codelens: &[u32], alphabet: Alphabet) -> [u32; N] {
+    let table_bits = N.trailing_zeros();
+    let mut bl_count = [0_u32; MAX_BITS as usize + 1];
+    let mut i = 0;
+    while i < codelens.len() {
+        assert!(codelens[i] <= table_bits);
+        bl_count[codelens[i] as usize] += 1;
+        i += 1;
+    }
+    bl_count[0] = 0;
+
+    let mut next_code = [0_u32; MAX_BITS as usize + 1];
+    let mut code = 0;
+    let mut bits = 1;
+    while bits <= MAX_BITS as usize {
+        code = (code + bl_count[bits - 1]) << 1;
+        next_code[bits] = code;
+        bits += 1;
+    }
+
+    let mut entries = [INVALID_ENTRY; N];
+    let mut symbol = 0;
+    while symbol < codelens.len() {
+        let bits = codelens[symbol];
+        if bits != 0 {
+            let mut idx = reverse_bits(next_code[bits as usize] as u16, bits) as usize;
+            next_code[bits as usize] += 1;
+            while idx < N {
+                entries[idx] = alphabet.entry(symbol) | bits;
+                idx += 1 << bits;
+            }
+        }
+        symbol += 1;
+    }
+    entries
+}
+
+/// A symbol decoded with a `DecodeTable`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Code {
+    /// A literal byte, or a symbol of the code length alphabet
+    Literal(u32),
+    /// A length or a distance: `base` plus the value of the `extra` bits that follow the code
+    Base { base: u32, extra: u32 },
+    EndOfBlock,
+}
+
+/// What `DecodeTable::lookup` finds at the start of the bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Lookup {
+    /// A code of `len` bits
+    Code(Code, u32),
+    /// The bits known so far are the start of a longer code, or too few to tell an invalid code
+    NeedBits,
+    /// Not a code of the table
+    Invalid,
+}
+
+/// A lookup table for decoding canonical huffman codes.
+///
+/// The primary table is indexed by the next `table_bits` bits of the input. Codes longer than that
+/// point to a subtable, which is indexed by the bits after the first `table_bits` bits.
+pub(crate) struct DecodeTable {
+    entries: Cow<'static, [u32]>,
+    table_bits: u32,
+}
+
+impl DecodeTable {
+    /// Build the table from code lengths, the codes are assigned as described in RFC 1951, Section 3.2.2.
+    /// Return None if the code lengths are over-subscribed. Incomplete codes are accepted,
+    /// the missing codes decode as an error.
+    fn build(codelens: &[u32], table_bits: u32, alphabet: Alphabet) -> Option<Self> {
+        let mut table = DecodeTable::empty(table_bits);
+        table.rebuild(codelens, alphabet).then_some(table)
+    }
+
+    /// A table with no codes, to be filled by `rebuild`
+    fn empty(table_bits: u32) -> Self {
+        DecodeTable { entries: Cow::Owned(Vec::new()), table_bits }
+    }
+
+    /// Same as `build`, reusing the entries of the table. Return false if the code lengths are over-subscribed.
+    fn rebuild(&mut self, codelens: &[u32], alphabet: Alphabet) -> bool {
+        let mut bl_count = [0_u32; MAX_BITS as usize + 1];
+        for &bits in codelens {
+            if bits > MAX_BITS { return false; }
+            bl_count[bits as usize] += 1;
+        }
+        bl_count[0] = 0;
+
+        // The codes of each length must fit in the space left by the shorter codes
+        let mut left = 1_i32;
+        for &count in &bl_count[1..] {
+            left = (left << 1) - count as i32;
+            if left < 0 { return false; }
+        }
+
+        let mut first_code = [0_u32; MAX_BITS as usize + 1];
+        let mut code = 0;
+        for bits in 1..=MAX_BITS as usize {
+            code = (code + bl_count[bits - 1]) << 1;
+            first_code[bits] = code;
+        }
+
+        // The codes are stored MSB first, so they are reversed to match the order the bits are read in
+        let codes = || {
+            let mut next_code = first_code;
+            codelens.iter().enumerate()
+                .filter(|(_, &bits)| bits != 0)
+                .map(move |(symbol, &bits)| {
+                    let code = next_code[bits as usize];
+                    next_code[bits as usize] += 1;
+                    (symbol, bits, reverse_bits(code as u16, bits) as u32)
+                })
+        };
+
+        // Size the subtable of each primary entry by its longest code, held in the entry until the subtables are laid out
+        let table_bits = self.table_bits;
+        let primary_size = 1 << table_bits;
+        let mask = primary_size - 1;
+        let entries = self.entries.to_mut();
+        entries.clear();
+        entries.resize(primary_size, 0);
+        for (_, bits, rev) in codes() {
+            if bits > table_bits {
+                let idx = (rev as usize) & mask;
+                entries[idx] = entries[idx].max(bits - table_bits);
+            }
+        }
+
+        for idx in 0..primary_size {
+            let bits = entries[idx];
+            if bits != 0 {
+                entries[idx] = KIND_SUBTABLE << 8 | bits << 4 | table_bits | (entries.len() as u32) << 16;
+                entries.resize(entries.len() + (1 << bits), INVALID_ENTRY);
+            } else {
+                entries[idx] = INVALID_ENTRY;
+            }
+        }
+
+        // Fill every entry whose index starts with the code
+        for (symbol, bits, rev) in codes() {
+            let entry = alphabet.entry(symbol);
+            if bits <= table_bits {
+                for idx in ((rev as usize)..primary_size).step_by(1 << bits) {
+                    entries[idx] = entry | bits;
+                }
+            } else {
+                let pointer = entries[(rev as usize) & mask];
+                let offset = entry_value(pointer) as usize;
+                let size = 1 << entry_extra(pointer);
+                let sub_len = bits - table_bits;
+                for idx in (((rev >> table_bits) as usize)..size).step_by(1 << sub_len) {
+                    entries[offset + idx] = entry | sub_len;
+                }
+            }
+        }
+        true
+    }
+
+    /// Find the code at the start of `bits`, of which only the low `available` bits are known, the others being zeros.
+    /// An entry found that way is right if its code is no longer than `available`, as all the entries a code starts are the same.
+    pub(crate) fn lookup(&self, bits: u64, available: u32) -> Lookup {
+        let mut entry = self.entries[(bits & ((1 << self.table_bits) - 1)) as usize];
+        let mut len = 0;
+        let mut index_bits = self.table_bits;
+        if entry_kind(entry) == KIND_SUBTABLE {
+            if available < self.table_bits {
+                return Lookup::NeedBits;
+            }
+            let idx = (bits >> self.table_bits) & ((1 << entry_extra(entry)) - 1);
+            index_bits += entry_extra(entry);
+            entry = self.entries[entry_value(entry) as usize + idx as usize];
+            len = self.table_bits;
+        }
+
+        if entry_kind(entry) == KIND_INVALID {
+            return if available < index_bits { Lookup::NeedBits } else { Lookup::Invalid };
+        }
+        len += entry_len(entry);
+        if len > available {
+            return Lookup::NeedBits;
+        }
+        let code = match entry_kind(entry) {
+            KIND_LITERAL => Code::Literal(entry_value(entry)),
+            KIND_END_OF_BLOCK => Code::EndOfBlock,
+            _ => Code::Base { base: entry_value(entry), extra: entry_extra(entry) },
+        };
+        Lookup::Code(code, len)
+    }
+
+    /// Decode ONE symbol from the bitstream and return its entry
+    #[inline]
+    fn decode(&self, reader: &mut BitReader) -> Result<u32> {
+        let start = reader.bit_offset();
+        reader.refill();
+
+        let bits = reader.peek();
+        let mut entry = self.entries[(bits & ((1 << self.table_bits) - 1)) as usize];
+        if entry_kind(entry) == KIND_SUBTABLE {
+            let idx = (bits >> self.table_bits) & ((1 << entry_extra(entry)) - 1);
+            reader.consume(self.table_bits)?;
+            entry = self.entries[entry_value(entry) as usize + idx as usize];
+        }
+
+        if entry_kind(entry) == KIND_INVALID {
+            return Err(Error::InvalidHuffmanTable { bit_offset: start });
+        }
+        reader.consume(entry_len(entry))?;
+        Ok(entry)
+    }
+}
+
+/// The literal/length and distance tables of the fixed codes (BTYPE = 01)
+pub(crate) fn fixed_tables() -> (&'static DecodeTable, &'static DecodeTable) {
+    (&FIXED_LITERAL_TABLE, &FIXED_DISTANCE_TABLE)
+}
+
+/// The tables of a dynamic block, kept from block to block by a decoder that takes its input in pieces (see `Inflater`),
+/// so that building them does not allocate once the entries have grown
+pub(crate) struct DynamicTables {
+    pub codelen: DecodeTable,
+    pub literal: DecodeTable,
+    pub distance: DecodeTable,
+}
+
+impl DynamicTables {
+    pub fn new() -> Self {
+        DynamicTables {
+            codelen: DecodeTable::empty(CODELEN_TABLE_BITS),
+            literal: DecodeTable::empty(LITERAL_TABLE_BITS),
+            distance: DecodeTable::empty(DISTANCE_TABLE_BITS),
+        }
+    }
+
+    /// Build the table of the code length alphabet from its 19 code lengths, in symbol order. Return false if they are invalid.
+    pub fn build_codelen(&mut self, codelens: &[u32; 19]) -> bool {
+        self.codelen.rebuild(codelens, Alphabet::CodeLength)
+    }
+
+    /// Build the literal/length and distance tables from the code lengths of the block,
+    /// the first `hlit` of them being those of the literal/length alphabet. Return false if they are invalid.
+    pub fn build_codes(&mut self, codelens: &[u32], hlit: usize) -> bool {
+        // the end of block code is required
+        codelens[256] != 0
+            && self.literal.rebuild(&codelens[..hlit], Alphabet::LiteralLength)
+            && self.distance.rebuild(&codelens[hlit..], Alphabet::Distance)
+    }
+}
+
+/// Decompress a huffman compressed block and append the output to `dst`.
+/// `dst` also serves as the history for back references, so it must contain all the data decoded so far.
+///
+/// The block header (BFINAL and BTYPE) has already been consumed. `dynamic` selects between BTYPE = 10 and BTYPE = 01.
+pub(crate) fn huffman_decode_block<O: Output>(reader: &mut BitReader, dst: &mut O, dynamic: bool) -> Result<()> {
+    if dynamic {
+        let (literal_table, distance_table) = read_dynamic_tables(reader)?;
+        decode_symbols(reader, dst, &literal_table, &distance_table)
+    } else {
+        decode_symbols(reader, dst, &FIXED_LITERAL_TABLE, &FIXED_DISTANCE_TABLE)
+    }
+}
+
+/// Decode literals and back references until the end of block symbol
+fn decode_symbols<O: Output>(reader: &mut BitReader, dst: &mut O, literal_table: &DecodeTable, distance_table: &DecodeTable) -> Result<()> {
+    loop {
+        let start = reader.bit_offset();
+        let entry = literal_table.decode(reader)?;
+
+        match entry_kind(entry) {
+            KIND_LITERAL => dst.push(entry_value(entry) as u8)?,
+            KIND_END_OF_BLOCK => return Ok(()),
+            _ => {
+                let length = (entry_value(entry) + reader.read_bits(entry_extra(entry))?) as usize;
+
+                let entry = distance_table.decode(reader)?;
+                let distance = (entry_value(entry) + reader.read_bits(entry_extra(entry))?) as usize;
+                if distance > dst.len() {
+                    return Err(Error::DistanceTooFar { distance, bit_offset: start });
+                }
+                dst.copy_match(distance, length)?;
+            }
+        }
+    }
+}
+
+/// Read the code lengths of a dynamic block and build the literal/length table and the distance table.
+/// The layout is described in `dynamic_huffman_encode_block`.
+fn read_dynamic_tables(reader: &mut BitReader) -> Result<(DecodeTable, DecodeTable)> {
+    let start = reader.bit_offset();
+
+    let hlit = reader.read_bits(5)? as usize + 257;
+    let hdist = reader.read_bits(5)? as usize + 1;
+    let hclen = reader.read_bits(4)? as usize + 4;
+    if hlit > MAX_LITERAL_CODES || hdist > MAX_DISTANCE_CODES {
+        return Err(Error::InvalidHuffmanTable { bit_offset: start });
+    }
+
+    let mut codelen_codelens = [0_u32; 19];
+    for &i in &CODELEN_ORDER[..hclen] {
+        codelen_codelens[i] = reader.read_bits(3)?;
+    }
+    let codelen_table = DecodeTable::build(&codelen_codelens, CODELEN_TABLE_BITS, Alphabet::CodeLength)
+        .ok_or(Error::InvalidHuffmanTable { bit_offset: start })?;
+
+    // The literal/length and distance code lengths form a single sequence, repeats may cross the boundary
+    let mut codelens = Vec::with_capacity(hlit + hdist);
+    while codelens.len() < hlit + hdist {
+        let (value, repeat) = match entry_value(codelen_table.decode(reader)?) {
+            len @ 0..=15 => (len, 1),
+            16 => match codelens.last() {
+                Some(&prev) => (prev, 3 + reader.read_bits(2)? as usize),
+                // repeat code without a previous code length
+                None => return Err(Error::InvalidHuffmanTable { bit_offset: start })
+            },
+            17 => (0, 3 + reader.read_bits(3)? as usize),
+            _ => (0, 11 + reader.read_bits(7)? as usize),
+        };
+
+        if codelens.len() + repeat > hlit + hdist {
+            return Err(Error::InvalidHuffmanTable { bit_offset: start });
+        }
+        codelens.extend(core::iter::repeat_n(value, repeat));
+    }
+
+    // the end of block code is required
+    if codelens[256] == 0 {
+        return Err(Error::InvalidHuffmanTable { bit_offset: start });
+    }
+
+    let literal_table = DecodeTable::build(&codelens[..hlit], LITERAL_TABLE_BITS, Alphabet::LiteralLength)
+        .ok_or(Error::InvalidHuffmanTable { bit_offset: start })?;
+    let distance_table = DecodeTable::build(&codelens[hlit..], DISTANCE_TABLE_BITS, Alphabet::Distance)
+        .ok_or(Error::InvalidHuffmanTable { bit_offset: start })?;
+
+    Ok((literal_table, distance_table))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_table() {
+        // codes of RFC 1951, Section 3.2.2: A=010, B=011, C=100, D=101, E=110, F=00, G=1110, H=1111
+        let table = DecodeTable::build(&[3, 3, 3, 3, 3, 2, 4, 4], 3, Alphabet::CodeLength).unwrap();
+
+        // F, H, A: the bits 00 1111 010 packed LSB first
+        let data = [0b1011_1100, 0b0];
+        let mut reader = BitReader::new(&data);
+        let symbols: Vec<u32> = (0..3).map(|_| entry_value(table.decode(&mut reader).unwrap())).collect();
+        assert_eq!(symbols, vec![5, 7, 0]);
+        assert_eq!(reader.bit_offset(), 9);
+    }
+
+    #[test]
+    fn test_decode_table_invalid() {
+        // over-subscribed
+        assert!(DecodeTable::build(&[1, 1, 1], 7, Alphabet::CodeLength).is_none());
+
+        // incomplete: the code 1 is missing
+        let table = DecodeTable::build(&[1], 7, Alphabet::CodeLength).unwrap();
+        let mut reader = BitReader::new(&[0b10]);
+        assert_eq!(entry_value(table.decode(&mut reader).unwrap()), 0);
+        assert!(matches!(table.decode(&mut reader), Err(Error::InvalidHuffmanTable { bit_offset: 1 })));
+    }
+
+    #[test]
+    fn test_too_many_codes() {
+        // HLIT = 30 (287 literal/length codes), then HDIST = 30 (31 distance codes)
+        for data in [[0x1e, 0, 0, 0], [0xc0, 0x03, 0, 0]] {
+            let mut reader = BitReader::new(&data);
+            assert!(matches!(read_dynamic_tables(&mut reader), Err(Error::InvalidHuffmanTable { bit_offset: 0 })));
+        }
+    }
+
+    #[test]
+    fn test_fixed_tables() {
+        // the tables built at compile time are the same as built at run time
+        let literal_table = DecodeTable::build(&FIXED_HUFFMAN_BITS, LITERAL_TABLE_BITS, Alphabet::LiteralLength).unwrap();
+        assert_eq!(FIXED_LITERAL_TABLE.entries, literal_table.entries);
+        let distance_table = DecodeTable::build(&[5; 32], DISTANCE_TABLE_BITS, Alphabet::Distance).unwrap();
+        assert_eq!(FIXED_DISTANCE_TABLE.entries, distance_table.entries);
+    }
+
+    #[test]
+    fn test_subtable() {
+        // with a 7 bit primary table, the 8 and 9 bit fixed codes go through subtables
+        let table = DecodeTable::build(&FIXED_HUFFMAN_BITS, 7, Alphabet::LiteralLength).unwrap();
+        for (symbol, code, bits) in [(0_u32, 0b00110000_u16, 8), (255, 0b111111111, 9), (144, 0b110010000, 9)] {
+            let data = reverse_bits(code, bits).to_le_bytes();
+            let mut reader = BitReader::new(&data);
+            let entry = table.decode(&mut reader).unwrap();
+            assert_eq!(entry_value(entry), symbol);
+            assert_eq!(reader.bit_offset(), bits as u64);
+        }
+
+        let mut reader = BitReader::new(&[0, 0]);
+        assert_eq!(entry_kind(table.decode(&mut reader).unwrap()), KIND_END_OF_BLOCK);
+        assert_eq!(reader.bit_offset(), 7);
+    }
+}
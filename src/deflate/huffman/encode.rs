@@ -1,193 +1,270 @@
-use bitstream_io::{BitWriter, BitWrite, LittleEndian};
-use std::error::Error;
+use crate::deflate::bitstream::{BitWriter, ByteSink};
 use crate::deflate::lz77::Symbol;
-use super::{HuffmanCodes};
-use super::{MAX_BITS, FIXED_LITERAL_CODES, LENGTH_REPR, DIST_REPR};
+use alloc::vec::Vec;
+use super::HuffmanCodes;
+use super::{MAX_BITS, CODELEN_ORDER, FIXED_HUFFMAN_BITS, FIXED_LITERAL_CODES, FIXED_DISTANCE_CODES, LENGTH_REPR, DIST_REPR};
 use super::limited_codelens_from_freq;
 
-/// Compress the block using huffman codes.
-/// The input block does not include EndOfBlock.
-/// 
-/// This function isn't aware of whether the block is the last block or not, so it leaves BFINAL bit as 0. The caller must deal with it.
-/// 
-/// The second part `(bits, value)` of the returned tuple exists since the encoded block may not be byte-aligned.
-/// For example, if the block is ...01011010 01001, then `(bits, value) = (5, 0b01001)`
-pub(crate) fn huffman_encode_block(block: &[Symbol]) -> Result<(Vec<u8>, (u32, u8)), Box<dyn Error>> {
-    
-    /*let fixed = fixed_huffman_encode_block(block)?;
-    let dynamic = dynamic_huffman_encode_block(block)?;
+/// The huffman codes chosen for a block, dynamic or fixed, whichever make it smaller, and the size of the block with them
+pub(crate) struct HuffmanBlock {
+    /// `None` for the fixed codes
+    dynamic: Option<DynamicCodes>,
+    bits: u64,
+}
+
+impl HuffmanBlock {
+    /// Choose the codes for the block, which does not include EndOfBlock. `fixed` forces the fixed codes.
+    pub fn new(block: &[Symbol], fixed: bool) -> Self {
+        let (literal_freqs, distance_freqs) = count_frequencies(block);
+        let extra = extra_bits(block);
+        let fixed_bits = fixed_cost(&literal_freqs, &distance_freqs) + extra;
+        if fixed {
+            return HuffmanBlock { dynamic: None, bits: fixed_bits };
+        }
+
+        let dynamic = DynamicCodes::new(&literal_freqs, &distance_freqs);
+        let dynamic_bits = dynamic.cost(&literal_freqs, &distance_freqs) + extra;
+        if dynamic_bits < fixed_bits {
+            HuffmanBlock { dynamic: Some(dynamic), bits: dynamic_bits }
+        } else {
+            HuffmanBlock { dynamic: None, bits: fixed_bits }
+        }
+    }
+
+    /// The size in bits of the block, header and end of block included
+    pub fn bits(&self) -> u64 {
+        self.bits
+    }
+
+    /// Whether the block has dynamic codes rather than the fixed codes
+    pub fn is_dynamic(&self) -> bool {
+        self.dynamic.is_some()
+    }
 
-    if dynamic.0.len() < fixed.0.len() {
-        Ok(dynamic)
-    } else {
-        Ok(fixed)
-    }*/
+    /// Compress the block with the chosen codes and write it to `writer`, including the block header.
+    /// `block` must be the one the codes were chosen for. `bfinal` tells whether this is the last block of the stream.
+    pub fn write<S: ByteSink>(&self, writer: &mut BitWriter<S>, block: &[Symbol], bfinal: bool) {
+        match &self.dynamic {
+            Some(codes) => dynamic_huffman_encode_block(writer, block, codes, bfinal),
+            None => fixed_huffman_encode_block(writer, block, bfinal)
+        }
+    }
+}
 
-    fixed_huffman_encode_block(block)
+/// The length code of a back reference, counted from symbol 257, and its distance code
+pub(crate) fn match_codes(length: u8, distance: u16) -> (usize, usize) {
+    (LENGTH_REPR[length as usize + 3].0 as usize - 257, DIST_REPR[distance as usize].0 as usize)
 }
 
 /// Compress the block using fixed huffman codes.
-/// Return the compressed block, including the header, the compressed data and the end of block symbol
-fn fixed_huffman_encode_block(block: &[Symbol]) -> Result<(Vec<u8>, (u32, u8)), Box<dyn Error>> {
-    let mut buf = Vec::new();
-    let mut writer = BitWriter::endian(&mut buf, LittleEndian);
-
-    writer.write(3, 0b010)?; // The header. BFINAL = 0, BTYPE = 01
+/// Write the header, the compressed data and the end of block symbol
+pub(crate) fn fixed_huffman_encode_block<S: ByteSink>(writer: &mut BitWriter<S>, block: &[Symbol], bfinal: bool) {
+    writer.write_bits(0b010 | bfinal as u32, 3); // The header. BTYPE = 01
 
     // Encode using fixed huffman code
+    encode_symbols(writer, block, &FIXED_LITERAL_CODES, &FIXED_DISTANCE_CODES)
+}
+
+/// Encode the block data and the end of block symbol.
+/// A back reference goes out in two writes: the length code with its extra bits, and the distance code with its extra bits.
+fn encode_symbols<S: ByteSink>(writer: &mut BitWriter<S>, block: &[Symbol], literal_codes: &HuffmanCodes, distance_codes: &HuffmanCodes) {
     for symbol in block {
         match *symbol {
             Symbol::Literal(lit) => {
-                FIXED_LITERAL_CODES.encode_char(&mut writer, lit as u16)?;
+                literal_codes.encode_char(writer, lit as u16);
             },
             Symbol::Pointer {length, distance} => {
-                assert!(distance >= 1 && distance <= 32768);
+                debug_assert!((1..=32768).contains(&distance));
 
                 let (code, bits, extra) = LENGTH_REPR[length as usize + 3];
-                FIXED_LITERAL_CODES.encode_char(&mut writer, code)?;
-                writer.write(bits as u32, extra)?;
+                literal_codes.encode_char_with_extra(writer, code, bits as u32, extra as u32);
 
                 let (code, bits, extra) = DIST_REPR[distance as usize];
-                writer.write(5, code)?;
-                writer.write(bits as u32, extra)?;
+                distance_codes.encode_char_with_extra(writer, code as u16, bits as u32, extra as u32);
             }
         }
     }
 
     // Write the end of block symbol
-    FIXED_LITERAL_CODES.encode_char(&mut writer, 256)?;
-
-    let last_byte = writer.into_unwritten();
-    Ok((buf, last_byte))
+    literal_codes.encode_char(writer, 256);
 }
 
-/// Compress the block using dynamic huffman codes, Return the compressed block.
-/// The structure of a dynamic huffman compressed block (See RFC 1951, Section 3.2.7): 
-/// - 3 bits: the header
-/// - 5 bits: HLIT, number of Literal/Length codes - 257
-/// - 5 bits: HDIST, number of Distance codes - 1
-/// - 4 bits: HCLEN, number of Code Length codes - 4
-/// - (HCLEN + 4) * 3 bits: the code lengths for the code length alphabet, in the order: 0,8,7,9,6,10,5,11,4,12,3,13,2,14,1,15. Zeros at the end are discarded.
-/// - HLIT + 257 code lengths for the literal/length alphabet, encoded using the code length alphabet
-/// - HDIST + 1 code lengths for the distance alphabet, encoded using the code length alphabet
-/// - the compressed data
-/// - the end of block symbol
-fn dynamic_huffman_encode_block(block: &[Symbol]) -> Result<(Vec<u8>, (u32, u8)), Box<dyn Error>> {
-    let mut buf = Vec::new();
-    let mut writer = BitWriter::endian(&mut buf, LittleEndian);
-
-    // write header
-    writer.write(3, 0b100)?; // BFINAL = 0, BTYPE = 10
-
-    // TODO: encode using dynamic huffman code
-
-    let mut literal_freqencies = [0_u32; 286];
-    let mut distance_freqencies = [0_u32; 30];
+/// Count the frequencies of the literal/length codes, end of block included, and of the distance codes
+fn count_frequencies(block: &[Symbol]) -> ([u32; 286], [u32; 30]) {
+    let mut literal_freqs = [0_u32; 286];
+    let mut distance_freqs = [0_u32; 30];
 
-    // Count freqencies of literals/lengths and distances
     for symbol in block {
         match *symbol {
             Symbol::Literal(lit) => {
-                literal_freqencies[lit as usize] += 1;
+                literal_freqs[lit as usize] += 1;
             },
             Symbol::Pointer {length, distance} => {
-                assert!(distance >= 1 && distance <= 32768);
-                literal_freqencies[LENGTH_REPR[length as usize].0 as usize] += 1;
-                distance_freqencies[DIST_REPR[distance as usize].0 as usize] += 1;
+                debug_assert!((1..=32768).contains(&distance));
+                literal_freqs[LENGTH_REPR[length as usize + 3].0 as usize] += 1;
+                distance_freqs[DIST_REPR[distance as usize].0 as usize] += 1;
             }
         }
     }
+    literal_freqs[256] = 1;
 
-    let literal_codelens = {
-        let mut codelens = limited_codelens_from_freq(&literal_freqencies, MAX_BITS);
-        // remove zeros at the end
-        while codelens[codelens.len() - 1] == 0 {
-            codelens.pop();
-        }
-        codelens
-    };
-
-    let distance_codelens = {
-        let mut codelens = limited_codelens_from_freq(&distance_freqencies, MAX_BITS);
-        // remove zeros at the end
-        while codelens[codelens.len() - 1] == 0 {
-            codelens.pop();
-        }
-        codelens
-    };
-
-    // Write HLIT, HDIST
-    let HLIT = literal_codelens.len() as u32 - 257;
-    let HDIST = distance_codelens.len() as u32 - 1;
-    writer.write(5, HLIT)?;
-    writer.write(5, HDIST)?;
+    (literal_freqs, distance_freqs)
+}
 
-    // Write HCLEN and the code lengths for the code length alphabet, literal/length alphabet and distance alphabet
-    //encode_codelens(&mut writer, &literal_codelens, &distance_codelens);
+/// The number of extra bits of the lengths and distances of a block, which do not depend on the codes
+fn extra_bits(block: &[Symbol]) -> u64 {
+    block.iter().map(|symbol| match *symbol {
+        Symbol::Literal(_) => 0,
+        Symbol::Pointer { length, distance } => (LENGTH_REPR[length as usize + 3].1 + DIST_REPR[distance as usize].1) as u64
+    }).sum()
+}
 
-    let literal_codes = HuffmanCodes::build_from_codelens(&literal_codelens)?;
-    let distance_codes = HuffmanCodes::build_from_codelens(&distance_codelens)?;
+/// The size in bits of the codes of a block, given the code lengths. The extra bits are left out, as they do not depend on the codes.
+fn codes_cost(freqs: &[u32], codelens: &[u32]) -> u64 {
+    freqs.iter().zip(codelens).map(|(&freq, &len)| freq as u64 * len as u64).sum()
+}
 
-    // Encode block data using dynamic huffman codes
-    for symbol in block {
-        match *symbol {
-            Symbol::Literal(lit) => {
-                literal_codes.encode_char(&mut writer, lit as u16)?;
-            },
-            Symbol::Pointer {length, distance} => {
-                assert!(distance >= 1 && distance <= 32768);
+/// The size in bits of a block with the fixed codes, without the extra bits
+fn fixed_cost(literal_freqs: &[u32], distance_freqs: &[u32]) -> u64 {
+    3 + codes_cost(literal_freqs, &FIXED_HUFFMAN_BITS) + 5 * distance_freqs.iter().map(|&freq| freq as u64).sum::<u64>()
+}
 
-                let (code, bits, extra) = LENGTH_REPR[length as usize];
-                literal_codes.encode_char(&mut writer, code)?;
-                writer.write(bits as u32, extra)?;
+/// The code length alphabet (See RFC 1951, Section 3.2.7): 0-15 are code lengths,
+/// 16 repeats the previous length 3-6 times, 17 repeats a zero length 3-10 times and 18 repeats it 11-138 times
+const REPEAT_PREVIOUS: u8 = 16;
+const REPEAT_ZERO: u8 = 17;
+const REPEAT_ZERO_LONG: u8 = 18;
+
+/// The longest code of the code length alphabet
+const MAX_CODELEN_BITS: u32 = 7;
+
+/// The dynamic huffman codes of a block, and the run-length encoded code lengths that describe them in the block header
+struct DynamicCodes {
+    literal_codelens: Vec<u32>,
+    distance_codelens: Vec<u32>,
+    /// (code length symbol, extra bits value) of the literal/length code lengths followed by the distance code lengths
+    runs: Vec<(u8, u8)>,
+    codelen_codelens: Vec<u32>,
+    /// The number of code length code lengths written in the header, in `CODELEN_ORDER`
+    hclen: usize,
+}
 
-                let (code, bits, extra) = DIST_REPR[distance as usize];
-                distance_codes.encode_char(&mut writer, code as u16)?;
-                writer.write(bits as u32, extra)?;
-            }
+impl DynamicCodes {
+    fn new(literal_freqs: &[u32], distance_freqs: &[u32]) -> Self {
+        let mut literal_codelens = limited_codelens_from_freq(literal_freqs, MAX_BITS);
+        let mut distance_codelens = limited_codelens_from_freq(distance_freqs, MAX_BITS);
+        // remove zeros at the end, down to the smallest counts HLIT and HDIST can tell
+        trim_zeros(&mut literal_codelens, 257);
+        trim_zeros(&mut distance_codelens, 1);
+
+        let runs = run_length_encode(&[literal_codelens.as_slice(), &distance_codelens].concat());
+        let mut codelen_freqs = [0_u32; 19];
+        for &(symbol, _) in &runs {
+            codelen_freqs[symbol as usize] += 1;
         }
-    }
+        let codelen_codelens = limited_codelens_from_freq(&codelen_freqs, MAX_CODELEN_BITS);
+        let hclen = CODELEN_ORDER.iter().rposition(|&i| codelen_codelens[i] != 0).map_or(0, |last| last + 1).max(4);
 
-    // Write the end of block symbol
-    literal_codes.encode_char(&mut writer, 256)?;
+        DynamicCodes { literal_codelens, distance_codelens, runs, codelen_codelens, hclen }
+    }
 
-    let last_byte = writer.into_unwritten();
-    Ok((buf, last_byte))
+    /// The size in bits of the block with these codes, header included, without the extra bits of the lengths and distances
+    fn cost(&self, literal_freqs: &[u32], distance_freqs: &[u32]) -> u64 {
+        let runs: u64 = self.runs.iter().map(|&(symbol, _)| self.codelen_codelens[symbol as usize] as u64 + repeat_bits(symbol) as u64).sum();
+        3 + 5 + 5 + 4 + 3 * self.hclen as u64 + runs
+            + codes_cost(literal_freqs, &self.literal_codelens) + codes_cost(distance_freqs, &self.distance_codelens)
+    }
 }
 
-
-/*
-
-/// The function first calulates code lengths for the code length alphabet. 
-/// Then it writes HCLEN and the code lengths for the code length alphabet.
-/// Finally it writes the code lengths for the literal/length alphabet and the distance alphabet, which are encoded using the code length alphabet. 
-
-fn encode_codelens<W: Write>(writer: &mut BitWriter<W, LittleEndian>, lit_codelens: &[u32], dist_codelens: &[u32]) -> Result<(), EncodeError> {
-    if codelens.len() == 0 {
-        return Err(EncodeError::from("encode_bitlens: empty bitlens"));
+/// Remove the zeros at the end of `codelens`, keeping at least `min` code lengths
+fn trim_zeros(codelens: &mut Vec<u32>, min: usize) {
+    while codelens.len() > min && codelens[codelens.len() - 1] == 0 {
+        codelens.pop();
     }
+}
 
-    let mut len = codelens[0];
-    let count = 1;
+/// The number of extra bits after a code length symbol
+fn repeat_bits(symbol: u8) -> u32 {
+    match symbol {
+        REPEAT_PREVIOUS => 2,
+        REPEAT_ZERO => 3,
+        REPEAT_ZERO_LONG => 7,
+        _ => 0
+    }
+}
 
-    for bits in &codelens[1..] {
-        if bits > MAX_BITS {
-            return Err(EncodeError::from("encode_bitlens: bit length > MAX_BITS"));
+/// Encode a sequence of code lengths with the code length alphabet, as (symbol, extra bits value)
+fn run_length_encode(codelens: &[u32]) -> Vec<(u8, u8)> {
+    let mut runs = Vec::new();
+    let mut i = 0;
+
+    while i < codelens.len() {
+        let len = codelens[i];
+        let run = codelens[i..].iter().take_while(|&&l| l == len).count();
+
+        if len == 0 && run >= 3 {
+            let run = run.min(138);
+            if run >= 11 {
+                runs.push((REPEAT_ZERO_LONG, (run - 11) as u8));
+            } else {
+                runs.push((REPEAT_ZERO, (run - 3) as u8));
+            }
+            i += run;
+        } else if len != 0 && run >= 4 {
+            // the length itself, then repeats of it, and the last one or two lengths as they are
+            runs.push((len as u8, 0));
+            let mut left = run - 1;
+            while left >= 3 {
+                let repeat = left.min(6);
+                runs.push((REPEAT_PREVIOUS, (repeat - 3) as u8));
+                left -= repeat;
+            }
+            runs.extend((0..left).map(|_| (len as u8, 0)));
+            i += run;
+        } else {
+            runs.push((len as u8, 0));
+            i += 1;
         }
+    }
+    runs
+}
 
+/// Compress the block using dynamic huffman codes and write it to `writer`.
+/// The structure of a dynamic huffman compressed block (See RFC 1951, Section 3.2.7): 
+/// - 3 bits: the header
+/// - 5 bits: HLIT, number of Literal/Length codes - 257
+/// - 5 bits: HDIST, number of Distance codes - 1
+/// - 4 bits: HCLEN, number of Code Length codes - 4
+/// - (HCLEN + 4) * 3 bits: the code lengths for the code length alphabet, in the order: 16,17,18,0,8,7,9,6,10,5,11,4,12,3,13,2,14,1,15. Zeros at the end are discarded.
+/// - HLIT + 257 code lengths for the literal/length alphabet, encoded using the code length alphabet
+/// - HDIST + 1 code lengths for the distance alphabet, encoded using the code length alphabet
+/// - the compressed data
+/// - the end of block symbol
+fn dynamic_huffman_encode_block<S: ByteSink>(writer: &mut BitWriter<S>, block: &[Symbol], codes: &DynamicCodes, bfinal: bool) {
+    // write header
+    writer.write_bits(0b100 | bfinal as u32, 3); // BTYPE = 10
 
-        if *bits == len {
-            count += 1;
-            continue;
-        } else if count > 0{
-            // write into the bitstream
-            
-        }
+    // Write HLIT, HDIST, HCLEN
+    writer.write_bits(codes.literal_codelens.len() as u32 - 257, 5);
+    writer.write_bits(codes.distance_codelens.len() as u32 - 1, 5);
+    writer.write_bits(codes.hclen as u32 - 4, 4);
+
+    // Write the code lengths for the code length alphabet, then the code lengths for the literal/length and distance alphabets
+    for &i in &CODELEN_ORDER[..codes.hclen] {
+        writer.write_bits(codes.codelen_codelens[i], 3);
+    }
+    // the code lengths come from `limited_codelens_from_freq`, so they always make valid codes
+    let codelen_codes = HuffmanCodes::build_from_codelens(&codes.codelen_codelens).expect("code lengths limited to MAX_BITS");
+    for &(symbol, extra) in &codes.runs {
+        codelen_codes.encode_char_with_extra(writer, symbol as u16, repeat_bits(symbol), extra as u32);
     }
 
-}
+    let literal_codes = HuffmanCodes::build_from_codelens(&codes.literal_codelens).expect("code lengths limited to MAX_BITS");
+    let distance_codes = HuffmanCodes::build_from_codelens(&codes.distance_codelens).expect("code lengths limited to MAX_BITS");
 
- */
+    // Encode block data using dynamic huffman codes
+    encode_symbols(writer, block, &literal_codes, &distance_codes)
+}
 
 #[cfg(test)]
 mod tests {
@@ -195,21 +272,21 @@ mod tests {
 
     #[test]
     fn test_fixed_huffman() {
-        println!("{:?}",LENGTH_REPR[7]);
-        println!("{:?}", DIST_REPR[14]);
-
-        let mut buf = Vec::new();
-        let mut writer = BitWriter::endian(&mut buf, LittleEndian);
-        let (code, bits, extra) = LENGTH_REPR[7];
-        FIXED_LITERAL_CODES.encode_char(&mut writer, code).unwrap();
-        writer.write(bits as u32, extra).unwrap();
-
-        let (code, bits, extra) = DIST_REPR[14];
-        writer.write(5, code).unwrap();
-        writer.write(bits as u32, extra).unwrap();
-        
-
-        let a = writer.into_unwritten();
-        println!("{:?} {:?}", buf, a);
+        // length 10 is code 264 (7 bits, 0001000), distance 14 is code 7 (5 bits) with 2 extra bits
+        let mut writer = BitWriter::new();
+        let block = [Symbol::Literal(b'a'), Symbol::Pointer { length: 7, distance: 14 }];
+        let huffman = HuffmanBlock::new(&block, false);
+        assert_eq!(huffman.bits(), 3 + 8 + 7 + 5 + 2 + 7);
+        huffman.write(&mut writer, &block, true);
+        let encoded = writer.finish();
+
+        let mut expected = BitWriter::new();
+        expected.write_bits(0b011, 3);
+        expected.write_bits(super::super::reverse_bits(0x30 + b'a' as u16, 8) as u32, 8);
+        expected.write_bits(super::super::reverse_bits(0b0001000, 7) as u32, 7);
+        expected.write_bits(super::super::reverse_bits(7, 5) as u32, 5);
+        expected.write_bits(1, 2);
+        expected.write_bits(0, 7);
+        assert_eq!(encoded, expected.finish());
     }
-}
\ No newline at end of file
+}
@@ -1,280 +1,265 @@
 pub(crate) mod encode;
+pub(crate) mod decode;
 
 
-use core::panic;
-use std::collections::HashMap;
-use std::{io::Read, io::Write};
-use bitstream_io::{BitReader, BitWriter, BitRead, BitWrite, LittleEndian};
-use lazy_static::lazy_static;
-use std::error::Error;
-use crate::error::*;
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::deflate::bitstream::{BitWriter, ByteSink};
 
 
 /// The maximum number of bits in a huffman code
 const MAX_BITS: u32 = 15;
 
+/// The size of the largest alphabet, the literal/length alphabet
+const MAX_SYMBOLS: usize = 288;
 
-lazy_static! {
-    static ref FIXED_HUFFMAN_BITS: [u32; 288] = {
-        let mut bits = [0; 288];
-        for i in 0..144 {
-            bits[i] = 8;
-        }
-        for i in 144..256 {
-            bits[i] = 9;
-        }
-        for i in 256..280 {
-            bits[i] = 7;
-        }
-        for i in 280..288 {
-            bits[i] = 8;
-        }
-        bits
-    };
-    
-    static ref FIXED_LITERAL_CODES: HuffmanCodes = HuffmanCodes::build_from_codelens(&*FIXED_HUFFMAN_BITS).unwrap();
+/// The order in which the code lengths of the code length alphabet are stored (See RFC 1951, Section 3.2.7)
+pub(crate) const CODELEN_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+
+/// The code lengths of the fixed literal/length codes (See RFC 1951, Section 3.2.6)
+const FIXED_HUFFMAN_BITS: [u32; 288] = {
+    let mut bits = [0; 288];
+    let mut i = 0;
+    while i < 288 {
+        bits[i] = match i {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+        i += 1;
+    }
+    bits
+};
+
+static FIXED_LITERAL_CODES: HuffmanCodes = match HuffmanCodes::build_from_codelens(&FIXED_HUFFMAN_BITS) {
+    Some(codes) => codes,
+    None => panic!("invalid fixed literal codes"),
+};
+
+/// Fixed distance codes are all 5 bits long
+static FIXED_DISTANCE_CODES: HuffmanCodes = match HuffmanCodes::build_from_codelens(&[5; 30]) {
+    Some(codes) => codes,
+    None => panic!("invalid fixed distance codes"),
+};
+
+/// [(code, bits, extra_bits), ...)]
+static LENGTH_REPR: [(u16, u8, u8); 259] = {
+    let mut repr = [(0, 0, 0); 259];
+    let mut i = 3;
+    while i < 11 {
+        repr[i] = (i as u16 + 254, 0, 0);
+        i += 1;
+    }
 
-    static ref FIXED_LITERAL_TREE: HuffmanTree = HuffmanTree::build_from_codes(&*FIXED_LITERAL_CODES).unwrap();
+    let mut base = 11;
+    let mut code = 265;
+    while code < 285 {
+        let bits = (code - 265) / 4 + 1;
 
-    /// [(code, bits, extra_bits), ...)]
-    static ref LENGTH_REPR: [(u16, u8, u8); 259] = {
-        let mut repr = [(0, 0, 0); 259];
-        for i in 3..11 {
-            repr[i] = (i as u16 + 254, 0, 0);
+        let mut extra = 0;
+        while extra < 1 << bits {
+            repr[base + extra] = (code as u16, bits as u8, extra as u8);
+            extra += 1;
         }
+        base += 1 << bits;
+        code += 1;
+    }
 
-        let mut base = 11;
+    repr[258] = (285, 0, 0);
+    repr
+};
+
+/// [(code, bits, extra_bits), ...)]
+static DIST_REPR: [(u8, u8, u16); 32769] = {
+    let mut repr = [(0, 0, 0); 32769];
+    let mut i = 1;
+    while i < 5 {
+        repr[i] = (i as u8 - 1, 0, 0);
+        i += 1;
+    }
 
-        for code in 265..285 {
-            let bits = (code - 265) / 4 + 1;
+    let mut base = 5;
+    let mut code = 4;
+    while code < 30 {
+        let bits = (code - 4) / 2 + 1;
 
-            for extra in 0..(1 << bits) {
-                let j = base + extra;
-                repr[j] = (code, bits as u8, extra as u8);
-            }
-            base += 1 << bits;
+        let mut extra = 0;
+        while extra < 1 << bits {
+            repr[base + extra] = (code as u8, bits as u8, extra as u16);
+            extra += 1;
         }
+        base += 1 << bits;
+        code += 1;
+    }
 
-        repr[258] = (285, 0, 0);
-        repr
-    };
+    repr
+};
 
-    static ref DIST_REPR: [(u8, u8, u16); 32769] = {
-        let mut repr: [(u8, u8, u16); 32769]= [(0, 0, 0); 32769];
-        for i in 1..5 {
-            let j = i as u8 - 1;
-            repr[i] = (j + 0, 0, 0);
+/// Compute the code lengths of a huffman code for the given symbol frequencies, with no code longer than `max_bits`.
+/// Symbols with a zero frequency get no code. If fewer than two symbols occur, one or two unused symbols get a code
+/// as well, since some decoders reject a code with a single symbol.
+///
+/// The lengths of an unlimited huffman tree are computed first. Codes that are too long are then cut to `max_bits`,
+/// and the number of codes of each length is fixed up until the code is complete again, the way miniz does it.
+/// The shortest lengths go to the most frequent symbols.
+fn limited_codelens_from_freq(frequencies: &[u32], max_bits: u32) -> Vec<u32> {
+    debug_assert!(frequencies.len() >= 2 && frequencies.len() <= 1 << max_bits);
+    // (frequency, symbol) of the symbols that occur, least frequent first
+    let mut symbols: Vec<(u32, usize)> = frequencies.iter().enumerate().filter(|&(_, &f)| f > 0).map(|(s, &f)| (f, s)).collect();
+    let missing = 2_usize.saturating_sub(symbols.len());
+    symbols.extend(frequencies.iter().enumerate().filter(|&(_, &f)| f == 0).take(missing).map(|(s, _)| (0, s)));
+    symbols.sort_unstable();
+
+    // build the tree bottom up with two queues, the sorted leaves and the internal nodes, which are created in increasing weight order.
+    // `parent[i]` is the parent of node i: the leaves are 0..n, and the internal nodes n..2n-1.
+    let n = symbols.len();
+    let mut weight: Vec<u64> = symbols.iter().map(|&(f, _)| f as u64).collect();
+    let mut parent = vec![0; 2 * n - 1];
+    let (mut leaf, mut node) = (0, n);
+    for next in n..2 * n - 1 {
+        let mut children = [0; 2];
+        for child in &mut children {
+            *child = if leaf < n && (node >= next || weight[leaf] <= weight[node]) {
+                leaf += 1;
+                leaf - 1
+            } else {
+                node += 1;
+                node - 1
+            };
         }
+        weight.push(weight[children[0]] + weight[children[1]]);
+        parent[children[0]] = next;
+        parent[children[1]] = next;
+    }
 
-        let mut base = 5;
-        
-        for code in 4..30 {
-            let bits = (code - 4) / 2 + 1;
-            let rcode = reverse_bits(code, 5);
+    // the depth of each node, from the root down, as the parents come after their children
+    let mut depth = vec![0u32; 2 * n - 1];
+    for i in (0..2 * n - 2).rev() {
+        depth[i] = depth[parent[i]] + 1;
+    }
 
-            for extra in 0..(1 << bits) {
-                let j = base + extra;
-                repr[j] = (rcode, bits as u8, extra as u16);
-            }
-            base += 1 << bits;
+    // the number of codes of each length, with the longer ones cut to `max_bits`
+    let mut bl_count = vec![0u32; max_bits as usize + 1];
+    for &d in &depth[..n] {
+        bl_count[d.min(max_bits) as usize] += 1;
+    }
+    // cutting made the code overfull: the Kraft sum, in units of 2^-max_bits, is above one
+    let mut total: u64 = (1..=max_bits).map(|bits| (bl_count[bits as usize] as u64) << (max_bits - bits)).sum();
+    while total > 1 << max_bits {
+        // turn a longest code and a shorter leaf into two codes one bit longer than the leaf
+        bl_count[max_bits as usize] -= 1;
+        if let Some(bits) = (1..max_bits as usize).rev().find(|&bits| bl_count[bits] > 0) {
+            bl_count[bits] -= 1;
+            bl_count[bits + 1] += 2;
         }
+        total -= 1;
+    }
 
-        repr
-    };
-}
-
-fn limited_codelens_from_freq(frequencies: &[u32], max_bits: u32) -> Vec<u32> {
-    unimplemented!();
+    let mut codelens = vec![0; frequencies.len()];
+    let mut lengths = (1..=max_bits).flat_map(|bits| (0..bl_count[bits as usize]).map(move |_| bits));
+    for &(_, symbol) in symbols.iter().rev() {
+        codelens[symbol] = lengths.next().unwrap();
+    }
+    codelens
 }
 
-fn reverse_bits(bits: u8, num: u32) -> u8 {
-    assert!(num <= 8);
-    let mut reversed = 0;
-    for i in 0..num {
-        reversed |= (bits & (1 << i)) >> i << (num - 1 - i);
-    }
-    reversed
+/// Reverse the lowest `num` bits of `bits`
+const fn reverse_bits(bits: u16, num: u32) -> u16 {
+    debug_assert!(num >= 1 && num <= 16);
+    bits.reverse_bits() >> (16 - num)
 }
 
 
-struct HuffmanCodes {
-    pub dict: HashMap<u16, Vec<bool>>
+/// The huffman codes of an alphabet, indexed by symbol.
+///
+/// Huffman codes are packed starting with their most significant bit, while everything else in the
+/// stream is packed LSB first, so the codes are stored bit-reversed and can be written as plain values.
+pub(crate) struct HuffmanCodes {
+    /// (reversed code, length) of each symbol. A length of zero means the symbol has no code.
+    codes: [(u16, u8); MAX_SYMBOLS]
 }
 
 impl HuffmanCodes {
-    pub fn build_from_tree(tree: &HuffmanTree) -> Self {
-        unimplemented!()
-    }
+    /// Build canonical huffman codes using given bit lengths. The algorithm is described in RFC 1951, Section 3.2.2.
+    /// Assume that the symbols in the alphabet begin from 0 and grow consecutively.
+    /// For example, `bitlen[3] == 2` means that the symbol 3 is encoded using 2 bits. 
+    /// Return None if any code length exceeds MAX_BITS, or if there are more than `MAX_SYMBOLS` symbols.
+    /// This is a `const fn`, so that the fixed codes are built at compile time.
+    pub const fn build_from_codelens(codelens: &[u32]) -> Option<Self> {
+        if codelens.len() > MAX_SYMBOLS { return None; }
 
-    pub fn build_from_codelens(codelens: &[u32]) -> Result<Self, DecodeError> {
         // Step 1: Count the number of codes for each code length
         let mut bl_count = [0_u32; MAX_BITS as usize + 1]; // A huffman code is 15 bits long at most.
-        for &bits in codelens {
-            if bits > MAX_BITS { return Err(DecodeError::from("")); }
+        let mut i = 0;
+        while i < codelens.len() {
+            let bits = codelens[i];
+            if bits > MAX_BITS { return None; }
             bl_count[bits as usize] += 1;
+            i += 1;
         }
 
         // Step 2: Find the numerical value for the smallest code for each code length
         bl_count[0] = 0;
         let mut next_code = [0; MAX_BITS as usize + 1];
         let mut code = 0_u16;
-        for bits in 0..MAX_BITS as usize {
+        let mut bits = 0;
+        while bits < MAX_BITS as usize {
             code = (code + bl_count[bits] as u16) << 1;
             next_code[bits + 1] = code;
+            bits += 1;
         }
 
         // Step 3: Assign numerical values to all codes, using consecutive values for all codes of the same length with the base values determined at step 2
-        let mut code_dict = HuffmanCodes {
-            dict: HashMap::new()
-        };
-        for (symbol, &bits) in codelens.iter().enumerate() {
+        let mut codes = [(0, 0); MAX_SYMBOLS];
+        let mut symbol = 0;
+        while symbol < codelens.len() {
+            let bits = codelens[symbol];
             if bits != 0 {
-                code = next_code[bits as usize];
+                let code = next_code[bits as usize];
                 next_code[bits as usize] += 1;
-                code_dict.set_code(symbol as u16, (bits, code));
+                codes[symbol] = (reverse_bits(code, bits), bits as u8);
             }
+            symbol += 1;
         }
 
-        Ok(code_dict)
-    }
-
-    /// Encode and insert the given character into the bitstream
-    pub fn encode_char<W: Write>(&self, writer: &mut BitWriter<W, LittleEndian>, character: u16) -> Result<(), Box<dyn Error>> {
-        match self.dict.get(&character) {
-            Some(code) => {
-                for bit in code {
-                    writer.write_bit(*bit)?;
-                }
-                Ok(())
-            },
-            None => Err(Box::new(EncodeError::from("failed when encoding huffman: character not found in alphabet")))
-        }
-    }
-
-    /// code: (bits, value)
-    /// 
-    /// Insert (or update) the huffman code for given character.
-    pub fn set_code(&mut self, character: u16, code: (u32, u16)) {
-        if code.0 > MAX_BITS {
-            panic!("huffman code longer than MAX_BITS");
-        }
-
-        let mut codevec = Vec::new();
-        for i in (0..code.0).rev() {
-            codevec.push((code.1 & (1 << i)) != 0);
-        }
-        self.dict.insert(character, codevec);
-    }
-}
-
-struct HuffmanTree {
-    character: Option<u16>, // 0-285
-    left: Option<Box<HuffmanTree>>,
-    right: Option<Box<HuffmanTree>>
-}
-
-impl HuffmanTree {
-    /// Create a new huffman tree node with no children
-    pub fn new(character: Option<u16>) -> Self {
-        HuffmanTree {
-            character,
-            left: None,
-            right: None,
-        }
-    }
-
-    pub fn is_leaf(&self) -> bool {
-        self.left.is_none() && self.right.is_none()
+        Some(HuffmanCodes { codes })
     }
-    
-
-    /// Build a canonical, length-limited huffman tree using given symbol freqencies.
-    /// Assume that the symbols in the alphabet begin from 0 and grow consecutively.
-    /// Return the root node, or None if the any error occurs
-    pub fn build_canonical_from_freq(frequencies: &[u32]) -> Result<Self, DecodeError> {
-        let mut codelens = limited_codelens_from_freq(frequencies, MAX_BITS);
 
-        Self::build_canonical_from_codelens(&codelens)
-    }
-
-    /// Build a canonical huffman tree using given bit lengths. The algorithm is described in RFC 1951, Section 3.2.2.
-    /// Assume that the symbols in the alphabet begin from 0 and grow consecutively.
-    /// For example, `bitlen[3] == 2` means that the symbol 3 is encoded using 2 bits. 
-    pub fn build_canonical_from_codelens(codelens: &[u32]) -> Result<Self, DecodeError> {
-        Self::build_from_codes(&HuffmanCodes::build_from_codelens(codelens)?)
+    /// The (reversed code, length) of the given character.
+    /// The codes are built from the frequencies of the data they encode, so every character written has a code.
+    #[inline]
+    fn code(&self, character: u16) -> (u32, u32) {
+        let (code, len) = self.codes[character as usize];
+        debug_assert!(len != 0, "no code for the symbol {}", character);
+        (code as u32, len as u32)
     }
 
-    /// Build a huffman tree from given huffman codes.
-    /// Return None if the codes cannot generate a legal huffman tree
-    fn build_from_codes(codes: &HuffmanCodes) -> Result<Self, DecodeError> {
-        let mut tree = HuffmanTree::new(None);
-        for (symbol, code) in &codes.dict {
-            let mut cur = &mut tree;
-
-            for bit in code {
-                match bit {
-                    false => { // go to the left
-                        if cur.left.is_none() {
-                            cur.left = Some(Box::new(HuffmanTree::new(None)));
-                        }
-                        cur = cur.left.as_mut().unwrap();
-                    },
-                    true => { // go to the right
-                        if cur.right.is_none() {
-                            cur.right = Some(Box::new(HuffmanTree::new(None)));
-                        }
-                        cur = cur.right.as_mut().unwrap();
-                    }
-                }
-            }
-
-            if !cur.is_leaf() {
-                return Err(DecodeError::from("build_from_codes: duplicated prefix"));
-            } else {
-                cur.character = Some(*symbol);
-            }
-            
-        }
-
-        Ok(tree)
+    /// Encode and insert the given character into the bitstream
+    #[inline]
+    pub fn encode_char<S: ByteSink>(&self, writer: &mut BitWriter<S>, character: u16) {
+        let (code, len) = self.code(character);
+        writer.write_bits(code, len);
     }
 
-    /// extracts ONE character from the bitstream and decode it using the huffman tree
-    pub fn decode_char<R: Read>(&self, reader: &mut BitReader<R, LittleEndian>) -> Result<u16, Box<dyn Error>> {
-        let mut cur = self;
-
-        while !cur.is_leaf() {
-            match reader.read_bit()? {
-                false => if let Some(ref left) = cur.left {
-                    cur = left;
-                } else {
-                    return Err(Box::new(DecodeError::from("failed when decoding huffman: stopped at non-leaf node")));
-                },
-                true => if let Some(ref right) = cur.right {
-                    cur = right;
-                } else {
-                    return Err(Box::new(DecodeError::from("failed when decoding huffman: stopped at non-leaf node")));
-                }
-            }
-        }
-
-        if let Some(character) = cur.character {
-            Ok(character)
-        } else {
-            Err(Box::new(DecodeError::from("failed when decoding huffman: empty leaf node")))
-        }
+    /// Encode the given character followed by `bits` extra bits holding `extra`, in a single write
+    #[inline]
+    pub fn encode_char_with_extra<S: ByteSink>(&self, writer: &mut BitWriter<S>, character: u16, bits: u32, extra: u32) {
+        let (code, len) = self.code(character);
+        writer.write_bits(code | extra << len, len + bits);
     }
 }
 
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use bitstream_io::{BitWrite, LittleEndian};
 
     #[test]
     fn test_to_unwritten() {
         let mut buf = Vec::new();
-        let mut writer = BitWriter::endian(&mut buf, LittleEndian);
+        let mut writer = bitstream_io::BitWriter::endian(&mut buf, LittleEndian);
  
         writer.write(3, 0b100).unwrap(); // BFINAL = 0, BTYPE = 10
         writer.write_bit(false).unwrap();
@@ -286,8 +271,12 @@ mod tests {
 
     #[test]
     fn test_huffman_from_bitlen() {
-        println!("{:?}", HuffmanCodes::build_from_codelens(&[2,1,3,3]).unwrap().dict);
-        println!("{:?}", HuffmanCodes::build_from_codelens(&[3,3,3,3,3,2,4,4]).unwrap().dict);
+        println!("{:?}", HuffmanCodes::build_from_codelens(&[2,1,3,3]).unwrap().codes);
+        println!("{:?}", HuffmanCodes::build_from_codelens(&[3,3,3,3,3,2,4,4]).unwrap().codes);
+
+        // A=010, B=011, C=100, D=101, E=110, F=00, G=1110, H=1111 (RFC 1951, Section 3.2.2), reversed
+        let codes = HuffmanCodes::build_from_codelens(&[3,3,3,3,3,2,4,4]).unwrap();
+        assert_eq!(codes.codes[..8], [(0b010, 3), (0b110, 3), (0b001, 3), (0b101, 3), (0b011, 3), (0b00, 2), (0b0111, 4), (0b1111, 4)]);
     }
 
     #[test]
@@ -300,6 +289,28 @@ mod tests {
         println!("{:?}", DIST_REPR[8191]);
     }
 
+    #[test]
+    fn test_limited_codelens() {
+        assert_eq!(limited_codelens_from_freq(&[10, 1, 1, 5], 15), [1, 3, 3, 2]);
+        // a single symbol is paired with an unused one
+        assert_eq!(limited_codelens_from_freq(&[0, 0, 7, 0], 15), [1, 0, 1, 0]);
+        assert_eq!(limited_codelens_from_freq(&[0, 0], 15), [1, 1]);
+
+        // fibonacci frequencies make the deepest tree, one level per symbol
+        let mut fib = vec![1u32, 1];
+        while fib.len() < 30 {
+            fib.push(fib[fib.len() - 1] + fib[fib.len() - 2]);
+        }
+        assert_eq!(*limited_codelens_from_freq(&fib, 30).iter().max().unwrap(), 29);
+        for max_bits in [7, 15] {
+            let codelens = limited_codelens_from_freq(&fib, max_bits);
+            assert_eq!(*codelens.iter().max().unwrap(), max_bits);
+            let kraft: u64 = codelens.iter().map(|&len| 1 << (max_bits - len)).sum();
+            assert_eq!(kraft, 1 << max_bits);
+            assert!(HuffmanCodes::build_from_codelens(&codelens).is_some());
+        }
+    }
+
     #[test]
     fn test_reverse_bits() {
         assert_eq!(reverse_bits(0b0010_1001, 5), 0b0001_0010);
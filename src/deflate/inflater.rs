@@ -0,0 +1,624 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use super::WINDOW_SIZE;
+use super::huffman::CODELEN_ORDER;
+use super::huffman::decode::{fixed_tables, Code, DecodeTable, DynamicTables, Lookup, MAX_DISTANCE_CODES, MAX_LITERAL_CODES};
+use crate::error::{Error, Limit, Result};
+
+/// Where the decoding of the stream is, between two calls to `Inflater::inflate`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    /// At a block boundary: BFINAL and BTYPE come next
+    BlockHeader,
+    /// LEN and NLEN of a stored block come next, after the padding to a byte boundary
+    StoredHeader,
+    /// Copying the data of a stored block, `remaining` bytes left
+    Stored { remaining: usize },
+    /// HLIT, HDIST and HCLEN of a dynamic block come next
+    TableSizes,
+    /// Reading the code lengths of the code length alphabet, `read` of them so far
+    CodelenCodelens { read: usize },
+    /// Reading the code lengths of the literal/length and distance alphabets, `read` of them so far
+    Codelens { read: usize },
+    /// A literal, a length or the end of the block comes next
+    Codes,
+    /// A literal was decoded, to be written once there is room in the output.
+    /// Codes are decoded even when the output is full, so that the end of the stream is found without more room.
+    Literal { byte: u8 },
+    /// The distance of a match of `length` bytes comes next. The match starts at bit `start`.
+    Distance { length: usize, start: u64 },
+    /// Copying a match, `length` bytes left
+    Copy { length: usize, distance: usize },
+    /// The final block was decoded
+    Done,
+}
+
+/// The input of a call to `Inflater::inflate`
+struct Input<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+/// The bits taken from the input and not decoded yet, LSB first.
+///
+/// Bytes are only taken as an item needs them (a code with its extra bits, a header field),
+/// so between items less than a byte is held, and the stream ends at the last byte it takes.
+struct Bits {
+    buf: u64,
+    count: u32,
+    /// The number of bytes taken so far, counted from the start of the compressed stream
+    taken: u64,
+}
+
+impl Bits {
+    /// Take bytes until at least `bits` bits are held. Return false if the input runs out first.
+    fn need(&mut self, input: &mut Input, bits: u32) -> bool {
+        while self.count < bits {
+            if !self.pull(input) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Take one more byte. Return false if the input has run out.
+    fn pull(&mut self, input: &mut Input) -> bool {
+        let Some(&byte) = input.data.get(input.pos) else {
+            return false;
+        };
+        self.buf |= (byte as u64) << self.count;
+        self.count += 8;
+        input.pos += 1;
+        self.taken += 1;
+        true
+    }
+
+    /// Drop and return `bits` bits (at most 32), which must be held
+    fn take(&mut self, bits: u32) -> u32 {
+        debug_assert!(bits <= self.count);
+        let value = (self.buf & ((1 << bits) - 1)) as u32;
+        self.buf >>= bits;
+        self.count -= bits;
+        value
+    }
+
+    /// The offset in the compressed stream of the next bit to decode
+    fn offset(&self) -> u64 {
+        self.taken * 8 - self.count as u64
+    }
+
+    /// Decode the next code of `table` with the extra bits that follow it, `extra(code)` of them.
+    /// Return the code and the value of its extra bits, or None, with nothing decoded, if the input runs out first.
+    fn code(&mut self, input: &mut Input, table: &DecodeTable, extra: fn(Code) -> u32) -> Result<Option<(Code, u32)>> {
+        loop {
+            match table.lookup(self.buf, self.count) {
+                Lookup::Code(code, len) => {
+                    let extra = extra(code);
+                    if !self.need(input, len + extra) {
+                        return Ok(None);
+                    }
+                    self.take(len);
+                    return Ok(Some((code, self.take(extra))));
+                }
+                Lookup::NeedBits => {
+                    if !self.pull(input) {
+                        return Ok(None);
+                    }
+                }
+                Lookup::Invalid => return Err(Error::InvalidHuffmanTable { bit_offset: self.offset() }),
+            }
+        }
+    }
+}
+
+/// The extra bits of the lengths and distances
+fn base_extra(code: Code) -> u32 {
+    match code {
+        Code::Base { extra, .. } => extra,
+        _ => 0,
+    }
+}
+
+/// The extra bits of the repeat codes of the code length alphabet
+fn codelen_extra(code: Code) -> u32 {
+    match code {
+        Code::Literal(16) => 2,
+        Code::Literal(17) => 3,
+        Code::Literal(18) => 7,
+        _ => 0,
+    }
+}
+
+/// A raw deflate decompressor that takes its input and gives its output in pieces of any size, in the style of zlib's `inflate`.
+///
+/// Decoding is a state machine that stops wherever the input runs out or the output is full, in the middle of a table header,
+/// a code or a match, and goes on from there with the next call: the input is never decoded twice.
+/// The history is kept in a window of 32 KiB, so the memory does not depend on the size of the blocks or of the output.
+/// Between blocks, the state of the stream is just its bit offset and the window,
+/// so decoding can also be resumed at a block boundary (see `with_window`).
+pub(crate) struct Inflater {
+    mode: Mode,
+    /// Whether the current block is the final one (BFINAL)
+    bfinal: bool,
+    /// Whether the current block uses the fixed codes (BTYPE = 01), or `tables`
+    fixed: bool,
+    bits: Bits,
+    /// The bits of the first byte of input that belong to the previous block, when decoding resumes in the middle of a byte
+    skip_bits: u32,
+    /// The number of literal/length, distance and code length codes of the dynamic block being read
+    hlit: usize,
+    hdist: usize,
+    hclen: usize,
+    /// The offset of the table header of the dynamic block being read, for the errors
+    table_start: u64,
+    codelen_codelens: [u32; 19],
+    codelens: [u32; MAX_LITERAL_CODES + MAX_DISTANCE_CODES],
+    tables: DynamicTables,
+    /// The last `WINDOW_SIZE` bytes of output, as a ring written at `window_pos`
+    window: Vec<u8>,
+    window_pos: usize,
+    /// The number of bytes of history in `window`, the farthest a match may reach back
+    window_len: usize,
+    /// The number of bytes decoded so far, and the most that may be decoded
+    decoded: u64,
+    max_output: u64,
+}
+
+impl Inflater {
+    pub fn new() -> Self {
+        Inflater::with_window(0, &[])
+    }
+
+    /// Resume decoding at `bit_offset` of the compressed stream, which must be the start of a block.
+    /// `window` holds the data decoded before that point, at least the last window of it if there is that much.
+    /// The input given afterwards starts with the byte holding `bit_offset`.
+    pub fn with_window(bit_offset: u64, window: &[u8]) -> Self {
+        let mut inflater = Inflater {
+            mode: Mode::BlockHeader,
+            bfinal: false,
+            fixed: false,
+            bits: Bits { buf: 0, count: 0, taken: 0 },
+            skip_bits: 0,
+            hlit: 0,
+            hdist: 0,
+            hclen: 0,
+            table_start: 0,
+            codelen_codelens: [0; 19],
+            codelens: [0; MAX_LITERAL_CODES + MAX_DISTANCE_CODES],
+            tables: DynamicTables::new(),
+            window: vec![0; WINDOW_SIZE],
+            window_pos: 0,
+            window_len: 0,
+            decoded: 0,
+            max_output: u64::MAX,
+        };
+        inflater.reset(bit_offset, window);
+        inflater
+    }
+
+    /// Same as `with_window`, keeping the buffers and tables, and with no output limit.
+    /// Decoding many streams this way does not allocate each time.
+    pub fn reset(&mut self, bit_offset: u64, window: &[u8]) {
+        self.mode = Mode::BlockHeader;
+        self.bits = Bits { buf: 0, count: 0, taken: bit_offset / 8 };
+        self.skip_bits = (bit_offset % 8) as u32;
+        self.window_pos = 0;
+        self.window_len = 0;
+        self.push_window(window);
+        self.decoded = 0;
+        self.max_output = u64::MAX;
+    }
+
+    /// Fail with `LimitExceeded(Limit::OutputSize)` instead of decoding more than `max_output` bytes in total
+    pub fn set_output_limit(&mut self, max_output: u64) {
+        self.max_output = max_output;
+    }
+
+    /// Decode `input` into `output`, until the input runs out, the output is full or the stream ends,
+    /// or, with `stop_at_block`, a block ends. Return the number of bytes taken from the input and written to the output.
+    ///
+    /// All the input is taken unless decoding stops before it runs out, and none after the end of the stream.
+    /// The bits of an item that is cut short are kept, and decoding goes on with the input of the next call.
+    pub fn inflate(&mut self, input: &[u8], output: &mut [u8], stop_at_block: bool) -> Result<(usize, usize)> {
+        let mut input = Input { data: input, pos: 0 };
+        let mut produced = 0;
+
+        if self.skip_bits > 0 {
+            if !self.bits.need(&mut input, self.skip_bits) {
+                return Ok((input.pos, 0));
+            }
+            self.bits.take(self.skip_bits);
+            self.skip_bits = 0;
+        }
+
+        loop {
+            match self.mode {
+                Mode::BlockHeader => {
+                    let start = self.bits.offset();
+                    if !self.bits.need(&mut input, 3) {
+                        break;
+                    }
+                    self.bfinal = self.bits.take(1) == 1;
+                    self.mode = match self.bits.take(2) {
+                        0b00 => Mode::StoredHeader,
+                        0b01 => {
+                            self.fixed = true;
+                            Mode::Codes
+                        }
+                        0b10 => {
+                            self.fixed = false;
+                            self.table_start = self.bits.offset();
+                            Mode::TableSizes
+                        }
+                        _ => return Err(Error::InvalidBlockType { bit_offset: start }),
+                    };
+                }
+                Mode::StoredHeader => {
+                    self.bits.take(self.bits.count % 8);
+                    let start = self.bits.offset();
+                    if !self.bits.need(&mut input, 32) {
+                        break;
+                    }
+                    let len = self.bits.take(16);
+                    let nlen = self.bits.take(16);
+                    if len != !nlen & 0xffff {
+                        // the length does not match its complement
+                        return Err(Error::InvalidBlockType { bit_offset: start });
+                    }
+                    self.mode = Mode::Stored { remaining: len as usize };
+                }
+                Mode::Stored { remaining: 0 } => {
+                    if self.end_block(stop_at_block) {
+                        break;
+                    }
+                }
+                Mode::Stored { remaining } => {
+                    // LEN and NLEN end at a byte boundary, so the data comes straight from the input
+                    debug_assert_eq!(self.bits.count, 0);
+                    let len = remaining.min(input.data.len() - input.pos).min(output.len() - produced);
+                    if len == 0 {
+                        break;
+                    }
+                    self.write(&input.data[input.pos..input.pos + len], output, &mut produced)?;
+                    input.pos += len;
+                    self.bits.taken += len as u64;
+                    self.mode = Mode::Stored { remaining: remaining - len };
+                }
+                Mode::TableSizes => {
+                    if !self.bits.need(&mut input, 14) {
+                        break;
+                    }
+                    self.hlit = self.bits.take(5) as usize + 257;
+                    self.hdist = self.bits.take(5) as usize + 1;
+                    self.hclen = self.bits.take(4) as usize + 4;
+                    if self.hlit > MAX_LITERAL_CODES || self.hdist > MAX_DISTANCE_CODES {
+                        return Err(Error::InvalidHuffmanTable { bit_offset: self.table_start });
+                    }
+                    self.codelen_codelens = [0; 19];
+                    self.mode = Mode::CodelenCodelens { read: 0 };
+                }
+                Mode::CodelenCodelens { mut read } => {
+                    while read < self.hclen && self.bits.need(&mut input, 3) {
+                        self.codelen_codelens[CODELEN_ORDER[read]] = self.bits.take(3);
+                        read += 1;
+                    }
+                    self.mode = Mode::CodelenCodelens { read };
+                    if read < self.hclen {
+                        break;
+                    }
+                    if !self.tables.build_codelen(&self.codelen_codelens) {
+                        return Err(Error::InvalidHuffmanTable { bit_offset: self.table_start });
+                    }
+                    self.mode = Mode::Codelens { read: 0 };
+                }
+                Mode::Codelens { mut read } => {
+                    let total = self.hlit + self.hdist;
+                    // The literal/length and distance code lengths form a single sequence, repeats may cross the boundary
+                    while read < total {
+                        let Some((code, extra)) = self.bits.code(&mut input, &self.tables.codelen, codelen_extra)? else {
+                            break;
+                        };
+                        let (value, repeat) = match code {
+                            Code::Literal(16) => match read {
+                                // repeat code without a previous code length
+                                0 => return Err(Error::InvalidHuffmanTable { bit_offset: self.table_start }),
+                                _ => (self.codelens[read - 1], 3 + extra as usize),
+                            },
+                            Code::Literal(17) => (0, 3 + extra as usize),
+                            Code::Literal(18) => (0, 11 + extra as usize),
+                            Code::Literal(len) => (len, 1),
+                            _ => return Err(Error::InvalidHuffmanTable { bit_offset: self.table_start }),
+                        };
+                        if read + repeat > total {
+                            return Err(Error::InvalidHuffmanTable { bit_offset: self.table_start });
+                        }
+                        self.codelens[read..read + repeat].fill(value);
+                        read += repeat;
+                    }
+                    self.mode = Mode::Codelens { read };
+                    if read < total {
+                        break;
+                    }
+                    if !self.tables.build_codes(&self.codelens[..total], self.hlit) {
+                        return Err(Error::InvalidHuffmanTable { bit_offset: self.table_start });
+                    }
+                    self.mode = Mode::Codes;
+                }
+                Mode::Codes => {
+                    let start = self.bits.offset();
+                    let literal_table = if self.fixed { fixed_tables().0 } else { &self.tables.literal };
+                    match self.bits.code(&mut input, literal_table, base_extra)? {
+                        None => break,
+                        Some((Code::Literal(byte), _)) if produced < output.len() => self.write(&[byte as u8], output, &mut produced)?,
+                        Some((Code::Literal(byte), _)) => self.mode = Mode::Literal { byte: byte as u8 },
+                        Some((Code::Base { base, .. }, extra)) => self.mode = Mode::Distance { length: (base + extra) as usize, start },
+                        Some((Code::EndOfBlock, _)) => {
+                            if self.end_block(stop_at_block) {
+                                break;
+                            }
+                        }
+                    }
+                }
+                Mode::Literal { byte } => {
+                    if produced == output.len() {
+                        break;
+                    }
+                    self.write(&[byte], output, &mut produced)?;
+                    self.mode = Mode::Codes;
+                }
+                Mode::Distance { length, start } => {
+                    let distance_table = if self.fixed { fixed_tables().1 } else { &self.tables.distance };
+                    let distance = match self.bits.code(&mut input, distance_table, base_extra)? {
+                        None => break,
+                        Some((Code::Base { base, .. }, extra)) => (base + extra) as usize,
+                        // the distance alphabet has nothing else
+                        Some(_) => return Err(Error::InvalidHuffmanTable { bit_offset: start }),
+                    };
+                    if distance > self.window_len {
+                        return Err(Error::DistanceTooFar { distance, bit_offset: start });
+                    }
+                    self.mode = Mode::Copy { length, distance };
+                }
+                Mode::Copy { length, distance } => {
+                    let len = length.min(output.len() - produced);
+                    if len == 0 {
+                        break;
+                    }
+                    self.copy_match(distance, len, output, &mut produced)?;
+                    self.mode = if len == length { Mode::Codes } else { Mode::Copy { length: length - len, distance } };
+                }
+                Mode::Done => break,
+            }
+        }
+
+        Ok((input.pos, produced))
+    }
+
+    /// Go on to the next block, or end the stream at a byte boundary after the final block.
+    /// Return whether `inflate` stops here.
+    fn end_block(&mut self, stop_at_block: bool) -> bool {
+        if self.bfinal {
+            self.bits.take(self.bits.count % 8);
+            self.mode = Mode::Done;
+            return true;
+        }
+        self.mode = Mode::BlockHeader;
+        stop_at_block
+    }
+
+    /// Check the output limit before `len` more bytes are decoded
+    fn count_output(&mut self, len: usize) -> Result<()> {
+        if self.decoded + len as u64 > self.max_output {
+            return Err(Error::LimitExceeded(Limit::OutputSize));
+        }
+        self.decoded += len as u64;
+        Ok(())
+    }
+
+    /// Append `data` to the output and to the window
+    fn write(&mut self, data: &[u8], output: &mut [u8], produced: &mut usize) -> Result<()> {
+        self.count_output(data.len())?;
+        output[*produced..*produced + data.len()].copy_from_slice(data);
+        *produced += data.len();
+        self.push_window(data);
+        Ok(())
+    }
+
+    /// Append `len` bytes copied from `distance` bytes back to the output and to the window
+    fn copy_match(&mut self, distance: usize, len: usize, output: &mut [u8], produced: &mut usize) -> Result<()> {
+        self.count_output(len)?;
+        let mut left = len;
+        while left > 0 {
+            // copy in pieces that do not wrap around the ring, and that are no longer than the distance,
+            // so that a piece never reads bytes it writes itself
+            let from = (self.window_pos + WINDOW_SIZE - distance) % WINDOW_SIZE;
+            let n = left.min(distance).min(WINDOW_SIZE - from).min(WINDOW_SIZE - self.window_pos);
+            self.window.copy_within(from..from + n, self.window_pos);
+            output[*produced..*produced + n].copy_from_slice(&self.window[self.window_pos..self.window_pos + n]);
+            *produced += n;
+            self.window_pos = (self.window_pos + n) % WINDOW_SIZE;
+            left -= n;
+        }
+        self.window_len = (self.window_len + len).min(WINDOW_SIZE);
+        Ok(())
+    }
+
+    /// Append `data` to the window, of which only the last `WINDOW_SIZE` bytes are kept
+    fn push_window(&mut self, data: &[u8]) {
+        let data = &data[data.len().saturating_sub(WINDOW_SIZE)..];
+        let first = data.len().min(WINDOW_SIZE - self.window_pos);
+        self.window[self.window_pos..self.window_pos + first].copy_from_slice(&data[..first]);
+        self.window[..data.len() - first].copy_from_slice(&data[first..]);
+        self.window_pos = (self.window_pos + data.len()) % WINDOW_SIZE;
+        self.window_len = (self.window_len + data.len()).min(WINDOW_SIZE);
+    }
+
+    /// The last window of the decoded data
+    pub fn window(&self) -> Vec<u8> {
+        let start = (self.window_pos + WINDOW_SIZE - self.window_len) % WINDOW_SIZE;
+        if start + self.window_len <= WINDOW_SIZE {
+            self.window[start..start + self.window_len].to_vec()
+        } else {
+            [&self.window[start..], &self.window[..self.window_pos]].concat()
+        }
+    }
+
+    /// The bit offset of the next bit to decode in the compressed stream, which is the end of the stream once it is done
+    pub fn bit_offset(&self) -> u64 {
+        self.bits.offset() + self.skip_bits as u64
+    }
+
+    /// The number of bytes of the compressed stream taken so far, where a stream that is cut short ends
+    pub fn input_offset(&self) -> u64 {
+        self.bits.taken
+    }
+
+    /// Whether decoding is between two blocks, or at the end of the stream
+    pub fn at_block_boundary(&self) -> bool {
+        matches!(self.mode, Mode::BlockHeader | Mode::Done)
+    }
+
+    /// Whether the final block has been decoded
+    pub fn is_done(&self) -> bool {
+        self.mode == Mode::Done
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deflate::{deflate, deflate_chunk, deflate_stored, Params};
+
+    fn sample() -> Vec<u8> {
+        (0..100_000u32).flat_map(|i| format!("{} ", i % 1234).into_bytes()).collect()
+    }
+
+    /// Decode `src` fed `piece` bytes at a time into `out_piece`-byte outputs.
+    /// Return the output, the input taken, and the bit offsets of the block boundaries.
+    fn inflate_in_pieces(inflater: &mut Inflater, src: &[u8], piece: usize, out_piece: usize) -> Result<(Vec<u8>, usize, Vec<u64>)> {
+        let mut output = Vec::new();
+        let mut offsets = Vec::new();
+        let mut buf = vec![0; out_piece];
+        let mut pos = 0;
+
+        while !inflater.is_done() {
+            let before = inflater.bit_offset();
+            let end = (pos + piece).min(src.len());
+            let (taken, written) = inflater.inflate(&src[pos..end], &mut buf, true)?;
+            pos += taken;
+            output.extend_from_slice(&buf[..written]);
+            if inflater.at_block_boundary() && inflater.bit_offset() != before {
+                offsets.push(inflater.bit_offset());
+            }
+            if taken == 0 && written == 0 && inflater.bit_offset() == before && pos == src.len() {
+                return Err(Error::TruncatedInput { bit_offset: inflater.input_offset() * 8 });
+            }
+        }
+        Ok((output, pos, offsets))
+    }
+
+    #[test]
+    fn test_inflater() {
+        let raw = sample();
+        let src = [deflate(&raw).unwrap(), b"trailer".to_vec()].concat();
+        let len = src.len() - b"trailer".len();
+
+        for (piece, out_piece) in [(1, 1 << 20), (100, 1), (1000, 777), (src.len(), 1 << 20)] {
+            let mut inflater = Inflater::new();
+            // the input after the end of the stream is not taken
+            assert_eq!(inflate_in_pieces(&mut inflater, &src, piece, out_piece).unwrap().0, raw, "{} {}", piece, out_piece);
+            assert_eq!(inflater.bit_offset(), len as u64 * 8);
+        }
+
+        let mut inflater = Inflater::new();
+        let (_, taken, _) = inflate_in_pieces(&mut inflater, &src, 1000, 4096).unwrap();
+        assert_eq!(taken, len);
+
+        // stored blocks, with a window full of their data
+        let stored = deflate_stored(&raw);
+        let mut inflater = Inflater::new();
+        assert_eq!(inflate_in_pieces(&mut inflater, &stored, 999, 1001).unwrap().0, raw);
+        assert_eq!(inflater.window(), raw[raw.len() - WINDOW_SIZE..]);
+    }
+
+    #[test]
+    fn test_no_redecoding() {
+        // each byte of input is looked at once: the bit buffer never holds more than the item being decoded
+        let raw = sample();
+        let src = deflate(&raw).unwrap();
+        let mut inflater = Inflater::new();
+        let mut out = vec![0; raw.len()];
+        let mut produced = 0;
+        for byte in src.chunks(1) {
+            let (taken, written) = inflater.inflate(byte, &mut out[produced..], false).unwrap();
+            assert_eq!(taken, 1);
+            produced += written;
+            assert!(inflater.bits.count < 64);
+        }
+        assert!(inflater.is_done());
+        assert_eq!(out, raw);
+    }
+
+    #[test]
+    fn test_resume() {
+        let raw = sample();
+        let split = 1037;
+        let src = [deflate_chunk(&raw, 0, split, &Params::default(), None).unwrap(), deflate_chunk(&raw, split, raw.len(), &Params::default(), None).unwrap()].concat();
+
+        let mut inflater = Inflater::new();
+        let (_, _, offsets) = inflate_in_pieces(&mut inflater, &src, 4096, 4096).unwrap();
+        // the sync flush of the first chunk starts in the middle of a byte
+        assert_eq!(offsets[0] % 8, 7);
+
+        // the second chunk starts after the sync flush
+        let mut first = Inflater::new();
+        let mut out = vec![0; split];
+        let mut produced = 0;
+        let mut taken = 0;
+        while produced < split || !first.at_block_boundary() {
+            let (t, w) = first.inflate(&src[taken..], &mut out[produced..], true).unwrap();
+            taken += t;
+            produced += w;
+        }
+        assert_eq!(out, raw[..split]);
+
+        let bit_offset = first.bit_offset();
+        let mut resumed = Inflater::with_window(bit_offset, &first.window());
+        let (output, _, _) = inflate_in_pieces(&mut resumed, &src[bit_offset as usize / 8..], 777, 555).unwrap();
+        assert_eq!(output, raw[split..]);
+    }
+
+    #[test]
+    fn test_inflater_errors() {
+        let src = deflate(&sample()).unwrap();
+        let half = src.len() / 2;
+        let mut inflater = Inflater::new();
+        assert!(matches!(inflate_in_pieces(&mut inflater, &src[..half], 100, 4096), Err(Error::TruncatedInput { bit_offset }) if bit_offset == half as u64 * 8));
+
+        // BTYPE = 11 after an empty stored block
+        let mut inflater = Inflater::new();
+        let result = inflater.inflate(&[0x00, 0x00, 0x00, 0xff, 0xff, 0x07], &mut [0; 10], false);
+        assert!(matches!(result, Err(Error::InvalidBlockType { bit_offset: 40 })));
+
+        // a distance before the start of the output: a literal, then a match of length 3 at distance 2
+        let mut inflater = Inflater::new();
+        let result = inflater.inflate(&[0x4b, 0x04, 0x42, 0x00], &mut [0; 10], false);
+        assert!(matches!(result, Err(Error::DistanceTooFar { distance: 2, bit_offset: 11 })));
+    }
+
+    #[test]
+    fn test_output_limit() {
+        let raw = sample();
+        let src = deflate(&raw).unwrap();
+
+        let mut inflater = Inflater::new();
+        inflater.set_output_limit(raw.len() as u64);
+        assert_eq!(inflate_in_pieces(&mut inflater, &src, 1000, 1000).unwrap().0, raw);
+
+        let mut inflater = Inflater::new();
+        inflater.set_output_limit(raw.len() as u64 - 1);
+        let result = inflate_in_pieces(&mut inflater, &src, 1000, 1000);
+        assert!(matches!(result, Err(Error::LimitExceeded(Limit::OutputSize))));
+    }
+}
@@ -1,138 +1,309 @@
-use super::Symbol;
-use std::error::Error;
-use std::io::{BufRead, ErrorKind};
-use super::Lz77Status;
-use crate::circular_buf::CircularBuf;
-
-/// Encode the input data using lz77 algorithm
-pub(crate) fn lz77_encode_block<R: BufRead>(reader: &mut R, search: &mut CircularBuf<u8>, lookahead: &mut CircularBuf<u8>, blksize: usize) -> Result<Lz77Status, Box::<dyn Error>> {
-    let mut output = Vec::new();
-
-    let mut ended = false;
-    let mut buf = [0u8; 1];
-
-    loop {
-        // Fill the lookahead buffer
-        while !ended && !lookahead.is_full() {
-            match reader.read_exact(&mut buf) {
-                Ok(_) => lookahead.push_back(buf[0]),
-                Err(err) => {
-                    if err.kind() == ErrorKind::UnexpectedEof {
-                        ended = true;
-                        break;
-                    } else {
-                        return Err(Box::new(err));
+use alloc::vec;
+use alloc::vec::Vec;
+use super::{Symbol, WINDOW_SIZE, LOOKAHEAD_SIZE, MIN_MATCH};
+use crate::deflate::Params;
+use crate::options::Strategy;
+
+/// For each level: how many earlier positions with the same hash are tried before giving up on a longer match,
+/// and the match length that is good enough to stop looking. Level 0 does not look for matches.
+const LEVELS: [(usize, usize); 10] = [
+    (0, 0), (4, 8), (8, 16), (16, 32), (32, 64), (64, 128), (128, LOOKAHEAD_SIZE), (256, LOOKAHEAD_SIZE), (1024, LOOKAHEAD_SIZE), (4096, LOOKAHEAD_SIZE),
+];
+
+/// With `Strategy::Filtered`, shorter matches are left as literals, as in zlib
+const FILTERED_MIN_MATCH: usize = 6;
+
+/// Finds back references through hash chains over the 3-byte prefixes of the last `window` positions.
+///
+/// The positions are indices into the data passed to `encode`, stored plus one so that zero means no entry.
+/// `head` holds the latest position of each hash, and `prev` links a position to the previous one with the same hash.
+pub(crate) struct MatchFinder {
+    head: Vec<usize>,
+    prev: Vec<usize>,
+    /// The largest distance of a match, a power of two up to `WINDOW_SIZE`
+    window: usize,
+    hash_bits: u32,
+    strategy: Strategy,
+    max_chain: usize,
+    nice_length: usize,
+    /// No match starts before this position
+    start: usize,
+}
+
+impl MatchFinder {
+    /// A finder for matches up to a window back, with a hash table of `1 << (mem_level + 7)` entries.
+    /// The level tells how long to look for a match, and the strategy which matches are worth taking.
+    pub fn new(params: &Params) -> Self {
+        let hash_bits = params.mem_level as u32 + 7;
+        debug_assert!(params.window_size() <= WINDOW_SIZE && (1..=16).contains(&hash_bits));
+        let (max_chain, nice_length) = LEVELS[params.level as usize];
+        // level 0 finds no matches, and stores the data
+        let strategy = if params.level == 0 { Strategy::HuffmanOnly } else { params.strategy };
+        MatchFinder {
+            head: vec![0; 1 << hash_bits],
+            prev: vec![0; params.window_size()],
+            window: params.window_size(),
+            hash_bits,
+            strategy,
+            max_chain,
+            nice_length,
+            start: 0,
+        }
+    }
+
+    /// Encode `data[pos..end]` into `symbols`, with `data[..pos]` as the history.
+    /// A match may run past `end` up to the end of `data`.
+    /// Stop early when `symbols` holds `max_symbols` symbols. Return the position reached.
+    pub fn encode(&mut self, data: &[u8], mut pos: usize, end: usize, symbols: &mut Vec<Symbol>, max_symbols: usize) -> usize {
+        while pos < end && symbols.len() < max_symbols {
+            let found = match self.strategy {
+                Strategy::Default | Strategy::Fixed => self.longest_match(data, pos),
+                Strategy::Filtered => self.longest_match(data, pos).filter(|&(length, _)| length >= FILTERED_MIN_MATCH),
+                Strategy::Rle => self.run_match(data, pos),
+                Strategy::HuffmanOnly => None,
+            };
+            match found {
+                Some((length, distance)) => {
+                    debug_assert!((3..=258).contains(&length));
+                    symbols.push(Symbol::Pointer { length: (length - 3) as u8, distance: distance as u16 });
+                    for p in pos..pos + length {
+                        self.insert(data, p);
                     }
+                    pos += length;
+                },
+                None => {
+                    symbols.push(Symbol::Literal(data[pos]));
+                    self.insert(data, pos);
+                    pos += 1;
                 }
-            };
+            }
         }
+        pos
+    }
 
-        if lookahead.is_empty() {
-            break;
+    /// Add `data[..end]` to the hash chains, so that it can be used as a preset dictionary
+    pub fn prime(&mut self, data: &[u8], end: usize) {
+        for pos in end.saturating_sub(self.window)..end {
+            self.insert(data, pos);
         }
+    }
 
-        match longest_match(&search, &lookahead) {
-            Some((length, distance)) => {
-                // Found a match
-                assert!(length >= 3 && length <= 258);
-                output.push(Symbol::Pointer { length: (length - 3) as u8, distance: distance as u16 });
-                for _ in 0..length {
-                    search.push_back(lookahead.pop_front().unwrap());
+    /// Forget all the positions, to encode new data from scratch with the same tables.
+    /// `inserted` is the data whose positions are all the ones inserted so far, if the caller still has it:
+    /// when it is shorter than the hash table, only the entries of its positions are cleared, which is cheaper for short inputs.
+    /// `prev` is left as it is, as it is only reached through `head`, and each position sets its own link when it is inserted.
+    pub fn reset(&mut self, inserted: Option<&[u8]>) {
+        match inserted {
+            Some(data) if data.len() < self.head.len() => {
+                for pos in 0..data.len().saturating_sub(MIN_MATCH - 1) {
+                    self.head[hash(data, pos, self.hash_bits)] = 0;
                 }
-            },
-            None => {
-                // No match found
-                let c = lookahead.pop_front().unwrap();
-                output.push(Symbol::Literal(c));
-                search.push_back(c);
             }
+            _ => self.head.fill(0)
         }
+        self.start = 0;
+    }
 
-        if output.len() == blksize {
-            break;
+    /// Forget the history before `pos`, so that the data from `pos` on is encoded without referring back before it
+    pub fn forget_before(&mut self, pos: usize) {
+        self.start = pos;
+    }
+
+    /// Move all positions back by `WINDOW_SIZE`, after the caller dropped that many bytes from the front of its buffer.
+    /// The caller always slides by the largest window, so that its buffer does not depend on `window`.
+    /// Positions that fall off the front are forgotten.
+    pub fn slide(&mut self) {
+        for entry in self.head.iter_mut().chain(self.prev.iter_mut()) {
+            *entry = entry.saturating_sub(WINDOW_SIZE);
         }
+        self.start = self.start.saturating_sub(WINDOW_SIZE);
     }
 
-    if lookahead.is_empty() {
-        Ok(Lz77Status::LastBlock(output))
-    } else {
-        Ok(Lz77Status::Normal(output))
+    /// Add `pos` to the hash chains. Positions too close to the end of `data` to have a 3-byte prefix are skipped,
+    /// and so is everything when the strategy does not use the chains.
+    #[inline]
+    fn insert(&mut self, data: &[u8], pos: usize) {
+        if pos + MIN_MATCH <= data.len() && !matches!(self.strategy, Strategy::Rle | Strategy::HuffmanOnly) {
+            let hash = hash(data, pos, self.hash_bits);
+            self.prev[pos & (self.window - 1)] = self.head[hash];
+            self.head[hash] = pos + 1;
+        }
     }
-}
 
+    /// Find the longest match for `data[pos..]` within the window, as (length, distance)
+    fn longest_match(&self, data: &[u8], pos: usize) -> Option<(usize, usize)> {
+        let max = LOOKAHEAD_SIZE.min(data.len() - pos);
+        if max < MIN_MATCH {
+            return None;
+        }
 
-/// A trival implementation of longest match, may be slow. 
-/// To be optimized
-fn longest_match(window: &CircularBuf<u8>, lookahead: &CircularBuf<u8>) -> Option<(usize, usize)> {
-    let mut best_match = (0, 0); // (length, distance)
+        let mut best = (0, 0); // (length, distance)
+        let mut entry = self.head[hash(data, pos, self.hash_bits)];
 
-    for i in 0..window.len() {
-        let mut match_length = 0;
-        for j in 0..lookahead.len() {
-            if i + j >= window.len() {
-                break;
-            } else if window[i + j] == lookahead[j] {
-                match_length += 1;
-            } else {
+        for _ in 0..self.max_chain {
+            // the chain goes back in order, so the rest of it is out of reach too
+            if entry == 0 || entry - 1 < self.start || pos - (entry - 1) > self.window {
                 break;
             }
+            let candidate = entry - 1;
+
+            // a longer match must at least agree on the byte after the current best
+            if data[candidate + best.0] == data[pos + best.0] {
+                let length = match_length(data, candidate, pos, max);
+                if length > best.0 {
+                    best = (length, pos - candidate);
+                    if length >= max.min(self.nice_length) {
+                        break;
+                    }
+                }
+            }
+            entry = self.prev[candidate & (self.window - 1)];
         }
-        if match_length >= 3 && match_length > best_match.0 {
-            best_match = (match_length, window.len() - i);
+
+        if best.0 >= MIN_MATCH {
+            Some(best)
+        } else {
+            None
         }
     }
 
-    if best_match.0 >= 3 {
-        Some(best_match)
-    } else {
-        None
+    /// Find the run of the byte before `pos` that starts at `pos`, as a match at distance 1
+    fn run_match(&self, data: &[u8], pos: usize) -> Option<(usize, usize)> {
+        if pos == 0 || pos - 1 < self.start {
+            return None;
+        }
+        let max = LOOKAHEAD_SIZE.min(data.len() - pos);
+        let length = match_length(data, pos - 1, pos, max);
+        if length >= MIN_MATCH {
+            Some((length, 1))
+        } else {
+            None
+        }
     }
 }
 
+/// A `bits`-bit hash of the 3-byte prefix at `pos`
+#[inline]
+fn hash(data: &[u8], pos: usize, bits: u32) -> usize {
+    let prefix = u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], 0]);
+    (prefix.wrapping_mul(0x9e37_79b1) >> (32 - bits)) as usize
+}
+
+/// The length of the common prefix of `data[a..]` and `data[b..]`, at most `max`.
+/// Compares 8 bytes at a time: the first differing byte is the lowest nonzero byte of the XOR.
+#[inline]
+fn match_length(data: &[u8], a: usize, b: usize, max: usize) -> usize {
+    let mut length = 0;
+    while length + 8 <= max {
+        let x = u64::from_le_bytes(data[a + length..a + length + 8].try_into().unwrap());
+        let y = u64::from_le_bytes(data[b + length..b + length + 8].try_into().unwrap());
+        let diff = x ^ y;
+        if diff != 0 {
+            return length + (diff.trailing_zeros() / 8) as usize;
+        }
+        length += 8;
+    }
+    while length < max && data[a + length] == data[b + length] {
+        length += 1;
+    }
+    length
+}
+
 
 #[cfg(test)]
 mod tests {
-    use crate::deflate::lz77::{WINDOW_SIZE, LOOKAHEAD_SIZE};
-
     use super::*;
-    use std::io::BufReader;
+
+    #[test]
+    fn test_match_length() {
+        let data = b"abcdefghijklmnopqrstuvwxyz-abcdefghijklmnopqrsTUVWXYZ";
+        assert_eq!(match_length(data, 0, 27, 26), 19);
+        assert_eq!(match_length(data, 0, 27, 10), 10);
+        assert_eq!(match_length(data, 1, 27, 26), 0);
+        // overlapping, like a run of a repeated byte
+        assert_eq!(match_length(&[7; 20], 0, 1, 19), 19);
+    }
 
     #[test]
     fn test_longest_match() {
-        let mut window = CircularBuf::with_capacity(WINDOW_SIZE);
-        let mut lookahead = CircularBuf::with_capacity(LOOKAHEAD_SIZE);
+        let data = "Hello, world!\nHello, Rust!\nRust is the best language!\n".as_bytes();
+        let mut finder = MatchFinder::new(&Params::default());
+        let mut symbols = Vec::new();
 
-        let l = "Hello, world!\nHello, Rust!\nRust is the best language!\n".as_bytes();
+        assert_eq!(finder.encode(data, 0, 14, &mut symbols, usize::MAX), 14);
+        assert_eq!(finder.longest_match(data, 14), Some((7, 14)));
+    }
 
-        for b in l {
-            lookahead.push_back(*b);
-        }
+    #[test]
+    fn test_lz77_encode() {
+        let input = "Hello, world!\nHello, Rust!\nRust is the best language!\n".as_bytes();
+        let mut finder = MatchFinder::new(&Params::default());
+        let mut symbols = Vec::new();
 
-        for _ in 0..14 {
-            window.push_back(lookahead.pop_front().unwrap());
-        }
+        assert_eq!(finder.encode(input, 0, input.len(), &mut symbols, 65535), input.len());
+        let encoded: Vec<String> = symbols.iter().map(|s| s.to_string()).collect();
+        assert_eq!(encoded[14], "(7,14)");
+        assert_eq!(encoded[21], "(4,6)");
+        assert_eq!(encoded[32], "(3,12)");
+        assert_eq!(encoded.len(), 43);
 
-        assert_eq!(longest_match(&window, &lookahead), Some((7, 14)));
+        // stops after the given number of symbols
+        let mut finder = MatchFinder::new(&Params::default());
+        symbols.clear();
+        assert_eq!(finder.encode(input, 0, input.len(), &mut symbols, 3), 3);
     }
 
     #[test]
-    fn test_lz77_encode() {
-        let input = Vec::from("Hello, world!\nHello, Rust!\nRust is the best language!\n");
-        let mut reader = BufReader::new(input.as_slice());
-        let mut window = CircularBuf::with_capacity(WINDOW_SIZE);
-        let mut lookahead = CircularBuf::with_capacity(LOOKAHEAD_SIZE);
-
-        let block = lz77_encode_block(&mut reader, &mut window, &mut lookahead, 65535)
-            .unwrap();
-
-        match block {
-            Lz77Status::LastBlock(blk) => {
-                for s in blk {
-                    print!("{}, ", s);
-                }
-            }
-            _ => panic!("Should be the last block")
+    fn test_slide() {
+        let mut data = vec![0u8; WINDOW_SIZE];
+        data.extend_from_slice(b"abcdef");
+        data.extend_from_slice(&[1u8; WINDOW_SIZE - 6]);
+        data.extend_from_slice(b"abcdef");
+
+        let mut finder = MatchFinder::new(&Params::default());
+        let mut symbols = Vec::new();
+        let end = 2 * WINDOW_SIZE;
+        assert_eq!(finder.encode(&data, 0, end, &mut symbols, usize::MAX), end);
+
+        finder.slide();
+        let data = &data[WINDOW_SIZE..];
+        assert_eq!(finder.longest_match(data, WINDOW_SIZE), Some((6, WINDOW_SIZE)));
+    }
+
+    #[test]
+    fn test_small_window() {
+        let mut data = b"abcdef".to_vec();
+        data.extend_from_slice(&[1u8; 300]);
+        data.extend_from_slice(b"abcdef");
+
+        let mut finder = MatchFinder::new(&Params { window_bits: 8, mem_level: 2, ..Default::default() });
+        let mut symbols = Vec::new();
+        let end = data.len() - 6;
+        assert_eq!(finder.encode(&data, 0, end, &mut symbols, usize::MAX), end);
+        // 306 bytes back, past the 256-byte window
+        assert_eq!(finder.longest_match(&data, end), None);
+
+        let mut finder = MatchFinder::new(&Params { window_bits: 9, mem_level: 2, ..Default::default() });
+        finder.prime(&data, end);
+        assert_eq!(finder.longest_match(&data, end), Some((6, 306)));
+    }
+
+    #[test]
+    fn test_strategies() {
+        let data = b"xyz1 abcdef abcdef aaaaaaaa xyz2 abcdefgh";
+        let encode = |strategy| {
+            let mut symbols = Vec::new();
+            MatchFinder::new(&Params { strategy, ..Default::default() }).encode(data, 0, data.len(), &mut symbols, usize::MAX);
+            symbols.iter().map(|s| s.to_string()).collect::<Vec<_>>()
         };
+
+        let matches = |symbols: Vec<String>| symbols.into_iter().filter(|s| s.starts_with('(')).collect::<Vec<_>>();
+
+        assert_eq!(matches(encode(Strategy::Default)), ["(9,7)", "(7,1)", "(3,28)", "(7,21)"]);
+        // the 3-byte match is left as literals
+        assert_eq!(matches(encode(Strategy::Filtered)), ["(9,7)", "(7,1)", "(7,21)"]);
+        // only the run of a
+        assert_eq!(matches(encode(Strategy::Rle)), ["(7,1)"]);
+        assert_eq!(encode(Strategy::HuffmanOnly).len(), data.len());
     }
 }
-
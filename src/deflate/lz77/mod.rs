@@ -1,9 +1,11 @@
 pub(super) mod encode;
 
-use std::fmt::Display;
+use core::fmt::{self, Display};
 
+/// The largest window of deflate, 32 KiB (windowBits 15)
 pub(super) const WINDOW_SIZE: usize = 32768;
 pub(super) const LOOKAHEAD_SIZE: usize = 258;
+pub(super) const MIN_MATCH: usize = 3;
 
 #[derive(Debug, Clone, Copy)]
 pub(crate) enum Symbol {
@@ -15,7 +17,7 @@ pub(crate) enum Symbol {
 }
 
 impl Display for Symbol {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Symbol::Literal(c) => write!(f, "{:02X}", c),
             Symbol::Pointer { length, distance } => write!(f, "({},{})", *length as u16 + 3, distance)
@@ -23,12 +25,6 @@ impl Display for Symbol {
     }
 }
 
-pub(crate) enum Lz77Status {
-    Normal(Vec<Symbol>),
-    LastBlock(Vec<Symbol>)
-}
-
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -37,7 +33,7 @@ mod tests {
     #[test]
     fn test_symbol_display() {
         assert_eq!(format!("{}", Symbol::Literal(10)), "0A");
-        assert_eq!(format!("{}", Symbol::Pointer { length: 3, distance: 6 }), "(3,6)");
+        assert_eq!(format!("{}", Symbol::Pointer { length: 0, distance: 6 }), "(3,6)");
         // assert_eq!(format!("{}", vec![Symbol::Literal(10),Symbol::Pointer { length: 3, distance: 6 },Symbol::EndOfBlock]), "(255,32768)");
     }
 
@@ -1,76 +1,528 @@
 mod lz77;
 mod huffman;
+mod bitstream;
+mod inflater;
+mod output;
 
-use core::panic;
-use std::error::Error;
-use std::io::BufReader;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::mem;
+use core::time::Duration;
+use lz77::{WINDOW_SIZE, LOOKAHEAD_SIZE};
+use lz77::encode::MatchFinder;
+use huffman::encode::{fixed_huffman_encode_block, HuffmanBlock};
+use huffman::decode::huffman_decode_block;
+use bitstream::{BitReader, BitWriter, ByteSink};
+use output::{LimitedVec, Output, SliceOutput};
+use crate::error::{Error, Result};
+use crate::options::Strategy;
+use crate::stats::{BlockKind, BlockStats, CompressionStats, Stopwatch};
 
-use bitstream_io::{BitWriter, BitWrite, LittleEndian};
-
-use lz77::{Lz77Status, WINDOW_SIZE, LOOKAHEAD_SIZE};
-use lz77::encode::lz77_encode_block;
-use huffman::encode::huffman_encode_block;
-use crate::circular_buf::CircularBuf;
+pub(crate) use inflater::Inflater;
+pub(crate) use lz77::Symbol;
+pub(crate) use huffman::encode::match_codes;
 
 const BLKSIZE: usize = 65535;
 
-pub fn deflate(src: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+/// The most input a block covers. Each block is written as a stored block when that is smaller,
+/// so the output is never more than 5 bytes per block larger than the input (see `compress_bound`).
+/// A block with its window fits in the buffer of `Deflater`, even when its last match runs past the limit.
+pub(crate) const MAX_BLOCK_SPAN: usize = WINDOW_SIZE - LOOKAHEAD_SIZE;
+
+/// The largest stored block, LEN is 16 bits
+const MAX_STORED: usize = 65535;
+
+/// The input `Deflater` holds back until more comes: a full match, and one more byte,
+/// so that every position of a match ending there has the 3 bytes its hash needs, as in `deflate`
+const HOLD_BACK: usize = LOOKAHEAD_SIZE + 1;
+
+/// The size of the sliding buffer of `Deflater`: two windows, plus the held back input so that a full window
+/// of history is still there after the older window is dropped.
+const BUF_SIZE: usize = 2 * WINDOW_SIZE + HOLD_BACK;
+
+/// The range of windowBits, the base-2 logarithm of the window size: 256 bytes to 32 KiB
+pub(crate) const MIN_WINDOW_BITS: u8 = 8;
+pub(crate) const MAX_WINDOW_BITS: u8 = 15;
+/// The range of the memory level, which sizes the hash table of the match finder as in zlib
+pub(crate) const MIN_MEM_LEVEL: u8 = 1;
+pub(crate) const MAX_MEM_LEVEL: u8 = 9;
+/// The compression levels, as in zlib: 0 stores the data, 9 compresses best
+pub(crate) const MAX_LEVEL: u8 = 9;
+
+/// The settings of the compressor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Params {
+    /// How hard the match finder looks for long matches. Level 0 writes stored blocks.
+    pub level: u8,
+    /// No back reference reaches further than `1 << window_bits` bytes, so a decoder only needs that much history
+    pub window_bits: u8,
+    /// The hash table of the match finder has `1 << (mem_level + 7)` entries
+    pub mem_level: u8,
+    pub strategy: Strategy,
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Params { level: 6, window_bits: MAX_WINDOW_BITS, mem_level: 8, strategy: Strategy::Default }
+    }
+}
+
+impl Params {
+    pub fn window_size(&self) -> usize {
+        1 << self.window_bits
+    }
+}
+
+/// Write a block of symbols with the codes the strategy asks for, or as a stored block if that is smaller.
+/// `raw` is the input the symbols encode, at most `MAX_BLOCK_SPAN` bytes. Level 0 always stores it.
+/// Return how the block was written, and its size in bits.
+fn encode_block<S: ByteSink>(writer: &mut BitWriter<S>, symbols: &[Symbol], raw: &[u8], bfinal: bool, params: &Params) -> (BlockKind, u64) {
+    debug_assert!(raw.len() < MAX_BLOCK_SPAN + LOOKAHEAD_SIZE);
+    // the header and the padding to a byte boundary, LEN and NLEN, then the data
+    let header_bits = (writer.unaligned_bits() + 3).next_multiple_of(8) - writer.unaligned_bits();
+    let stored_bits = header_bits as u64 + 32 + 8 * raw.len() as u64;
+    if params.level == 0 {
+        write_stored_block(writer, raw, bfinal);
+        return (BlockKind::Stored, stored_bits);
+    }
+
+    let huffman = HuffmanBlock::new(symbols, params.strategy == Strategy::Fixed);
+    if stored_bits < huffman.bits() {
+        write_stored_block(writer, raw, bfinal);
+        (BlockKind::Stored, stored_bits)
+    } else {
+        huffman.write(writer, symbols, bfinal);
+        (if huffman.is_dynamic() { BlockKind::Dynamic } else { BlockKind::Fixed }, huffman.bits())
+    }
+}
+
+/// Compress the data into a raw deflate stream. The matcher works on `src` directly.
+pub fn deflate(src: &[u8]) -> Result<Vec<u8>> {
+    deflate_with(src, &Params::default())
+}
+
+/// Same as `deflate`, with the given settings
+pub(crate) fn deflate_with(src: &[u8], params: &Params) -> Result<Vec<u8>> {
+    deflate_chunk(src, 0, src.len(), params, None)
+}
+
+/// Same as `deflate_with`, recording the blocks in `stats` if it is given,
+/// and calling `on_block` after each block with the number of bytes of `src` read and of output written so far.
+/// An error from `on_block` stops the compression.
+pub(crate) fn deflate_with_progress<F>(src: &[u8], params: &Params, stats: Option<&mut CompressionStats>, mut on_block: F) -> Result<Vec<u8>>
+where F: FnMut(usize, usize) -> Result<()> {
+    let mut writer = BitWriter::new();
+    deflate_blocks(&mut writer, src, 0, src.len(), params, stats, &mut on_block)?;
+    Ok(writer.finish())
+}
+
+/// The rolling hash of `deflate_rsyncable` covers the last 12 bytes or so, and hits a boundary once every 4 KiB on average
+const RSYNC_BITS: u32 = 12;
+const RSYNC_MASK: u32 = (1 << RSYNC_BITS) - 1;
+const RSYNC_HIT: u32 = RSYNC_MASK >> 1;
+
+/// Compress the data with a full flush at content-defined boundaries, so that the output resynchronizes
+/// a few KiB after a local change in the input and rsync only has to send the compressed data around it.
+/// The boundaries are where a rolling hash of the last few bytes hits a fixed value, as in pigz.
+/// `stats` and `on_block` are as for `deflate_with_progress`, with `on_block` called at each boundary.
+pub(crate) fn deflate_rsyncable<F>(src: &[u8], params: &Params, stats: Option<&mut CompressionStats>, mut on_block: F) -> Result<Vec<u8>>
+where F: FnMut(usize, usize) -> Result<()> {
+    let mut deflater = Deflater::with_params(params);
+    if stats.is_some() {
+        deflater.collect_stats();
+    }
     let mut dst = Vec::new();
-    if src.is_empty() {
-        return Ok(dst);
+    let mut hash = 0;
+    let mut start = 0;
+
+    for (i, &byte) in src.iter().enumerate() {
+        hash = ((hash << 1) ^ byte as u32) & RSYNC_MASK;
+        if hash == RSYNC_HIT {
+            deflater.write(&src[start..=i])?;
+            deflater.flush(Flush::Full)?;
+            dst.append(&mut deflater.take_output());
+            start = i + 1;
+            on_block(start, dst.len())?;
+        }
     }
 
-    let mut writer = BitWriter::endian(&mut dst, LittleEndian);
-    let mut reader = BufReader::new(src);
+    deflater.write(&src[start..])?;
+    dst.append(&mut deflater.finish()?);
+    if let (Some(stats), Some(collected)) = (stats, deflater.take_stats()) {
+        stats.append(collected);
+    }
+    Ok(dst)
+}
 
-    let mut blocks = Vec::new();
-    let mut search = CircularBuf::with_capacity(WINDOW_SIZE);
-    let mut lookahead = CircularBuf::with_capacity(LOOKAHEAD_SIZE);
+/// The largest raw deflate stream `deflate_with` makes from `len` bytes: every block is at most 5 bytes larger than its input
+pub(crate) fn deflate_bound(len: usize) -> usize {
+    len.saturating_add(5 * len.div_ceil(MAX_BLOCK_SPAN).max(1))
+}
 
+/// Compress the data into a raw deflate stream in `dst`, and return its length.
+/// Fail with `BufferTooSmall` if it does not fit, which cannot happen when `dst` holds `deflate_bound(src.len())` bytes.
+/// The blocks are written straight into `dst`, and compression stops after the first block that does not fit.
+pub(crate) fn deflate_into(src: &[u8], dst: &mut [u8], params: &Params) -> Result<usize> {
+    let capacity = dst.len();
+    let mut writer = BitWriter::to_slice(dst);
+    deflate_blocks(&mut writer, src, 0, src.len(), params, None, &mut |_, written| {
+        if written > capacity {
+            return Err(Error::BufferTooSmall);
+        }
+        Ok(())
+    })?;
+    writer.finish_slice()
+}
+
+/// Compress `src[start..end]`, with the window before `start` as a preset dictionary.
+///
+/// Unless the chunk reaches the end of `src`, the stream is left open with a sync flush, which ends it at a byte boundary.
+/// The compressed chunks of consecutive ranges can then be joined into a single stream.
+/// The blocks are recorded in `stats` if it is given.
+pub(crate) fn deflate_chunk(src: &[u8], start: usize, end: usize, params: &Params, stats: Option<&mut CompressionStats>) -> Result<Vec<u8>> {
+    let mut writer = BitWriter::new();
+    deflate_blocks(&mut writer, src, start, end, params, stats, &mut |_, _| Ok(()))?;
+    if end != src.len() {
+        sync_flush(&mut writer);
+    }
+
+    // write the last byte
+    Ok(writer.finish())
+}
+
+/// Write the blocks of `src[start..end]` to `writer`, with the window before `start` as a preset dictionary.
+/// The last block is final if the range reaches the end of `src`.
+/// The blocks are recorded in `stats` if it is given, and `on_block` is called after each block
+/// with the number of bytes of the range read and of output written so far.
+fn deflate_blocks<S: ByteSink, F>(writer: &mut BitWriter<S>, src: &[u8], start: usize, end: usize, params: &Params,
+                     mut stats: Option<&mut CompressionStats>, on_block: &mut F) -> Result<()>
+where F: FnMut(usize, usize) -> Result<()> {
+    let dict_start = start.saturating_sub(params.window_size());
+    let data = &src[dict_start..end];
+    let last = end == src.len();
+
+    let mut finder = MatchFinder::new(params);
+    let mut symbols = Vec::with_capacity(BLKSIZE);
+    let mut pos = start - dict_start;
+    finder.prime(data, pos);
 
     loop {
-        match lz77_encode_block(&mut reader, &mut search, &mut lookahead, BLKSIZE)? {
-            Lz77Status::Normal(block) => {
-                blocks.push(huffman_encode_block(&block)?);
+        let block_start = pos;
+        let lz77 = Stopwatch::start();
+        pos = finder.encode(data, pos, data.len().min(block_start + MAX_BLOCK_SPAN), &mut symbols, BLKSIZE);
+        let lz77_time = lz77.elapsed();
+        // the last block has BFINAL set, even if it is empty
+        let done = pos >= data.len();
+        let huffman = Stopwatch::start();
+        let (kind, output_bits) = encode_block(writer, &symbols, &data[block_start..pos], done && last, params);
+        if let Some(stats) = stats.as_deref_mut() {
+            let block = BlockStats { kind, input_bytes: (pos - block_start) as u64, output_bits };
+            stats.add_block(block, &symbols, lz77_time, huffman.elapsed());
+        }
+        symbols.clear();
+        on_block(dict_start + pos.min(data.len()) - start, writer.byte_len())?;
+
+        if done {
+            return Ok(());
+        }
+    }
+}
+
+/// Store the data without compression, in stored blocks (See RFC 1951, Section 3.2.4)
+pub(crate) fn deflate_stored(src: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+    let mut chunks = src.chunks(MAX_STORED).peekable();
+
+    loop {
+        // an empty input still needs an (empty) final block
+        let chunk = chunks.next().unwrap_or_default();
+        let last = chunks.peek().is_none();
+        write_stored_block(&mut writer, chunk, last);
+
+        if last {
+            break;
+        }
+    }
+
+    writer.finish()
+}
+
+/// Write an empty stored block, which pads the stream to a byte boundary
+fn sync_flush(writer: &mut BitWriter) {
+    write_stored_block(writer, &[], false);
+}
+
+fn write_stored_block<S: ByteSink>(writer: &mut BitWriter<S>, data: &[u8], bfinal: bool) {
+    debug_assert!(data.len() <= MAX_STORED);
+    writer.write_bits(bfinal as u32, 3); // BTYPE = 00
+    writer.align_to_byte();
+    let len = data.len() as u16;
+    writer.write_bytes(&len.to_le_bytes());
+    writer.write_bytes(&(!len).to_le_bytes());
+    writer.write_bytes(data);
+}
+
+/// How `Deflater::flush` ends the output so far, from the weakest to the strongest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Flush {
+    /// Write an empty fixed huffman block after the pending data. The output is not padded to a byte boundary,
+    /// so the last few bits of the data may only come out with the next block.
+    Partial,
+    /// Write an empty stored block, which pads the output to a byte boundary, so that all the input so far can be decoded
+    Sync,
+    /// Same as `Sync`, and also forget the window, so that decoding can start from scratch at this point
+    Full,
+}
+
+/// A deflate compressor that takes its input in pieces.
+///
+/// The input goes through a sliding buffer of `BUF_SIZE` bytes, whatever the window size. When it is full, the older 32 KiB are dropped
+/// with a single copy instead of moving the bytes one by one. Without flushes, the output is the same as `deflate` on the whole input.
+pub(crate) struct Deflater {
+    buf: Vec<u8>,
+    /// The next position of `buf` to be encoded
+    pos: usize,
+    /// The position of `buf` where the pending block starts
+    block_start: usize,
+    /// Whether older input was dropped from the front of `buf`
+    slid: bool,
+    finder: MatchFinder,
+    symbols: Vec<Symbol>,
+    writer: BitWriter,
+    params: Params,
+    /// The flush that ended the output so far, if no input was written after it
+    last_flush: Option<Flush>,
+    /// The blocks written so far, once `collect_stats` is called, and the time spent finding the matches of the pending block
+    stats: Option<Box<CompressionStats>>,
+    lz77_time: Duration,
+}
+
+impl Deflater {
+    pub fn new() -> Self {
+        Deflater::with_params(&Params::default())
+    }
+
+    pub fn with_params(params: &Params) -> Self {
+        Deflater {
+            buf: Vec::with_capacity(BUF_SIZE),
+            pos: 0,
+            block_start: 0,
+            slid: false,
+            finder: MatchFinder::new(params),
+            symbols: Vec::with_capacity(BLKSIZE),
+            writer: BitWriter::new(),
+            params: *params,
+            last_flush: None,
+            stats: None,
+            lz77_time: Duration::ZERO,
+        }
+    }
+
+    pub fn write(&mut self, mut input: &[u8]) -> Result<()> {
+        if !input.is_empty() {
+            self.last_flush = None;
+        }
+        while !input.is_empty() {
+            if self.buf.len() == BUF_SIZE {
+                // everything up to the lookahead has been encoded, so the older window is no longer needed,
+                // and the pending block is short enough to start after it
+                debug_assert!(self.pos >= 2 * WINDOW_SIZE && self.block_start >= WINDOW_SIZE);
+                self.buf.copy_within(WINDOW_SIZE.., 0);
+                self.buf.truncate(BUF_SIZE - WINDOW_SIZE);
+                self.pos -= WINDOW_SIZE;
+                self.block_start -= WINDOW_SIZE;
+                self.slid = true;
+                self.finder.slide();
             }
-            Lz77Status::LastBlock(block) => {
-                if block.is_empty() {
-                    if blocks.is_empty() {
-                        panic!("lz77 output is empty"); // should not happen
-                    } else {
-                        // the last block should have BFINAL
-                        let len = blocks.len();
-                        set_bfinal(&mut blocks[len - 1]);
-                    }
-                } else {
-                    let mut last = huffman_encode_block(&block)?;
-                    set_bfinal(&mut last);
-                    blocks.push(last);
-                }
-                break;
+
+            let len = input.len().min(BUF_SIZE - self.buf.len());
+            self.buf.extend_from_slice(&input[..len]);
+            input = &input[len..];
+
+            // keep a full lookahead, more input may extend the matches
+            self.compress(self.buf.len().saturating_sub(HOLD_BACK));
+        }
+        Ok(())
+    }
+
+    /// Compress all the input so far into non-final blocks, and end the output as `mode` tells.
+    /// As in zlib, nothing is written if no input came since a flush at least as strong.
+    pub fn flush(&mut self, mode: Flush) -> Result<()> {
+        if self.last_flush.is_some_and(|last| mode <= last) {
+            return Ok(());
+        }
+        self.last_flush = Some(mode);
+        self.compress(self.buf.len());
+        if !self.symbols.is_empty() {
+            self.write_block(false);
+        }
+
+        match mode {
+            Flush::Partial => fixed_huffman_encode_block(&mut self.writer, &[], false),
+            Flush::Sync => sync_flush(&mut self.writer),
+            Flush::Full => {
+                sync_flush(&mut self.writer);
+                self.finder.forget_before(self.pos);
             }
-        };
+        }
+        Ok(())
     }
 
-    // write the blocks
-    for (data, (bits, value)) in blocks {
-        writer.write_bytes(&data)?;
-        writer.write(bits, value)?;
+    /// Record the blocks from now on, for `take_stats`
+    pub fn collect_stats(&mut self) {
+        self.stats.get_or_insert_with(Box::default);
     }
 
-    // write the last byte
-    writer.byte_align()?;
+    /// Take the statistics of the blocks written since `collect_stats`, and start over
+    pub fn take_stats(&mut self) -> Option<CompressionStats> {
+        self.stats.as_deref_mut().map(mem::take)
+    }
 
-    Ok(dst)
+    /// Take the compressed data written so far, up to the last whole byte
+    pub fn take_output(&mut self) -> Vec<u8> {
+        self.writer.take_bytes()
+    }
+
+    /// Move the compressed data written so far, up to the last whole byte, to the end of `dst`
+    pub fn move_output_to(&mut self, dst: &mut Vec<u8>) {
+        self.writer.move_bytes_to(dst);
+    }
+
+    /// Compress the rest of the input and end the stream. Return the compressed data not taken yet.
+    /// Nothing more may be written until `reset`.
+    pub fn finish(&mut self) -> Result<Vec<u8>> {
+        self.end();
+        Ok(self.writer.take_bytes())
+    }
+
+    /// Same as `finish`, moving the compressed data to the end of `dst` so that the buffer of the writer is kept
+    pub fn finish_to(&mut self, dst: &mut Vec<u8>) -> Result<()> {
+        self.end();
+        self.move_output_to(dst);
+        Ok(())
+    }
+
+    fn end(&mut self) {
+        self.compress(self.buf.len());
+        self.write_block(true);
+        self.writer.align_to_byte();
+    }
+
+    /// Start a new stream with the same settings, keeping the buffers and tables.
+    /// The compressed data not taken yet is dropped.
+    pub fn reset(&mut self) {
+        self.finder.reset((!self.slid).then_some(&self.buf[..]));
+        self.buf.clear();
+        self.pos = 0;
+        self.block_start = 0;
+        self.slid = false;
+        self.symbols.clear();
+        self.writer.reset();
+        self.last_flush = None;
+        self.lz77_time = Duration::ZERO;
+    }
+
+    /// Encode the buffer up to `end`, writing out every block that fills up
+    fn compress(&mut self, end: usize) {
+        while self.pos < end {
+            let block_end = self.block_start + MAX_BLOCK_SPAN;
+            let lz77 = self.stats.is_some().then(Stopwatch::start);
+            self.pos = self.finder.encode(&self.buf, self.pos, end.min(block_end), &mut self.symbols, BLKSIZE);
+            self.lz77_time += lz77.map_or(Duration::ZERO, |lz77| lz77.elapsed());
+            if self.symbols.len() == BLKSIZE || self.pos >= block_end {
+                self.write_block(false);
+            }
+        }
+    }
 
+    /// Write the pending symbols as a block, and start the next block
+    fn write_block(&mut self, bfinal: bool) {
+        let huffman = Stopwatch::start();
+        let (kind, output_bits) = encode_block(&mut self.writer, &self.symbols, &self.buf[self.block_start..self.pos], bfinal, &self.params);
+        if let Some(stats) = &mut self.stats {
+            let block = BlockStats { kind, input_bytes: (self.pos - self.block_start) as u64, output_bits };
+            stats.add_block(block, &self.symbols, mem::take(&mut self.lz77_time), huffman.elapsed());
+        }
+        self.symbols.clear();
+        self.block_start = self.pos;
+    }
 }
 
-fn set_bfinal(block: &mut (Vec<u8>, (u32, u8))) {
-    if block.0.is_empty() {
-        block.1.1 |= 1;
-    } else {
-        block.0[0] |= 0x01;
+/// Decompress a raw deflate stream.
+/// Return the decompressed data and the number of bytes of `src` occupied by the stream.
+pub fn inflate(src: &[u8]) -> Result<(Vec<u8>, usize)> {
+    inflate_limited(src, usize::MAX, |_, _| Ok(()))
+}
+
+/// Same as `inflate`, but fail with `LimitExceeded(Limit::OutputSize)` as soon as the output would exceed `max_len` bytes.
+/// `on_block` is called after each block with the number of bytes of `src` read and of output written so far,
+/// and an error from it stops the decompression.
+pub(crate) fn inflate_limited<F>(src: &[u8], max_len: usize, on_block: F) -> Result<(Vec<u8>, usize)>
+where F: FnMut(usize, usize) -> Result<()> {
+    let mut dst = Vec::new();
+    let len = inflate_stream(src, &mut LimitedVec { vec: &mut dst, max_len }, on_block)?;
+    Ok((dst, len))
+}
+
+/// Decompress a raw deflate stream directly into `dst`, which also holds the history, so nothing is buffered.
+/// Return the length of the decompressed data and the number of bytes of `src` occupied by the stream,
+/// or fail with `BufferTooSmall` if the decompressed data does not fit in `dst`.
+pub(crate) fn inflate_into(src: &[u8], dst: &mut [u8]) -> Result<(usize, usize)> {
+    let mut output = SliceOutput::new(dst);
+    let len = inflate_stream(src, &mut output, |_, _| Ok(()))?;
+    Ok((output.len(), len))
+}
+
+/// Decompress the blocks of a raw deflate stream into `dst`, and return the number of bytes of `src` occupied by the stream
+fn inflate_stream<O: Output, F>(src: &[u8], dst: &mut O, mut on_block: F) -> Result<usize>
+where F: FnMut(usize, usize) -> Result<()> {
+    let mut reader = BitReader::new(src);
+
+    loop {
+        let bfinal = inflate_block(&mut reader, dst)?;
+        on_block((reader.bit_offset() / 8) as usize, dst.len())?;
+        if bfinal {
+            break;
+        }
     }
+
+    // the stream ends at a byte boundary
+    reader.align_to_byte();
+
+    Ok(reader.byte_offset())
+}
+
+/// Decompress one block, header included, and append the output to `dst`, which holds the history.
+/// Return whether it was the final block (BFINAL).
+fn inflate_block<O: Output>(reader: &mut BitReader, dst: &mut O) -> Result<bool> {
+    let start = reader.bit_offset();
+    let bfinal = reader.read_bits(1)? == 1;
+    let btype = reader.read_bits(2)?;
+
+    match btype {
+        0b00 => inflate_stored_block(reader, dst)?,
+        0b01 => huffman_decode_block(reader, dst, false)?,
+        0b10 => huffman_decode_block(reader, dst, true)?,
+        _ => return Err(Error::InvalidBlockType { bit_offset: start })
+    }
+
+    Ok(bfinal)
+}
+
+/// Copy a stored block (BTYPE = 00) to `dst`. The block header has already been consumed.
+fn inflate_stored_block<O: Output>(reader: &mut BitReader, dst: &mut O) -> Result<()> {
+    reader.align_to_byte();
+    let start = reader.bit_offset();
+    let len = reader.read_bits(16)?;
+    let nlen = reader.read_bits(16)?;
+    if len != !nlen & 0xffff {
+        // the length does not match its complement
+        return Err(Error::InvalidBlockType { bit_offset: start });
+    }
+
+    reader.align_to_byte();
+    let data = reader.read_bytes(len as usize)?;
+    dst.extend_from_slice(data)
 }
 
 #[cfg(test)]
@@ -91,6 +543,266 @@ mod tests {
         let dst: Vec<u8> = deflate(raw.as_bytes()).unwrap();
         println!("{:?}", dst);
     }
-}
 
+    #[test]
+    fn test_inflate() {
+        let raw = "Hello, world!\nHello, Rust!\nRust is the best language!\n";
+        let dst = deflate(raw.as_bytes()).unwrap();
+        let (decoded, consumed) = inflate(&dst).unwrap();
+
+        assert_eq!(decoded, raw.as_bytes());
+        assert_eq!(consumed, dst.len());
+    }
+
+    #[test]
+    fn test_inflate_into() {
+        let raw: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8 ^ (i / 1000) as u8).collect();
+        let src = deflate(&raw).unwrap();
+        let mut dst = vec![0; raw.len()];
+        assert_eq!(inflate_into(&src, &mut dst).unwrap(), (raw.len(), src.len()));
+        assert_eq!(dst, raw);
+
+        let mut dst = vec![0; raw.len() - 1];
+        assert!(matches!(inflate_into(&src, &mut dst), Err(Error::BufferTooSmall)));
+        // a stored block that does not fit
+        assert!(matches!(inflate_into(&[0x01, 0x03, 0x00, 0xfc, 0xff, b'a', b'b', b'c'], &mut [0; 2]), Err(Error::BufferTooSmall)));
+        assert!(matches!(inflate_into(&[0x4b, 0x04, 0x42, 0x00], &mut [0; 8]), Err(Error::DistanceTooFar { distance: 2, .. })));
+    }
+
+    #[test]
+    fn test_inflate_stored() {
+        // a single stored block holding "abc", followed by trailing data
+        let src = [0x01, 0x03, 0x00, 0xfc, 0xff, b'a', b'b', b'c', 0xaa];
+        assert_eq!(inflate(&src).unwrap(), (Vec::from("abc"), 8));
+    }
+
+    #[test]
+    fn test_inflate_errors() {
+        // BTYPE = 11
+        assert!(matches!(inflate(&[0x07]), Err(Error::InvalidBlockType { bit_offset: 0 })));
+        // stored block cut short
+        assert!(matches!(inflate(&[0x01, 0x03, 0x00, 0xfc, 0xff, b'a']), Err(Error::TruncatedInput { .. })));
+        let dst = deflate(b"aaaa").unwrap();
+        assert!(matches!(inflate(&dst[..1]), Err(Error::TruncatedInput { .. })));
+        // fixed block with one literal followed by a back reference of length 3, distance 2
+        assert!(matches!(inflate(&[0x4b, 0x04, 0x42, 0x00]), Err(Error::DistanceTooFar { distance: 2, bit_offset: 11 })));
+    }
+
+    #[test]
+    fn test_deflater() {
+        // long enough to slide the buffer a few times
+        let mut raw = Vec::new();
+        for i in 0..20000u32 {
+            raw.extend_from_slice(format!("line {} {}\n", i % 1000, i * 7 % 13).as_bytes());
+        }
+
+        let mut deflater = Deflater::new();
+        for chunk in raw.chunks(1000) {
+            deflater.write(chunk).unwrap();
+        }
+        let dst = deflater.finish().unwrap();
+
+        assert_eq!(dst, deflate(&raw).unwrap());
+        assert_eq!(inflate(&dst).unwrap(), (raw, dst.len()));
+    }
+
+    #[test]
+    fn test_deflater_reset() {
+        let small: Vec<Vec<u8>> = (0..50).map(|i| format!("{{\"id\":{},\"name\":\"user{}\",\"ok\":true}}", i, i % 7).into_bytes()).collect();
+        let large: Vec<u8> = (0..200_000u32).flat_map(|i| format!("{} ", i % 4321).into_bytes()).collect();
+
+        let mut deflater = Deflater::new();
+        let mut buffers = None;
+        // a large input slides the buffer, so the next reset clears the whole hash table
+        for raw in small.iter().chain([&large]).chain(&small) {
+            deflater.write(raw).unwrap();
+            let mut dst = Vec::new();
+            deflater.finish_to(&mut dst).unwrap();
+            assert_eq!(dst, deflate(raw).unwrap());
+            deflater.reset();
+
+            // the buffers grow for the large input, but not for the small ones after it
+            let current = (deflater.buf.as_ptr(), deflater.buf.capacity(), deflater.symbols.as_ptr());
+            if raw.len() == large.len() {
+                buffers = Some(current);
+            }
+            assert_eq!(*buffers.get_or_insert(current), current);
+        }
+    }
+
+    #[test]
+    fn test_deflate_rsyncable() {
+        let mut state = 1u32;
+        let raw: Vec<u8> = (0..300_000).map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            b"abcdefgh\n "[(state >> 16) as usize % 10]
+        }).collect();
+        let dst = deflate_rsyncable(&raw, &Params::default(), None, |_, _| Ok(())).unwrap();
+        assert_eq!(inflate(&dst).unwrap(), (raw.clone(), dst.len()));
+
+        // after a change near the start, the output is the same again from the next boundary on
+        let mut changed = raw.clone();
+        changed[1000] ^= 1;
+        let changed_dst = deflate_rsyncable(&changed, &Params::default(), None, |_, _| Ok(())).unwrap();
+        let common = dst.iter().rev().zip(changed_dst.iter().rev()).take_while(|(a, b)| a == b).count();
+        assert!(common > dst.len() * 9 / 10, "{} of {} bytes in common", common, dst.len());
+    }
+
+    #[test]
+    fn test_deflater_flush() {
+        let first = "Hello, world!\nHello, Rust!\n".repeat(100);
+        let second = "Rust is the best language!\nHello, Rust!\n".repeat(100);
+
+        for mode in [Flush::Partial, Flush::Sync, Flush::Full] {
+            let mut deflater = Deflater::new();
+            deflater.write(first.as_bytes()).unwrap();
+            deflater.flush(mode).unwrap();
+            let flushed = deflater.take_output();
+            deflater.write(second.as_bytes()).unwrap();
+            let dst = [flushed.clone(), deflater.finish().unwrap()].concat();
+
+            assert_eq!(inflate(&dst).unwrap().0, [first.as_bytes(), second.as_bytes()].concat());
+
+            if mode != Flush::Partial {
+                // all the input so far can be decoded from the flushed output
+                assert_eq!(flushed[flushed.len() - 4..], [0x00, 0x00, 0xff, 0xff]);
+                let mut out = vec![0; first.len() + 1];
+                let (_, written) = Inflater::new().inflate(&flushed, &mut out, false).unwrap();
+                assert_eq!(out[..written], *first.as_bytes());
+            }
+            if mode == Flush::Full {
+                // the rest does not refer back before the flush
+                assert_eq!(inflate(&dst[flushed.len()..]).unwrap().0, second.as_bytes());
+            }
+        }
+    }
+
+    #[test]
+    fn test_deflate_chunk() {
+        let raw = "Hello, world!\nHello, Rust!\nRust is the best language!\n".repeat(10);
+        let raw = raw.as_bytes();
 
+        let first = deflate_chunk(raw, 0, 100, &Params::default(), None).unwrap();
+        // the sync flush marker
+        assert_eq!(first[first.len() - 4..], [0x00, 0x00, 0xff, 0xff]);
+        let second = deflate_chunk(raw, 100, raw.len(), &Params::default(), None).unwrap();
+        // the dictionary lets the second chunk refer back into the first one
+        assert!(second.len() < deflate(&raw[100..]).unwrap().len());
+
+        let joined = [first, second].concat();
+        assert_eq!(inflate(&joined).unwrap(), (raw.to_vec(), joined.len()));
+    }
+
+    #[test]
+    fn test_deflate_stored() {
+        assert_eq!(deflate_stored(b"abc"), [0x01, 0x03, 0x00, 0xfc, 0xff, b'a', b'b', b'c']);
+        assert_eq!(deflate_stored(&[]), [0x01, 0x00, 0x00, 0xff, 0xff]);
+
+        let raw: Vec<u8> = (0..150_000u32).map(|i| (i * 7) as u8).collect();
+        let dst = deflate_stored(&raw);
+        assert_eq!(dst.len(), raw.len() + 3 * 5);
+        assert_eq!(inflate(&dst).unwrap(), (raw, dst.len()));
+    }
+
+    #[test]
+    fn test_deflate_empty() {
+        let dst = deflate(&[]).unwrap();
+        assert_eq!(inflate(&dst).unwrap(), (Vec::new(), dst.len()));
+    }
+
+    #[test]
+    fn test_window_bits() {
+        let raw = "Hello, world!\nHello, Rust!\nRust is the best language!\n".repeat(10);
+        let mut text = raw.clone().into_bytes();
+        let mut state = 1u32;
+        text.extend((0..1000).map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 16) as u8
+        }));
+        text.extend_from_slice(raw.as_bytes());
+
+        for window_bits in MIN_WINDOW_BITS..=MAX_WINDOW_BITS {
+            let params = Params { window_bits, mem_level: 1, ..Default::default() };
+            let dst = deflate_with(&text, &params).unwrap();
+            assert_eq!(inflate(&dst).unwrap(), (text.clone(), dst.len()));
+
+            let mut deflater = Deflater::with_params(&params);
+            deflater.write(&text).unwrap();
+            assert_eq!(deflater.finish().unwrap(), dst);
+        }
+        // the repeat is out of reach of the smallest window
+        let small = deflate_with(&text, &Params { window_bits: MIN_WINDOW_BITS, mem_level: 1, ..Default::default() }).unwrap();
+        assert!(small.len() > deflate(&text).unwrap().len());
+    }
+
+    #[test]
+    fn test_strategies() {
+        let raw = "Hello, world!\nHello, Rust!\nRust is the best language!\n".repeat(100);
+        let default = deflate(raw.as_bytes()).unwrap();
+
+        for strategy in [Strategy::Filtered, Strategy::HuffmanOnly, Strategy::Rle, Strategy::Fixed] {
+            let params = Params { strategy, ..Default::default() };
+            let dst = deflate_with(raw.as_bytes(), &params).unwrap();
+            assert_eq!(inflate(&dst).unwrap(), (raw.as_bytes().to_vec(), dst.len()), "{:?}", strategy);
+        }
+
+        // a single final fixed block
+        let dst = deflate_with(raw.as_bytes(), &Params { strategy: Strategy::Fixed, ..Default::default() }).unwrap();
+        assert_eq!(dst[0] & 0b111, 0b011);
+        // a single final dynamic block
+        let dst = deflate_with(raw.as_bytes(), &Params { strategy: Strategy::HuffmanOnly, ..Default::default() }).unwrap();
+        assert_eq!(dst[0] & 0b111, 0b101);
+        assert!(dst.len() < raw.len() && dst.len() > default.len());
+    }
+
+    #[test]
+    fn test_levels() {
+        let raw: Vec<u8> = (0..100_000u32).flat_map(|i| format!("{} ", i * 7 % 1234).into_bytes()).collect();
+        let sizes: Vec<usize> = (0..=MAX_LEVEL).map(|level| {
+            let params = Params { level, ..Default::default() };
+            let dst = deflate_with(&raw, &params).unwrap();
+            assert_eq!(inflate(&dst).unwrap(), (raw.clone(), dst.len()), "level {}", level);
+
+            let mut deflater = Deflater::with_params(&params);
+            for chunk in raw.chunks(10_000) {
+                deflater.write(chunk).unwrap();
+            }
+            assert_eq!(deflater.finish().unwrap(), dst, "level {}", level);
+            dst.len()
+        }).collect();
+
+        // stored blocks of MAX_BLOCK_SPAN bytes
+        assert_eq!(sizes[0], raw.len() + raw.len().div_ceil(MAX_BLOCK_SPAN) * 5);
+        assert!(sizes[1] < sizes[0] && sizes[9] < sizes[1], "{:?}", sizes);
+        assert_eq!(sizes[6], deflate(&raw).unwrap().len());
+    }
+
+    #[test]
+    fn test_stored_fallback() {
+        // incompressible data, with a compressible run in the middle
+        let mut raw: Vec<u8> = (0..300_000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8).collect();
+        raw[100_000..140_000].fill(b'a');
+
+        for strategy in [Strategy::Default, Strategy::Fixed, Strategy::HuffmanOnly] {
+            let params = Params { strategy, ..Default::default() };
+            let dst = deflate_with(&raw, &params).unwrap();
+            assert!(dst.len() <= deflate_bound(raw.len()), "{:?}", strategy);
+            assert!(dst.len() < raw.len(), "{:?}", strategy);
+            assert_eq!(inflate(&dst).unwrap().0, raw);
+
+            let mut deflater = Deflater::with_params(&params);
+            for chunk in raw.chunks(777) {
+                deflater.write(chunk).unwrap();
+            }
+            assert_eq!(deflater.finish().unwrap(), dst, "{:?}", strategy);
+        }
+
+        let mut dst = vec![0; deflate_bound(raw.len())];
+        let len = deflate_into(&raw, &mut dst, &Params::default()).unwrap();
+        assert_eq!(dst[..len], deflate(&raw).unwrap());
+        assert!(matches!(deflate_into(&raw, &mut dst[..len - 1], &Params::default()), Err(Error::BufferTooSmall)));
+        assert!(matches!(deflate_into(&raw, &mut [0; 10], &Params::default()), Err(Error::BufferTooSmall)));
+        assert_eq!(deflate_bound(0), 5);
+        assert_eq!(deflate(&[]).unwrap().len(), 2);
+    }
+}
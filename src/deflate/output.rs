@@ -0,0 +1,139 @@
+use alloc::vec::Vec;
+use crate::error::{Error, Limit, Result};
+
+/// Where the decoder writes the decompressed data.
+/// The data written so far is also the history that back references copy from.
+pub(crate) trait Output {
+    /// The number of bytes written so far
+    fn len(&self) -> usize;
+
+    fn push(&mut self, byte: u8) -> Result<()>;
+
+    fn extend_from_slice(&mut self, data: &[u8]) -> Result<()>;
+
+    /// Append `length` bytes copied from `distance` bytes back. `distance` is between 1 and `len()`.
+    fn copy_match(&mut self, distance: usize, length: usize) -> Result<()>;
+}
+
+/// A growing output that fails with `LimitExceeded(Limit::OutputSize)` instead of growing past `max_len` bytes
+pub(crate) struct LimitedVec<'a> {
+    pub vec: &'a mut Vec<u8>,
+    pub max_len: usize,
+}
+
+impl Output for LimitedVec<'_> {
+    fn len(&self) -> usize {
+        self.vec.len()
+    }
+
+    fn push(&mut self, byte: u8) -> Result<()> {
+        if self.vec.len() == self.max_len {
+            return Err(Error::LimitExceeded(Limit::OutputSize));
+        }
+        self.vec.push(byte);
+        Ok(())
+    }
+
+    fn extend_from_slice(&mut self, data: &[u8]) -> Result<()> {
+        if self.vec.len() + data.len() > self.max_len {
+            return Err(Error::LimitExceeded(Limit::OutputSize));
+        }
+        self.vec.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn copy_match(&mut self, distance: usize, length: usize) -> Result<()> {
+        if self.vec.len() + length > self.max_len {
+            return Err(Error::LimitExceeded(Limit::OutputSize));
+        }
+        let from = self.vec.len() - distance;
+        if distance >= length {
+            self.vec.extend_from_within(from..from + length);
+        } else {
+            // The source and the destination overlap, so copy byte by byte
+            for i in 0..length {
+                self.vec.push(self.vec[from + i]);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A fixed buffer provided by the caller, filled from the start.
+/// Fails with `BufferTooSmall` when the data does not fit.
+pub(crate) struct SliceOutput<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> SliceOutput<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        SliceOutput { buf, len: 0 }
+    }
+}
+
+impl Output for SliceOutput<'_> {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn push(&mut self, byte: u8) -> Result<()> {
+        *self.buf.get_mut(self.len).ok_or(Error::BufferTooSmall)? = byte;
+        self.len += 1;
+        Ok(())
+    }
+
+    fn extend_from_slice(&mut self, data: &[u8]) -> Result<()> {
+        self.buf.get_mut(self.len..self.len + data.len()).ok_or(Error::BufferTooSmall)?.copy_from_slice(data);
+        self.len += data.len();
+        Ok(())
+    }
+
+    fn copy_match(&mut self, distance: usize, length: usize) -> Result<()> {
+        if self.len + length > self.buf.len() {
+            return Err(Error::BufferTooSmall);
+        }
+        let from = self.len - distance;
+        if distance >= length {
+            self.buf.copy_within(from..from + length, self.len);
+        } else {
+            // The source and the destination overlap, so copy byte by byte
+            for i in self.len..self.len + length {
+                self.buf[i] = self.buf[i - distance];
+            }
+        }
+        self.len += length;
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slice_output() {
+        let mut buf = [0; 8];
+        let mut output = SliceOutput::new(&mut buf);
+        output.extend_from_slice(b"ab").unwrap();
+        output.copy_match(2, 5).unwrap();
+        assert_eq!(output.len(), 7);
+        output.push(b'c').unwrap();
+        assert!(matches!(output.push(b'd'), Err(Error::BufferTooSmall)));
+        assert!(matches!(output.copy_match(1, 1), Err(Error::BufferTooSmall)));
+        assert!(matches!(output.extend_from_slice(b"d"), Err(Error::BufferTooSmall)));
+        assert_eq!(buf, *b"abababac");
+    }
+
+    #[test]
+    fn test_limited_vec() {
+        let mut vec = b"ab".to_vec();
+        let mut output = LimitedVec { vec: &mut vec, max_len: 6 };
+        output.copy_match(2, 3).unwrap();
+        assert!(matches!(output.copy_match(1, 2), Err(Error::LimitExceeded(Limit::OutputSize))));
+        output.push(b'c').unwrap();
+        assert!(matches!(output.extend_from_slice(b"d"), Err(Error::LimitExceeded(Limit::OutputSize))));
+        assert_eq!(vec, b"ababac");
+    }
+}
@@ -0,0 +1,138 @@
+use std::io::{self, Write};
+use crc32fast::Hasher;
+use crate::deflate::{Deflater, Flush};
+use crate::error::Result;
+use crate::member::{encode_header, encode_trailer, Header, OS_UNIX};
+use crate::options::CompressOptions;
+
+/// A gzip compressor that writes a single member to `inner` as the data comes in.
+///
+/// The header has no file name and a zero modification time. `finish` must be called to end the stream.
+/// `flush` does a sync flush: all the data written so far can be decoded from the output, without ending the stream.
+pub struct GzEncoder<W: Write> {
+    inner: W,
+    deflater: Deflater,
+    hasher: Hasher,
+    len: u64,
+    header_written: bool,
+}
+
+impl<W: Write> GzEncoder<W> {
+    pub fn new(inner: W) -> Self {
+        GzEncoder {
+            inner,
+            deflater: Deflater::new(),
+            hasher: Hasher::new(),
+            len: 0,
+            header_written: false,
+        }
+    }
+
+    /// Compress with the window size, memory level and strategy of `options`
+    pub fn with_options(inner: W, options: &CompressOptions) -> Result<Self> {
+        let mut encoder = GzEncoder::new(inner);
+        encoder.deflater = Deflater::with_params(&options.deflate_params()?);
+        Ok(encoder)
+    }
+
+    /// Same as `flush`, and also forget the history, so that decoding can start from scratch after this point
+    /// (given a decoder that resumes at a byte offset of the deflate stream).
+    pub fn full_flush(&mut self) -> io::Result<()> {
+        self.flush_with(Flush::Full)
+    }
+
+    /// Push out all the data written so far, except possibly its last few bits, without padding the output to a byte boundary
+    pub fn partial_flush(&mut self) -> io::Result<()> {
+        self.flush_with(Flush::Partial)
+    }
+
+    /// End the stream with the trailer, and return the inner writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_header()?;
+        let deflated = self.deflater.finish()?;
+        self.inner.write_all(&deflated)?;
+        self.inner.write_all(&encode_trailer(self.hasher.finalize(), self.len))?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn flush_with(&mut self, mode: Flush) -> io::Result<()> {
+        self.deflater.flush(mode)?;
+        self.write_output()?;
+        self.inner.flush()
+    }
+
+    /// Write the compressed data produced so far, after the header if it was not written yet
+    fn write_output(&mut self) -> io::Result<()> {
+        self.write_header()?;
+        let deflated = self.deflater.take_output();
+        self.inner.write_all(&deflated)
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if !self.header_written {
+            let mut header = Vec::new();
+            encode_header(&Header { mtime: 0, extra: None, name: None, comment: None, os: OS_UNIX }, &mut header);
+            self.inner.write_all(&header)?;
+            self.header_written = true;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for GzEncoder<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.deflater.write(data)?;
+        self.hasher.update(data);
+        self.len += data.len() as u64;
+        self.write_output()?;
+        Ok(data.len())
+    }
+
+    /// Sync flush: end the output so far with an empty stored block, so that all the data written so far can be decoded
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_with(Flush::Sync)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deflate::inflate;
+
+    /// The length of the header written by `GzEncoder`
+    const HEADER_SIZE: usize = 10;
+
+    #[test]
+    fn test_encoder_flush() {
+        let first = "Hello, world!\n".repeat(1000);
+        let second = "Hello, Rust!\n".repeat(1000);
+
+        let mut encoder = GzEncoder::new(Vec::new());
+        encoder.write_all(first.as_bytes()).unwrap();
+        encoder.flush().unwrap();
+        let flushed = encoder.inner.len();
+        assert_eq!(encoder.inner[flushed - 4..], [0x00, 0x00, 0xff, 0xff]);
+        // nothing new to flush
+        encoder.flush().unwrap();
+        encoder.partial_flush().unwrap();
+        assert_eq!(encoder.inner.len(), flushed);
+
+        encoder.write_all(second.as_bytes()).unwrap();
+        encoder.full_flush().unwrap();
+        let full_flushed = encoder.inner.len();
+
+        encoder.write_all(first.as_bytes()).unwrap();
+        encoder.partial_flush().unwrap();
+        let gz = encoder.finish().unwrap();
+
+        let raw = [first.as_bytes(), second.as_bytes(), first.as_bytes()].concat();
+        let (inflated, len) = inflate(&gz[HEADER_SIZE..]).unwrap();
+        assert_eq!(inflated, raw);
+        assert_eq!(gz[HEADER_SIZE + len..], [crc32fast::hash(&raw).to_le_bytes(), (raw.len() as u32).to_le_bytes()].concat());
+
+        // decoding restarts after the full flush
+        assert_eq!(inflate(&gz[full_flushed..]).unwrap().0, first.as_bytes());
+    }
+}
@@ -1,46 +1,195 @@
-use std::fmt;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::io;
 
+/// The error type of the library.
+///
+/// Errors found in the compressed data carry their position: `offset` is a byte offset in the input,
+/// `bit_offset` is a bit offset counted from the first byte of the input.
 #[derive(Debug)]
-pub struct DecodeError {
-    message: String
+#[non_exhaustive]
+pub enum Error {
+    /// An I/O error of the underlying reader or writer
+    #[cfg(feature = "std")]
+    Io(io::Error),
+    /// The input does not start with the gzip magic number 1f 8b
+    BadMagic { offset: u64 },
+    /// The zlib header has a bad check value, a window larger than 32 KiB, or asks for a preset dictionary, which is not supported
+    BadZlibHeader { offset: u64 },
+    /// The compression method in the header is not deflate
+    UnsupportedMethod { method: u8, offset: u64 },
+    /// The header CRC16 (FHCRC) does not match the header
+    HeaderCrc { offset: u64 },
+    /// The CRC32 in the trailer does not match the decompressed data
+    DataCrc { expected: u32, actual: u32, offset: u64 },
+    /// The Adler-32 in the zlib trailer does not match the decompressed data
+    DataAdler32 { expected: u32, actual: u32, offset: u64 },
+    /// The ISIZE in the trailer does not match the length of the decompressed data
+    LengthMismatch { expected: u32, actual: u32, offset: u64 },
+    /// BTYPE = 11, which is reserved, or a stored block whose LEN does not match NLEN
+    InvalidBlockType { bit_offset: u64 },
+    /// The code lengths do not form a valid huffman code, or a code that is not in the table was found
+    InvalidHuffmanTable { bit_offset: u64 },
+    /// A back reference points before the start of the output
+    DistanceTooFar { distance: usize, bit_offset: u64 },
+    /// The input ended in the middle of the stream
+    TruncatedInput { bit_offset: u64 },
+    /// A gzip member without the BC extra subfield (the block size) was found in a BGZF file
+    NotBgzf { offset: u64 },
+    /// A BGZF virtual offset that points past the end of its block
+    InvalidVirtualOffset { virtual_offset: u64 },
+    /// A random access index (see `GzIndex`) that cannot be read
+    InvalidIndex,
+    /// An option is out of its range, e.g. `CompressOptions::window_bits`
+    InvalidOption(&'static str),
+    /// Decoding was stopped because it would have gone past a limit of `DecompressOptions`
+    LimitExceeded(Limit),
+    /// The buffer given to a `*_into` function is too small for the decompressed data
+    BufferTooSmall,
+    /// The work was stopped with the `CancelToken` of the options
+    Cancelled,
 }
 
-#[derive(Debug)]
-pub struct EncodeError {
-    message: String
+/// The limits of `DecompressOptions`, to tell which one was exceeded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Limit {
+    /// The total size of the decompressed data
+    OutputSize,
+    /// The size of the decompressed data over the size of the compressed data
+    Ratio,
+    /// The number of gzip members
+    Members,
+    /// The length of the file name (FNAME) in a gzip header
+    NameLength,
+    /// The length of the comment (FCOMMENT) in a gzip header
+    CommentLength,
+    /// The length of the extra field (FEXTRA) in a gzip header
+    ExtraLength,
 }
 
-impl std::error::Error for DecodeError {}
-impl std::error::Error for EncodeError {}
-
-impl fmt::Display for DecodeError {
+impl fmt::Display for Limit {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "DecodeError: {}",
-            self.message
-        )
+        f.write_str(match self {
+            Limit::OutputSize => "decompressed size",
+            Limit::Ratio => "compression ratio",
+            Limit::Members => "number of members",
+            Limit::NameLength => "file name length",
+            Limit::CommentLength => "comment length",
+            Limit::ExtraLength => "extra field length",
+        })
+    }
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+impl Error {
+    /// Move the position of the error by `bytes`.
+    /// Used when the failed part of the input does not start at the beginning of the whole input.
+    pub(crate) fn offset_by(self, bytes: u64) -> Self {
+        match self {
+            Error::BadMagic { offset } => Error::BadMagic { offset: offset + bytes },
+            Error::BadZlibHeader { offset } => Error::BadZlibHeader { offset: offset + bytes },
+            Error::UnsupportedMethod { method, offset } => Error::UnsupportedMethod { method, offset: offset + bytes },
+            Error::HeaderCrc { offset } => Error::HeaderCrc { offset: offset + bytes },
+            Error::NotBgzf { offset } => Error::NotBgzf { offset: offset + bytes },
+            Error::DataCrc { expected, actual, offset } => Error::DataCrc { expected, actual, offset: offset + bytes },
+            Error::DataAdler32 { expected, actual, offset } => Error::DataAdler32 { expected, actual, offset: offset + bytes },
+            Error::LengthMismatch { expected, actual, offset } => Error::LengthMismatch { expected, actual, offset: offset + bytes },
+            Error::InvalidBlockType { bit_offset } => Error::InvalidBlockType { bit_offset: bit_offset + bytes * 8 },
+            Error::InvalidHuffmanTable { bit_offset } => Error::InvalidHuffmanTable { bit_offset: bit_offset + bytes * 8 },
+            Error::DistanceTooFar { distance, bit_offset } => Error::DistanceTooFar { distance, bit_offset: bit_offset + bytes * 8 },
+            Error::TruncatedInput { bit_offset } => Error::TruncatedInput { bit_offset: bit_offset + bytes * 8 },
+            err => err
+        }
+    }
+}
+
+impl core::error::Error for Error {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            #[cfg(feature = "std")]
+            Error::Io(err) => Some(err),
+            _ => None
+        }
     }
 }
 
-impl fmt::Display for EncodeError {
+impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "EncodeError: {}",
-            self.message
-        )
+        match self {
+            #[cfg(feature = "std")]
+            Error::Io(err) => write!(f, "I/O error: {}", err),
+            Error::BadMagic { offset } => write!(f, "not in gzip format (at byte {})", offset),
+            Error::BadZlibHeader { offset } => write!(f, "invalid zlib header (at byte {})", offset),
+            Error::UnsupportedMethod { method, offset } => write!(f, "unknown compression method {} (at byte {})", method, offset),
+            Error::HeaderCrc { offset } => write!(f, "header crc16 mismatch (at byte {})", offset),
+            Error::DataCrc { expected, actual, offset } =>
+                write!(f, "crc32 mismatch: expected {:08x}, got {:08x} (at byte {})", expected, actual, offset),
+            Error::DataAdler32 { expected, actual, offset } =>
+                write!(f, "adler32 mismatch: expected {:08x}, got {:08x} (at byte {})", expected, actual, offset),
+            Error::LengthMismatch { expected, actual, offset } =>
+                write!(f, "length mismatch: expected {}, got {} (at byte {})", expected, actual, offset),
+            Error::InvalidBlockType { bit_offset } => write!(f, "invalid block type (at bit {})", bit_offset),
+            Error::InvalidHuffmanTable { bit_offset } => write!(f, "invalid huffman code (at bit {})", bit_offset),
+            Error::DistanceTooFar { distance, bit_offset } => write!(f, "distance {} too far back (at bit {})", distance, bit_offset),
+            Error::TruncatedInput { bit_offset } => write!(f, "unexpected end of input (at bit {})", bit_offset),
+            Error::NotBgzf { offset } => write!(f, "not a BGZF block (at byte {})", offset),
+            Error::InvalidVirtualOffset { virtual_offset } => write!(f, "virtual offset {:#x} past the end of its block", virtual_offset),
+            Error::InvalidIndex => write!(f, "invalid gzip index"),
+            Error::InvalidOption(name) => write!(f, "invalid value for {}", name),
+            Error::LimitExceeded(limit) => write!(f, "{} limit exceeded", limit),
+            Error::BufferTooSmall => write!(f, "output buffer too small"),
+            Error::Cancelled => write!(f, "cancelled"),
+        }
     }
 }
 
-impl From<&str> for DecodeError {
-    fn from(msg: &str) -> Self {
-        DecodeError { message: String::from(msg) }
+/// I/O errors that carry an `Error` (see `From<Error> for io::Error`) are unwrapped, so the conversion round-trips.
+#[cfg(feature = "std")]
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        if err.get_ref().is_some_and(|inner| inner.is::<Error>()) {
+            *err.into_inner().unwrap().downcast::<Error>().unwrap()
+        } else {
+            Error::Io(err)
+        }
     }
 }
 
-impl From<&str> for EncodeError {
-    fn from(msg: &str) -> Self {
-        EncodeError { message: String::from(msg) }
+/// Lets `Read`/`Write` adapters report library errors. The original `Error` can be recovered with `Error::from`.
+#[cfg(feature = "std")]
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Io(err) => err,
+            Error::TruncatedInput { .. } => io::Error::new(io::ErrorKind::UnexpectedEof, err),
+            Error::LimitExceeded(_) | Error::BufferTooSmall | Error::Cancelled => io::Error::other(err),
+            Error::InvalidOption(_) => io::Error::new(io::ErrorKind::InvalidInput, err),
+            err => io::Error::new(io::ErrorKind::InvalidData, err)
+        }
+    }
+}
+
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_io_error_round_trip() {
+        let err: io::Error = Error::DistanceTooFar { distance: 10, bit_offset: 3 }.into();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(matches!(Error::from(err), Error::DistanceTooFar { distance: 10, bit_offset: 3 }));
+
+        let err: io::Error = Error::Io(io::Error::new(io::ErrorKind::NotFound, "missing")).into();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(matches!(Error::from(err), Error::Io(_)));
+    }
+
+    #[test]
+    fn test_offset_by() {
+        assert!(matches!(Error::TruncatedInput { bit_offset: 5 }.offset_by(2), Error::TruncatedInput { bit_offset: 21 }));
+        assert!(matches!(Error::BadMagic { offset: 5 }.offset_by(2), Error::BadMagic { offset: 7 }));
     }
 }
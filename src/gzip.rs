@@ -1,50 +1,136 @@
-use std::error::Error;
+use std::ffi::OsStr;
 use std::fs::File;
-use std::io::{Read, Write, BufWriter};
-use crate::deflate::deflate;
-use std::time::SystemTime;
+use std::io::{self, Read, Write};
+use crate::error::Result;
+use crate::member::{encode_header, encode_trailer, Header};
+use crate::metadata::copy_metadata;
+use crate::oneshot::{gunzip_members, gzip_member};
+use crate::options::{CompressOptions, DecompressOptions};
+use crate::stats::CompressionStats;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
 
-pub fn compress_to_gzip(src_path: &str, dst_path: &str) -> Result<(), Box<dyn Error>> {
+/// What could not be stored as is in the header of a compressed file. Compression does not fail because of it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HeaderWarnings {
+    /// The modification time of the source is before 1970 or after 2106, which MTIME cannot hold, so MTIME is zero (no time stamp)
+    pub mtime_out_of_range: bool,
+}
+
+/// Compress the file at `src_path` into a gzip file at `dst_path`.
+/// The output inherits the permissions, access/modification times and, when privileged, the owner of the source.
+pub fn compress_to_gzip<P: AsRef<Path>, Q: AsRef<Path>>(src_path: P, dst_path: Q) -> Result<HeaderWarnings> {
+    compress_to_gzip_with_options(src_path, dst_path, &CompressOptions::default())
+}
+
+/// Same as `compress_to_gzip`, with the header fields controlled by `options`.
+pub fn compress_to_gzip_with_options<P: AsRef<Path>, Q: AsRef<Path>>(src_path: P, dst_path: Q, options: &CompressOptions) -> Result<HeaderWarnings> {
+    compress_file(src_path.as_ref(), dst_path.as_ref(), options, None)
+}
+
+/// Same as `compress_to_gzip_with_options`, and also return what the compressor did, see `gzip_bytes_with_stats`
+pub fn compress_to_gzip_with_stats<P: AsRef<Path>, Q: AsRef<Path>>(src_path: P, dst_path: Q, options: &CompressOptions) -> Result<(CompressionStats, HeaderWarnings)> {
+    let mut stats = CompressionStats::default();
+    let warnings = compress_file(src_path.as_ref(), dst_path.as_ref(), options, Some(&mut stats))?;
+    Ok((stats, warnings))
+}
+
+fn compress_file(src_path: &Path, dst_path: &Path, options: &CompressOptions, stats: Option<&mut CompressionStats>) -> Result<HeaderWarnings> {
     let mut src_file = File::open(src_path)?;
-    let dst_file = File::create(dst_path)?;
+    let src_metadata = src_file.metadata()?;
 
     let mut raw_data = Vec::new();
     src_file.read_to_end(&mut raw_data)?;
 
-    let deflated_data = deflate(&raw_data)?;
+    // the header describes the source file, unless `options` give the fields
+    let mut options = options.clone();
+    let mut warnings = HeaderWarnings::default();
+    if !options.no_name {
+        if options.name.is_none() {
+            options.name = Some(src_path.file_name().map(encode_filename).unwrap_or_default());
+        }
+        if options.mtime.is_none() {
+            let mtime = encode_mtime(src_metadata.modified()?);
+            warnings.mtime_out_of_range = mtime.is_none();
+            options.mtime = Some(mtime.unwrap_or(0));
+        }
+    }
+    let gzip_data = gzip_member(&raw_data, &options, stats)?;
+
+    let mut dst_file = File::create(dst_path)?;
+    dst_file.write_all(&gzip_data)?;
+    copy_metadata(&src_metadata, &dst_file, None)?;
 
-    let mut writer = BufWriter::new(dst_file);
+    Ok(warnings)
+}
 
-    writer.write_all(&[0x1f, 0x8b, // fixed values
-                       0x08, // compression method: deflate
-                       0x08, // flags, FNAME set
-                       ])?;
+/// Write a gzip member: the header, the compressed data, and the trailer with the CRC32 and the length of the uncompressed data.
+pub(crate) fn write_member<W: Write>(writer: &mut W, header: &Header, deflated: &[u8], crc32: u32, len: usize) -> io::Result<()> {
+    let mut header_bytes = Vec::new();
+    encode_header(header, &mut header_bytes);
+    writer.write_all(&header_bytes)?;
 
-    // write mtime
-    let mtime = src_file.metadata()?.modified()?.duration_since(SystemTime::UNIX_EPOCH)?.as_secs() as u32;
-    writer.write_all(&mtime.to_le_bytes())?;
+    // write deflate data
+    writer.write_all(deflated)?;
 
-    // write extra flags, os
-    writer.write_all(&[0x00, 0x03])?;
+    writer.write_all(&encode_trailer(crc32, len as u64))
+}
 
-    // write file name
-    let filename = std::path::Path::new(src_path).file_name().unwrap().to_str().unwrap();
-    writer.write_all(filename.as_bytes())?;
-    writer.write_all(&[0x00])?;
+/// Decompress the gzip file at `src_path` into `dst_path`. Concatenated gzip members are decompressed one after another.
+/// The output gets the modification time stored in the header, and the permissions, access time and owner of the source.
+pub fn decompress_from_gzip<P: AsRef<Path>, Q: AsRef<Path>>(src_path: P, dst_path: Q) -> Result<()> {
+    decompress_from_gzip_with_options(src_path, dst_path, &DecompressOptions::default())
+}
 
-    // write deflate data
-    writer.write_all(&deflated_data)?;
+/// Same as `decompress_from_gzip`, within the limits of `options`.
+/// Nothing is written to `dst_path` when a limit is exceeded.
+pub fn decompress_from_gzip_with_options<P: AsRef<Path>, Q: AsRef<Path>>(src_path: P, dst_path: Q, options: &DecompressOptions) -> Result<()> {
+    let mut src_file = File::open(src_path)?;
+    let src_metadata = src_file.metadata()?;
+
+    let mut data = Vec::new();
+    src_file.read_to_end(&mut data)?;
+
+    let (raw_data, header_mtime) = gunzip_members(&data, options)?;
 
-    // write crc32
-    let crc32 = crc32fast::hash(&raw_data);
-    writer.write_all(&crc32.to_le_bytes())?;
+    let mut dst_file = File::create(dst_path)?;
+    dst_file.write_all(&raw_data)?;
 
-    // write isize
-    writer.write_all(&(raw_data.len() as u32).to_le_bytes())?;
+    // MTIME = 0 means no time stamp is available
+    let mtime = header_mtime
+        .filter(|&mtime| mtime != 0)
+        .map(|mtime| SystemTime::UNIX_EPOCH + Duration::from_secs(mtime as u64));
+    copy_metadata(&src_metadata, &dst_file, mtime)?;
 
     Ok(())
 }
 
+/// Convert a file name to the FNAME field. RFC 1952 specifies ISO-8859-1 (LATIN-1),
+/// so names made of LATIN-1 characters are converted, and any other name is stored as its raw bytes.
+fn encode_filename(name: &OsStr) -> Vec<u8> {
+    if let Some(name) = name.to_str() {
+        if let Some(latin1) = name.chars().map(|c| u8::try_from(c).ok()).collect::<Option<Vec<u8>>>() {
+            return latin1;
+        }
+    }
+
+    #[cfg(unix)]
+    let bytes = std::os::unix::ffi::OsStrExt::as_bytes(name).to_vec();
+    #[cfg(not(unix))]
+    let bytes = name.to_string_lossy().into_owned().into_bytes();
+
+    // the field is zero-terminated, so it cannot hold a zero byte
+    bytes.into_iter().filter(|&b| b != 0).collect()
+}
+
+/// Convert a modification time to the MTIME field, or None if it is out of its range:
+/// MTIME can only hold times between 1970 and 2106.
+fn encode_mtime(time: SystemTime) -> Option<u32> {
+    let secs = time.duration_since(SystemTime::UNIX_EPOCH).ok()?.as_secs();
+    u32::try_from(secs).ok()
+}
+
+
 
 #[cfg(test)]
 mod tests {
@@ -52,6 +138,30 @@ mod tests {
 
     #[test]
     fn test_gzip() {
-        compress_to_gzip("/home/louis/rust/test.txt", "/home/louis/rust/test.gz").unwrap();
+        let dir = std::env::temp_dir();
+        let dst = dir.join("rustgzip_unit_example1.gz");
+        compress_to_gzip("examples/example1", dst.to_str().unwrap()).unwrap();
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_encode_filename() {
+        assert_eq!(encode_filename(OsStr::new("stdio.h")), b"stdio.h");
+        assert_eq!(encode_filename(OsStr::new("caf\u{e9}.txt")), b"caf\xe9.txt");
+        // not representable in LATIN-1, stored as is
+        assert_eq!(encode_filename(OsStr::new("\u{4f60}\u{597d}")), "\u{4f60}\u{597d}".as_bytes());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            assert_eq!(encode_filename(OsStr::from_bytes(b"bad\xff\xfe")), b"bad\xff\xfe");
+        }
+    }
+
+    #[test]
+    fn test_encode_mtime() {
+        assert_eq!(encode_mtime(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)), Some(1_700_000_000));
+        assert_eq!(encode_mtime(SystemTime::UNIX_EPOCH - Duration::from_secs(1)), None);
+        assert_eq!(encode_mtime(SystemTime::UNIX_EPOCH + Duration::from_secs(1 << 32)), None);
+    }
+
+}
@@ -0,0 +1,369 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use crate::deflate::Inflater;
+use crate::error::{Error, Result};
+use crate::member::{check_trailer, parse_header};
+
+/// The compressed data is read in pieces of this size
+const READ_SIZE: u64 = 64 * 1024;
+
+/// The start of a serialized index: a name and a format version
+const INDEX_MAGIC: &[u8; 8] = b"rgzidx\x00\x01";
+
+/// The largest window of an access point, the deflate window size
+const MAX_WINDOW: usize = 32768;
+
+/// A place where decompression can start: a block boundary, with the window of data before it
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct AccessPoint {
+    /// The offset of the block in the compressed file, in bits
+    bit_offset: u64,
+    /// The offset in the uncompressed data
+    out_offset: u64,
+    /// The last 32 KiB (or less, at the start) of the uncompressed data before the point
+    window: Vec<u8>,
+}
+
+/// A random access index of a gzip file, like the one of zlib's `zran.c`.
+///
+/// Access points are recorded at the deflate block boundaries, every `span` bytes of uncompressed data or so.
+/// Each one takes a window of 32 KiB, so the span trades the size of the index against the time to seek.
+/// Only the first member of the file is indexed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GzIndex {
+    /// The length of the uncompressed data
+    len: u64,
+    points: Vec<AccessPoint>,
+}
+
+impl GzIndex {
+    /// Decompress the gzip file read from `reader`, verifying it, and record an access point every `span` bytes of output.
+    pub fn build<R: Read>(mut reader: R, span: u64) -> Result<Self> {
+        let mut input = Vec::new();
+        let header_len = loop {
+            match parse_header(&input) {
+                Ok((_, header_len)) => break header_len,
+                Err(err @ Error::TruncatedInput { .. }) => {
+                    if read_input(&mut reader, &mut input)? == 0 {
+                        return Err(err);
+                    }
+                },
+                Err(err) => return Err(err)
+            }
+        };
+
+        let start = header_len as u64 * 8;
+        let mut inflater = Inflater::with_window(start, &[]);
+        // the position in `input` of the next byte to decode
+        let mut pos = header_len;
+        let mut output = vec![0; READ_SIZE as usize];
+
+        let mut points = vec![AccessPoint { bit_offset: start, out_offset: 0, window: Vec::new() }];
+        let mut len = 0;
+        let mut crc32 = crc32fast::Hasher::new();
+
+        while !inflater.is_done() {
+            if pos == input.len() {
+                input.clear();
+                pos = 0;
+                if read_input(&mut reader, &mut input)? == 0 {
+                    return Err(Error::TruncatedInput { bit_offset: inflater.input_offset() * 8 });
+                }
+            }
+            let (taken, written) = inflater.inflate(&input[pos..], &mut output, true)?;
+            pos += taken;
+            crc32.update(&output[..written]);
+            len += written as u64;
+
+            if inflater.at_block_boundary() && !inflater.is_done() && len - points.last().unwrap().out_offset >= span {
+                points.push(AccessPoint { bit_offset: inflater.bit_offset(), out_offset: len, window: inflater.window() });
+            }
+        }
+
+        let end = inflater.bit_offset() / 8;
+        let mut trailer = input[pos..].to_vec();
+        while trailer.len() < 8 {
+            if read_input(&mut reader, &mut trailer)? == 0 {
+                return Err(Error::TruncatedInput { bit_offset: (end + trailer.len() as u64) * 8 });
+            }
+        }
+        check_trailer(&trailer[..8], crc32.finalize(), len).map_err(|err| err.offset_by(end))?;
+
+        Ok(GzIndex { len, points })
+    }
+
+    /// The length of the uncompressed data
+    pub fn uncompressed_len(&self) -> u64 {
+        self.len
+    }
+
+    /// Serialize the index, to keep it in a sidecar file next to the gzip file.
+    ///
+    /// The format is `INDEX_MAGIC`, the uncompressed length and the number of access points,
+    /// then for each point its bit offset, its uncompressed offset, the length of its window, and the window.
+    /// Integers are little endian, the window length takes 4 bytes and the others 8.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(INDEX_MAGIC)?;
+        writer.write_all(&self.len.to_le_bytes())?;
+        writer.write_all(&(self.points.len() as u64).to_le_bytes())?;
+        for point in &self.points {
+            writer.write_all(&point.bit_offset.to_le_bytes())?;
+            writer.write_all(&point.out_offset.to_le_bytes())?;
+            writer.write_all(&(point.window.len() as u32).to_le_bytes())?;
+            writer.write_all(&point.window)?;
+        }
+        writer.flush()
+    }
+
+    /// Read an index written by `write_to`
+    pub fn read_from<R: Read>(mut reader: R) -> Result<Self> {
+        let mut magic = [0; 8];
+        read_index_field(&mut reader, &mut magic)?;
+        if &magic != INDEX_MAGIC {
+            return Err(Error::InvalidIndex);
+        }
+        let len = read_u64(&mut reader)?;
+        let count = read_u64(&mut reader)?;
+
+        let mut points: Vec<AccessPoint> = Vec::new();
+        for _ in 0..count {
+            let bit_offset = read_u64(&mut reader)?;
+            let out_offset = read_u64(&mut reader)?;
+            let mut window_len = [0; 4];
+            read_index_field(&mut reader, &mut window_len)?;
+            let window_len = u32::from_le_bytes(window_len) as usize;
+            if window_len > MAX_WINDOW {
+                return Err(Error::InvalidIndex);
+            }
+            let mut window = vec![0; window_len];
+            read_index_field(&mut reader, &mut window)?;
+
+            // the points are in order, and the first one is at the start of the data
+            let in_order = match points.last() {
+                Some(last) => out_offset > last.out_offset && bit_offset > last.bit_offset && out_offset <= len,
+                None => out_offset == 0,
+            };
+            if !in_order {
+                return Err(Error::InvalidIndex);
+            }
+            points.push(AccessPoint { bit_offset, out_offset, window });
+        }
+        if points.is_empty() {
+            return Err(Error::InvalidIndex);
+        }
+
+        Ok(GzIndex { len, points })
+    }
+
+    /// The last access point at or before the uncompressed offset
+    fn point_before(&self, offset: u64) -> usize {
+        self.points.partition_point(|point| point.out_offset <= offset) - 1
+    }
+}
+
+fn read_input<R: Read>(reader: &mut R, input: &mut Vec<u8>) -> io::Result<usize> {
+    reader.by_ref().take(READ_SIZE).read_to_end(input)
+}
+
+fn read_index_field<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<()> {
+    reader.read_exact(buf).map_err(|err| match err.kind() {
+        io::ErrorKind::UnexpectedEof => Error::InvalidIndex,
+        _ => err.into()
+    })
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64> {
+    let mut buf = [0; 8];
+    read_index_field(reader, &mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+
+/// Reads the uncompressed data of a gzip file at any position, using a `GzIndex` of the file.
+///
+/// Seeking restarts decompression at the nearest access point before the target, and skips the data up to it.
+pub struct GzSeekableReader<R: Read + Seek> {
+    inner: R,
+    index: GzIndex,
+    inflater: Inflater,
+    /// The compressed data read from `inner`, and the position of the next byte to decode
+    input: Vec<u8>,
+    input_pos: usize,
+    /// The offset in the uncompressed data of the next byte read
+    pos: u64,
+}
+
+impl<R: Read + Seek> GzSeekableReader<R> {
+    /// Read the gzip file `inner` with its index. The reader starts at the beginning of the uncompressed data.
+    pub fn new(inner: R, index: GzIndex) -> Result<Self> {
+        let mut reader = GzSeekableReader {
+            inner,
+            index,
+            inflater: Inflater::new(),
+            input: Vec::new(),
+            input_pos: 0,
+            pos: 0,
+        };
+        reader.restart(0)?;
+        Ok(reader)
+    }
+
+    pub fn index(&self) -> &GzIndex {
+        &self.index
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Start decompressing again at an access point
+    fn restart(&mut self, point: usize) -> Result<()> {
+        let point = &self.index.points[point];
+        self.inner.seek(SeekFrom::Start(point.bit_offset / 8))?;
+        self.inflater.reset(point.bit_offset, &point.window);
+        self.input.clear();
+        self.input_pos = 0;
+        self.pos = point.out_offset;
+        Ok(())
+    }
+
+    /// Decode into `buf`, which must not be empty, reading the file as needed. Return 0 at the end of the data.
+    fn fill(&mut self, buf: &mut [u8]) -> Result<usize> {
+        while !self.inflater.is_done() {
+            if self.input_pos == self.input.len() {
+                self.input.clear();
+                self.input_pos = 0;
+                if read_input(&mut self.inner, &mut self.input)? == 0 {
+                    return Err(Error::TruncatedInput { bit_offset: self.inflater.input_offset() * 8 });
+                }
+            }
+            let (taken, written) = self.inflater.inflate(&self.input[self.input_pos..], buf, false)?;
+            self.input_pos += taken;
+            if written > 0 {
+                self.pos += written as u64;
+                return Ok(written);
+            }
+        }
+        Ok(0)
+    }
+}
+
+impl<R: Read + Seek> Read for GzSeekableReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        Ok(self.fill(buf)?)
+    }
+}
+
+impl<R: Read + Seek> Seek for GzSeekableReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.index.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        }.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek to a negative or overflowing position"))?;
+
+        // reading on is faster than restarting, unless there is an access point in between
+        let point = self.index.point_before(target);
+        if target < self.pos || self.index.points[point].out_offset > self.pos {
+            self.restart(point)?;
+        }
+
+        let mut skipped = vec![0; READ_SIZE as usize];
+        while self.pos < target {
+            let len = (target - self.pos).min(READ_SIZE) as usize;
+            if self.fill(&mut skipped[..len])? == 0 {
+                break;
+            }
+        }
+        // past the end, reads return nothing
+        self.pos = target;
+        Ok(target)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use crate::deflate::deflate;
+    use crate::gzip::write_member;
+    use crate::member::Header;
+
+    /// Data that hardly compresses, so that it takes many blocks
+    fn sample(len: usize) -> Vec<u8> {
+        let mut state = 1u32;
+        (0..len).map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 24) as u8
+        }).collect()
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut gz = Vec::new();
+        let header = Header { mtime: 0, extra: None, name: Some(b"sample"), comment: None, os: 3 };
+        write_member(&mut gz, &header, &deflate(data).unwrap(), crc32fast::hash(data), data.len()).unwrap();
+        gz
+    }
+
+    #[test]
+    fn test_build_index() {
+        let data = sample(1_000_000);
+        let gz = gzip(&data);
+        let index = GzIndex::build(Cursor::new(&gz), 100_000).unwrap();
+
+        assert_eq!(index.uncompressed_len(), data.len() as u64);
+        assert!(index.points.len() >= 5);
+        for (a, b) in index.points.iter().zip(&index.points[1..]) {
+            assert!(b.out_offset - a.out_offset >= 100_000);
+            assert_eq!(b.window, data[b.out_offset as usize - MAX_WINDOW..b.out_offset as usize]);
+        }
+
+        let mut corrupt = gz.clone();
+        let len = corrupt.len();
+        corrupt[len - 8] ^= 1;
+        assert!(matches!(GzIndex::build(Cursor::new(&corrupt), 100_000), Err(Error::DataCrc { .. })));
+        assert!(matches!(GzIndex::build(Cursor::new(&gz[..len - 4]), 100_000), Err(Error::TruncatedInput { .. })));
+    }
+
+    #[test]
+    fn test_serialize_index() {
+        let data = sample(300_000);
+        let index = GzIndex::build(Cursor::new(gzip(&data)), 50_000).unwrap();
+
+        let mut serialized = Vec::new();
+        index.write_to(&mut serialized).unwrap();
+        assert_eq!(GzIndex::read_from(Cursor::new(&serialized)).unwrap(), index);
+
+        assert!(matches!(GzIndex::read_from(Cursor::new(&serialized[..serialized.len() - 1])), Err(Error::InvalidIndex)));
+        serialized[0] = b'x';
+        assert!(matches!(GzIndex::read_from(Cursor::new(&serialized)), Err(Error::InvalidIndex)));
+    }
+
+    #[test]
+    fn test_seekable_reader() {
+        let data = sample(1_000_000);
+        let gz = gzip(&data);
+        let index = GzIndex::build(Cursor::new(&gz), 100_000).unwrap();
+        let mut reader = GzSeekableReader::new(Cursor::new(&gz), index).unwrap();
+
+        for offset in [999_000, 0, 123_456, 123_457, 500_000, 65_000, 999_999] {
+            assert_eq!(reader.seek(SeekFrom::Start(offset)).unwrap(), offset);
+            let mut buf = vec![0; 1000.min(data.len() - offset as usize)];
+            reader.read_exact(&mut buf).unwrap();
+            assert_eq!(buf, data[offset as usize..offset as usize + buf.len()]);
+        }
+
+        assert_eq!(reader.seek(SeekFrom::End(-10)).unwrap(), 999_990);
+        assert_eq!(reader.seek(SeekFrom::Current(-5)).unwrap(), 999_985);
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, data[999_985..]);
+
+        assert!(reader.seek(SeekFrom::Current(-2_000_000)).is_err());
+        reader.seek(SeekFrom::Start(2_000_000)).unwrap();
+        assert_eq!(reader.read(&mut [0; 10]).unwrap(), 0);
+    }
+}
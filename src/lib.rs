@@ -1,6 +1,48 @@
+//! Without the `std` feature, the crate is `no_std` and needs only `alloc`: the streaming `Compressor` and `Decompressor`
+//! and the checksums are available, while the file functions, the I/O adapters and parallel compression need `std`.
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+// the one-shot and chunked functions are only used by the `std` modules
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
 mod deflate;
+mod checksum;
+mod member;
+#[cfg(feature = "std")]
 mod gzip;
+#[cfg(feature = "std")]
+mod encoder;
+mod stream;
+mod zlib;
+#[cfg(feature = "std")]
+mod bgzf;
+#[cfg(feature = "std")]
+mod index;
 mod error;
-mod circular_buf;
+#[cfg(feature = "std")]
+mod metadata;
+mod options;
+mod progress;
+mod stats;
+mod oneshot;
+#[cfg(feature = "std")]
+mod parallel;
 
-pub use gzip::compress_to_gzip;
\ No newline at end of file
+#[cfg(feature = "std")]
+pub use gzip::{HeaderWarnings, compress_to_gzip, compress_to_gzip_with_options, compress_to_gzip_with_stats, decompress_from_gzip, decompress_from_gzip_with_options};
+#[cfg(feature = "std")]
+pub use encoder::GzEncoder;
+pub use oneshot::{gzip_bytes, gzip_bytes_with_stats, gunzip_bytes, zlib_compress_bytes, zlib_decompress_bytes, deflate_bytes, inflate_bytes};
+pub use oneshot::{compress_bound, compress_into, zlib_compress_into, deflate_into};
+pub use oneshot::{decompress_into, zlib_decompress_into, inflate_into};
+pub use stream::{Compressor, Decompressor, Format, Flush, State, Status};
+#[cfg(feature = "std")]
+pub use bgzf::{BgzfWriter, BgzfReader};
+#[cfg(feature = "std")]
+pub use index::{GzIndex, GzSeekableReader};
+pub use options::{CompressOptions, DecompressOptions, Strategy, DEFAULT_MAX_HEADER_FIELD};
+pub use progress::{CancelToken, Progress};
+pub use stats::{BlockKind, BlockStats, CompressionStats};
+pub use error::{Error, Limit, Result};
+pub use checksum::{crc32_combine, adler32, adler32_combine};
@@ -1,16 +1,224 @@
 use clap::Parser;
 use std::error::Error;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use clap::ValueEnum;
+use rustgzip::{BlockKind, CompressOptions, CompressionStats, DecompressOptions, Progress, Strategy};
 
 /// A Rust implementation of GZIP compression
 #[derive(Debug, Parser)]
 struct Cli {
     /// The input file to be compressed
-    input: String,
+    input: PathBuf,
     /// The output compressed file
     #[arg(short, long)]
-    output: Option<String>
+    output: Option<PathBuf>,
+    /// Decompress the input instead of compressing it
+    #[arg(short, long)]
+    decompress: bool,
+    /// The compression level, from 0 (no compression, fastest) to 9 (best compression, slowest)
+    #[arg(short, long, value_parser = clap::value_parser!(u8).range(0..=9))]
+    level: Option<u8>,
+    /// Do not save the original file name and time stamp. The time stamp is taken from SOURCE_DATE_EPOCH if it is set
+    #[arg(short, long)]
+    no_name: bool,
+    /// Compress with this many threads. The output is the same for any number above 1, and differs from the output with 1
+    #[arg(short = 'p', long, default_value_t = 1)]
+    threads: usize,
+    /// Make the output rsync-friendly, so that a local change in the input only changes the output around it
+    #[arg(long)]
+    rsyncable: bool,
+    /// The base-2 logarithm of the window size, from 8 to 15. A smaller window lowers the memory needed to decompress
+    #[arg(long, value_parser = clap::value_parser!(u8).range(8..=15))]
+    window_bits: Option<u8>,
+    /// How much memory to use for finding matches, from 1 to 9
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=9))]
+    mem_level: Option<u8>,
+    /// How to look for matches and code the blocks
+    #[arg(long, value_enum, default_value_t = StrategyArg::Default)]
+    strategy: StrategyArg,
+    /// Show a progress bar on stderr, if it is a terminal
+    #[arg(long)]
+    progress: bool,
+    /// Print statistics of the compression: the blocks, the matches and the time spent in each stage
+    #[arg(long, value_enum, num_args = 0..=1, require_equals = true, default_missing_value = "text", conflicts_with = "decompress")]
+    stats: Option<StatsFormat>,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum StatsFormat {
+    /// A report for people
+    Text,
+    /// A JSON object, for scripts
+    Json,
 }
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum StrategyArg {
+    /// Longest matches, dynamic or fixed codes
+    Default,
+    /// Leave short matches as literals, for PNG-like data
+    Filtered,
+    /// No matches, only literals
+    HuffmanOnly,
+    /// Only runs of the same byte
+    Rle,
+    /// Always the fixed huffman codes
+    Fixed,
+}
+
+impl From<StrategyArg> for Strategy {
+    fn from(arg: StrategyArg) -> Self {
+        match arg {
+            StrategyArg::Default => Strategy::Default,
+            StrategyArg::Filtered => Strategy::Filtered,
+            StrategyArg::HuffmanOnly => Strategy::HuffmanOnly,
+            StrategyArg::Rle => Strategy::Rle,
+            StrategyArg::Fixed => Strategy::Fixed,
+        }
+    }
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
     let args = Cli::parse();
-    rustgzip::compress_to_gzip(&args.input, &args.output.unwrap_or(args.input.clone() + ".gz"))
+    let progress = if args.progress && std::io::stderr().is_terminal() { Some(progress_bar(&args.input)?) } else { None };
+
+    if args.decompress {
+        let output = match args.output {
+            Some(output) => output,
+            None if args.input.extension().is_some_and(|ext| ext == "gz") => args.input.with_extension(""),
+            None => return Err(format!("{}: unknown suffix, please specify the output file", args.input.display()).into())
+        };
+        let options = DecompressOptions { progress, ..Default::default() };
+        rustgzip::decompress_from_gzip_with_options(&args.input, &output, &options)?;
+    } else {
+        let options = CompressOptions {
+            no_name: args.no_name,
+            mtime: if args.no_name { source_date_epoch()? } else { None },
+            threads: args.threads,
+            rsyncable: args.rsyncable,
+            window_bits: args.window_bits,
+            mem_level: args.mem_level,
+            strategy: args.strategy.into(),
+            level: args.level,
+            progress,
+            ..Default::default()
+        };
+        let output = args.output.unwrap_or_else(|| {
+            let mut output = args.input.clone().into_os_string();
+            output.push(".gz");
+            output.into()
+        });
+        let warnings = match args.stats {
+            Some(format) => {
+                let (stats, warnings) = rustgzip::compress_to_gzip_with_stats(&args.input, &output, &options)?;
+                match format {
+                    StatsFormat::Text => print_stats(&stats),
+                    StatsFormat::Json => println!("{}", stats_json(&stats)),
+                }
+                warnings
+            }
+            None => rustgzip::compress_to_gzip_with_options(&args.input, &output, &options)?
+        };
+        if warnings.mtime_out_of_range {
+            eprintln!("rustgzip: warning: {}: modification time out of range for the gzip header, storing zero instead", args.input.display());
+        }
+    }
+
+    Ok(())
+}
+
+/// The width of the progress bar, in characters
+const BAR_WIDTH: u64 = 40;
+
+/// A progress callback drawing a bar on stderr, with the share of the input read so far and the bytes in and out.
+/// The bar is redrawn in place, and the line is ended once all the input is read.
+fn progress_bar(input: &Path) -> Result<Progress, Box<dyn Error>> {
+    let total = std::fs::metadata(input)?.len();
+    Ok(Progress::new((total / 200).max(64 * 1024), move |read, written| {
+        let filled = (read * BAR_WIDTH).checked_div(total).unwrap_or(BAR_WIDTH);
+        let percent = (read * 100).checked_div(total).unwrap_or(100);
+        let mut stderr = std::io::stderr().lock();
+        let _ = write!(stderr, "\r[{:<width$}] {:3}% {} -> {}", "#".repeat(filled as usize), percent, human_size(read), human_size(written),
+                       width = BAR_WIDTH as usize);
+        if read >= total {
+            let _ = writeln!(stderr);
+        }
+    }))
+}
+
+/// A byte count with a binary unit, e.g. 12.3 MiB
+fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+fn print_stats(stats: &CompressionStats) {
+    let input: u64 = stats.blocks.iter().map(|block| block.input_bytes).sum();
+    let output: u64 = stats.blocks.iter().map(|block| block.output_bits).sum::<u64>().div_ceil(8);
+    println!("input:    {} bytes", input);
+    println!("output:   {} bytes of deflate data ({:.1}% of the input)", output, output as f64 * 100.0 / input.max(1) as f64);
+    println!("blocks:   {} ({} stored, {} fixed, {} dynamic)", stats.blocks.len(),
+             stats.block_count(BlockKind::Stored), stats.block_count(BlockKind::Fixed), stats.block_count(BlockKind::Dynamic));
+    println!("literals: {}", stats.literals);
+    println!("matches:  {} ({} bytes, {:.2} bytes on average)", stats.matches, stats.match_bytes, stats.average_match_length());
+    println!("time:     {:.3} ms in lz77, {:.3} ms in huffman", stats.lz77_time.as_secs_f64() * 1000.0, stats.huffman_time.as_secs_f64() * 1000.0);
+
+    println!();
+    println!("length code  matches");
+    for (i, &count) in stats.length_codes.iter().enumerate().filter(|(_, &count)| count > 0) {
+        println!("{:11}  {}", 257 + i, count);
+    }
+    println!();
+    println!("distance code  matches");
+    for (i, &count) in stats.distance_codes.iter().enumerate().filter(|(_, &count)| count > 0) {
+        println!("{:13}  {}", i, count);
+    }
+    println!();
+    println!("block  kind     input bytes  output bits");
+    for (i, block) in stats.blocks.iter().enumerate() {
+        println!("{:5}  {:7}  {:11}  {:11}", i, block_kind_name(block.kind), block.input_bytes, block.output_bits);
+    }
+}
+
+fn stats_json(stats: &CompressionStats) -> String {
+    let join = |values: Vec<String>| values.join(",");
+    let blocks = stats.blocks.iter()
+        .map(|block| format!("{{\"kind\":\"{}\",\"input_bytes\":{},\"output_bits\":{}}}", block_kind_name(block.kind), block.input_bytes, block.output_bits))
+        .collect();
+    format!("{{\"blocks\":[{}],\"stored_blocks\":{},\"fixed_blocks\":{},\"dynamic_blocks\":{},\"literals\":{},\"matches\":{},\"match_bytes\":{},\
+             \"average_match_length\":{},\"length_codes\":[{}],\"distance_codes\":[{}],\"lz77_seconds\":{},\"huffman_seconds\":{}}}",
+            join(blocks), stats.block_count(BlockKind::Stored), stats.block_count(BlockKind::Fixed), stats.block_count(BlockKind::Dynamic),
+            stats.literals, stats.matches, stats.match_bytes, stats.average_match_length(),
+            join(stats.length_codes.iter().map(u64::to_string).collect()), join(stats.distance_codes.iter().map(u64::to_string).collect()),
+            stats.lz77_time.as_secs_f64(), stats.huffman_time.as_secs_f64())
+}
+
+fn block_kind_name(kind: BlockKind) -> &'static str {
+    match kind {
+        BlockKind::Stored => "stored",
+        BlockKind::Fixed => "fixed",
+        BlockKind::Dynamic => "dynamic",
+    }
+}
+
+/// Read the SOURCE_DATE_EPOCH environment variable, see https://reproducible-builds.org/specs/source-date-epoch/
+fn source_date_epoch() -> Result<Option<u32>, Box<dyn Error>> {
+    match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(value) => match value.trim().parse() {
+            Ok(epoch) => Ok(Some(epoch)),
+            Err(_) => Err(format!("invalid SOURCE_DATE_EPOCH: {}", value).into())
+        },
+        Err(_) => Ok(None)
+    }
 }
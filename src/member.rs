@@ -0,0 +1,180 @@
+//! The header and the trailer of a gzip member, without I/O so that the streaming core can use them
+
+use alloc::vec::Vec;
+use crate::error::{Error, Limit, Result};
+
+/// Header flags, see RFC 1952, Section 2.3.1
+pub(crate) const FHCRC: u8 = 0x02;
+pub(crate) const FEXTRA: u8 = 0x04;
+pub(crate) const FNAME: u8 = 0x08;
+pub(crate) const FCOMMENT: u8 = 0x10;
+
+/// The OS field of the header, see RFC 1952, Section 2.3.1
+pub(crate) const OS_UNIX: u8 = 3;
+
+/// The fields of a gzip member header (See RFC 1952, Section 2.3)
+pub(crate) struct Header<'a> {
+    pub mtime: u32,
+    /// The extra field (FEXTRA), without its length
+    pub extra: Option<&'a [u8]>,
+    /// The file name (FNAME), without the terminating zero
+    pub name: Option<&'a [u8]>,
+    /// The comment (FCOMMENT), without the terminating zero
+    pub comment: Option<&'a [u8]>,
+    pub os: u8,
+}
+
+/// Append the header of a gzip member to `out`
+pub(crate) fn encode_header(header: &Header, out: &mut Vec<u8>) {
+    let mut flags = 0;
+    if header.extra.is_some() {
+        flags |= FEXTRA;
+    }
+    if header.name.is_some() {
+        flags |= FNAME;
+    }
+    if header.comment.is_some() {
+        flags |= FCOMMENT;
+    }
+
+    out.extend_from_slice(&[0x1f, 0x8b, // fixed values
+                            0x08, // compression method: deflate
+                            flags,
+                            ]);
+    out.extend_from_slice(&header.mtime.to_le_bytes());
+
+    // extra flags, os
+    out.extend_from_slice(&[0x00, header.os]);
+
+    if let Some(extra) = header.extra {
+        out.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+        out.extend_from_slice(extra);
+    }
+    for field in [header.name, header.comment].into_iter().flatten() {
+        debug_assert!(!field.contains(&0));
+        out.extend_from_slice(field);
+        out.push(0x00);
+    }
+}
+
+/// The trailer of a gzip member: the CRC32 and isize, the length of the uncompressed data modulo 2^32
+pub(crate) fn encode_trailer(crc32: u32, len: u64) -> [u8; 8] {
+    let mut trailer = [0; 8];
+    trailer[..4].copy_from_slice(&crc32.to_le_bytes());
+    trailer[4..].copy_from_slice(&(len as u32).to_le_bytes());
+    trailer
+}
+
+/// Check the CRC32 and ISIZE of the 8-byte trailer of a gzip member against the CRC32 and the length of the decompressed data.
+pub(crate) fn check_trailer(trailer: &[u8], actual_crc32: u32, actual_len: u64) -> Result<()> {
+    let crc32 = u32::from_le_bytes(trailer[0..4].try_into().unwrap());
+    let isize = u32::from_le_bytes(trailer[4..8].try_into().unwrap());
+
+    if crc32 != actual_crc32 {
+        return Err(Error::DataCrc { expected: crc32, actual: actual_crc32, offset: 0 });
+    }
+    // ISIZE is the length modulo 2^32
+    if isize != actual_len as u32 {
+        return Err(Error::LengthMismatch { expected: isize, actual: actual_len as u32, offset: 4 });
+    }
+    Ok(())
+}
+
+/// Parse the header of a gzip member (See RFC 1952, Section 2.3).
+/// Return the MTIME field and the length of the header.
+pub(crate) fn parse_header(data: &[u8]) -> Result<(u32, usize)> {
+    parse_header_limited(data, usize::MAX)
+}
+
+/// Same as `parse_header`, but fail with `LimitExceeded` as soon as FEXTRA, FNAME or FCOMMENT is longer than `max_field` bytes,
+/// without searching further for the end of the field
+pub(crate) fn parse_header_limited(data: &[u8], max_field: usize) -> Result<(u32, usize)> {
+    let truncated = || Error::TruncatedInput { bit_offset: data.len() as u64 * 8 };
+
+    let fixed = data.get(..10).ok_or_else(truncated)?;
+    if fixed[0..2] != [0x1f, 0x8b] {
+        return Err(Error::BadMagic { offset: 0 });
+    }
+    if fixed[2] != 0x08 {
+        return Err(Error::UnsupportedMethod { method: fixed[2], offset: 2 });
+    }
+    let flags = fixed[3];
+    let mtime = u32::from_le_bytes(fixed[4..8].try_into().unwrap());
+
+    let mut pos = 10;
+    if flags & FEXTRA != 0 {
+        let xlen = data.get(pos..pos + 2).ok_or_else(truncated)?;
+        let xlen = u16::from_le_bytes([xlen[0], xlen[1]]) as usize;
+        if xlen > max_field {
+            return Err(Error::LimitExceeded(Limit::ExtraLength));
+        }
+        pos += 2 + xlen;
+    }
+    // FNAME and FCOMMENT are zero-terminated
+    for (flag, limit) in [(FNAME, Limit::NameLength), (FCOMMENT, Limit::CommentLength)] {
+        if flags & flag != 0 {
+            let rest = data.get(pos..).ok_or_else(truncated)?;
+            let searched = &rest[..rest.len().min(max_field.saturating_add(1))];
+            let len = match searched.iter().position(|&b| b == 0) {
+                Some(len) => len,
+                None if rest.len() > max_field => return Err(Error::LimitExceeded(limit)),
+                None => return Err(truncated())
+            };
+            pos += len + 1;
+        }
+    }
+    if pos > data.len() {
+        return Err(truncated());
+    }
+
+    // the CRC16 is the two least significant bytes of the CRC32 of the header before it
+    if flags & FHCRC != 0 {
+        let crc16 = data.get(pos..pos + 2).ok_or_else(truncated)?;
+        if u16::from_le_bytes([crc16[0], crc16[1]]) != crc32fast::hash(&data[..pos]) as u16 {
+            return Err(Error::HeaderCrc { offset: pos as u64 });
+        }
+        pos += 2;
+    }
+
+    Ok((mtime, pos))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn test_parse_header() {
+        let header = [0x1f, 0x8b, 0x08, FNAME | FCOMMENT, 0x78, 0x56, 0x34, 0x12, 0x00, 0x03, b'a', 0, b'b', b'c', 0, 0xff];
+        assert_eq!(parse_header(&header).unwrap(), (0x12345678, 15));
+        assert!(parse_header(&header[..12]).is_err());
+        assert!(matches!(parse_header(&[0x1f, 0x8c, 0x08, 0, 0, 0, 0, 0, 0, 3]), Err(Error::BadMagic { offset: 0 })));
+        assert!(matches!(parse_header(&[0x1f, 0x8b, 0x07, 0, 0, 0, 0, 0, 0, 3]), Err(Error::UnsupportedMethod { method: 7, offset: 2 })));
+
+        let mut header = vec![0x1f, 0x8b, 0x08, FHCRC, 0, 0, 0, 0, 0, 3];
+        let crc16 = crc32fast::hash(&header) as u16;
+        header.extend_from_slice(&crc16.to_le_bytes());
+        assert_eq!(parse_header(&header).unwrap(), (0, 12));
+        header[11] ^= 1;
+        assert!(matches!(parse_header(&header), Err(Error::HeaderCrc { offset: 10 })));
+    }
+
+    #[test]
+    fn test_header_limits() {
+        let header = [0x1f, 0x8b, 0x08, FNAME | FCOMMENT, 0, 0, 0, 0, 0, 3, b'a', b'b', 0, b'c', 0];
+        assert_eq!(parse_header_limited(&header, 2).unwrap(), (0, 15));
+        assert!(matches!(parse_header_limited(&header, 1), Err(Error::LimitExceeded(Limit::NameLength))));
+        // the name is cut short, but already longer than the limit
+        assert!(matches!(parse_header_limited(&header[..12], 1), Err(Error::LimitExceeded(Limit::NameLength))));
+        assert!(matches!(parse_header_limited(&header[..12], 2), Err(Error::TruncatedInput { .. })));
+
+        let header = [0x1f, 0x8b, 0x08, FCOMMENT, 0, 0, 0, 0, 0, 3, b'a', b'b', 0];
+        assert!(matches!(parse_header_limited(&header, 1), Err(Error::LimitExceeded(Limit::CommentLength))));
+
+        let header = [0x1f, 0x8b, 0x08, FEXTRA, 0, 0, 0, 0, 0, 3, 0xff, 0xff];
+        assert!(matches!(parse_header_limited(&header, 1000), Err(Error::LimitExceeded(Limit::ExtraLength))));
+        assert!(matches!(parse_header(&header), Err(Error::TruncatedInput { .. })));
+    }
+}
@@ -0,0 +1,31 @@
+use std::fs::{File, FileTimes, Metadata};
+use std::io;
+use std::time::SystemTime;
+
+/// Copy the ownership, permissions and access/modification times of a source file to `dst`.
+/// If `mtime` is given, it replaces the modification time of the source.
+///
+/// Ownership can only be given away by a privileged user, so a permission error while changing it is ignored.
+/// The times are set last, since any further write to `dst` would update them.
+pub(crate) fn copy_metadata(src: &Metadata, dst: &File, mtime: Option<SystemTime>) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{fchown, MetadataExt};
+
+        match fchown(dst, Some(src.uid()), Some(src.gid())) {
+            Err(err) if err.kind() == io::ErrorKind::PermissionDenied => {},
+            result => result?
+        }
+    }
+
+    // changing the owner may clear the setuid/setgid bits, so the permissions go after it
+    dst.set_permissions(src.permissions())?;
+
+    let times = FileTimes::new()
+        .set_accessed(src.accessed()?)
+        .set_modified(match mtime {
+            Some(mtime) => mtime,
+            None => src.modified()?
+        });
+    dst.set_times(times)
+}
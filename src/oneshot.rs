@@ -0,0 +1,435 @@
+//! One-shot compression and decompression of data held in memory, for gzip, zlib and raw deflate
+
+use alloc::vec::Vec;
+use crate::checksum::adler32;
+use crate::deflate::{deflate_bound, deflate_into as deflate_stream_into, deflate_rsyncable, deflate_with_progress};
+use crate::deflate::{inflate_into as inflate_stream_into, inflate_limited, Params};
+use crate::error::{Error, Limit, Result};
+use crate::member::{check_trailer, encode_header, encode_trailer, parse_header, parse_header_limited, Header, OS_UNIX};
+use crate::options::{CompressOptions, DecompressOptions};
+use crate::stats::CompressionStats;
+use crate::zlib::{check_zlib_trailer, parse_zlib_header, zlib_header};
+
+/// Compress `data` into a gzip member, with the level and the header fields of `options`.
+/// The header has the `name` and `comment` of `options` if they are given, and `mtime` or zero for MTIME.
+pub fn gzip_bytes(data: &[u8], options: &CompressOptions) -> Result<Vec<u8>> {
+    gzip_member(data, options, None)
+}
+
+/// Same as `gzip_bytes`, and also return what the compressor did, e.g. how many blocks of each kind it wrote
+pub fn gzip_bytes_with_stats(data: &[u8], options: &CompressOptions) -> Result<(Vec<u8>, CompressionStats)> {
+    let mut stats = CompressionStats::default();
+    let gz = gzip_member(data, options, Some(&mut stats))?;
+    Ok((gz, stats))
+}
+
+/// Compress `data` into a gzip member, recording the blocks in `stats` if it is given
+pub(crate) fn gzip_member(data: &[u8], options: &CompressOptions, stats: Option<&mut CompressionStats>) -> Result<Vec<u8>> {
+    let name = if options.no_name { None } else { options.name.as_deref() };
+    let comment = options.comment.as_deref();
+    // the fields are zero-terminated
+    if name.is_some_and(|name| name.contains(&0)) {
+        return Err(Error::InvalidOption("name"));
+    }
+    if comment.is_some_and(|comment| comment.contains(&0)) {
+        return Err(Error::InvalidOption("comment"));
+    }
+
+    let (deflated, crc32) = deflate_data(data, options, stats)?;
+    let header = Header { mtime: options.mtime.unwrap_or(0), extra: None, name, comment, os: OS_UNIX };
+
+    let mut dst = Vec::with_capacity(deflated.len() + 18);
+    encode_header(&header, &mut dst);
+    dst.extend_from_slice(&deflated);
+    dst.extend_from_slice(&encode_trailer(crc32.unwrap_or_else(|| crc32fast::hash(data)), data.len() as u64));
+    Ok(dst)
+}
+
+/// Decompress gzip data. Concatenated gzip members are decompressed one after another.
+pub fn gunzip_bytes(data: &[u8], options: &DecompressOptions) -> Result<Vec<u8>> {
+    gunzip_members(data, options).map(|(dst, _)| dst)
+}
+
+/// Compress `data` into a zlib stream, with the level of `options`. The header fields do not apply.
+pub fn zlib_compress_bytes(data: &[u8], options: &CompressOptions) -> Result<Vec<u8>> {
+    let params = options.deflate_params()?;
+    let (deflated, _) = deflate_data(data, options, None)?;
+
+    let mut dst = Vec::with_capacity(deflated.len() + 6);
+    dst.extend_from_slice(&zlib_header(params.window_bits, params.level));
+    dst.extend_from_slice(&deflated);
+    dst.extend_from_slice(&adler32(data).to_be_bytes());
+    Ok(dst)
+}
+
+/// Decompress a zlib stream. The data after the end of the stream is ignored.
+pub fn zlib_decompress_bytes(data: &[u8], options: &DecompressOptions) -> Result<Vec<u8>> {
+    let pos = parse_zlib_header(data)?;
+    let (dst, deflate_len) = inflate_within(data, pos, options)?;
+    let pos = pos + deflate_len;
+
+    let trailer = data.get(pos..pos + 4).ok_or(Error::TruncatedInput { bit_offset: data.len() as u64 * 8 })?;
+    check_zlib_trailer(trailer, adler32(&dst)).map_err(|err| err.offset_by(pos as u64))?;
+    Ok(dst)
+}
+
+/// Compress `data` into a raw deflate stream, with the level of `options`. The header fields do not apply.
+pub fn deflate_bytes(data: &[u8], options: &CompressOptions) -> Result<Vec<u8>> {
+    deflate_data(data, options, None).map(|(deflated, _)| deflated)
+}
+
+/// Decompress a raw deflate stream. The data after the end of the stream is ignored.
+pub fn inflate_bytes(data: &[u8], options: &DecompressOptions) -> Result<Vec<u8>> {
+    inflate_within(data, 0, options).map(|(dst, _)| dst)
+}
+
+/// The length of the gzip header without optional fields, and of the trailer
+const GZIP_HEADER_LEN: usize = 10;
+const GZIP_TRAILER_LEN: usize = 8;
+
+/// The largest output of `compress_into` for `len` bytes of input, so that a buffer of this size never is too small.
+/// It is also a bound for `zlib_compress_into` and `deflate_into`, whose header and trailer are smaller,
+/// and for the `*_bytes` functions with the default options and no header fields.
+///
+/// The compressed data takes at most 5 bytes more than the input per 32 KiB of input, as incompressible data is stored as is.
+pub fn compress_bound(len: usize) -> usize {
+    deflate_bound(len).saturating_add(GZIP_HEADER_LEN + GZIP_TRAILER_LEN)
+}
+
+/// Compress `data` into a gzip member in `dst` with the default options, and return its length.
+/// Fail with `BufferTooSmall` if it does not fit, which cannot happen when `dst` holds `compress_bound(data.len())` bytes.
+///
+/// The output is the same as `gzip_bytes` with the default options, written to `dst` instead of a new `Vec`.
+pub fn compress_into(data: &[u8], dst: &mut [u8]) -> Result<usize> {
+    if dst.len() < GZIP_HEADER_LEN + GZIP_TRAILER_LEN {
+        return Err(Error::BufferTooSmall);
+    }
+    let mut header = Vec::with_capacity(GZIP_HEADER_LEN);
+    encode_header(&Header { mtime: 0, extra: None, name: None, comment: None, os: OS_UNIX }, &mut header);
+    dst[..GZIP_HEADER_LEN].copy_from_slice(&header);
+
+    let body_end = dst.len() - GZIP_TRAILER_LEN;
+    let len = GZIP_HEADER_LEN + deflate_stream_into(data, &mut dst[GZIP_HEADER_LEN..body_end], &Params::default())?;
+    dst[len..len + GZIP_TRAILER_LEN].copy_from_slice(&encode_trailer(crc32fast::hash(data), data.len() as u64));
+    Ok(len + GZIP_TRAILER_LEN)
+}
+
+/// Same as `compress_into`, for a zlib stream
+pub fn zlib_compress_into(data: &[u8], dst: &mut [u8]) -> Result<usize> {
+    if dst.len() < 6 {
+        return Err(Error::BufferTooSmall);
+    }
+    let params = Params::default();
+    dst[..2].copy_from_slice(&zlib_header(params.window_bits, params.level));
+
+    let body_end = dst.len() - 4;
+    let len = 2 + deflate_stream_into(data, &mut dst[2..body_end], &params)?;
+    dst[len..len + 4].copy_from_slice(&adler32(data).to_be_bytes());
+    Ok(len + 4)
+}
+
+/// Same as `compress_into`, for a raw deflate stream
+pub fn deflate_into(data: &[u8], dst: &mut [u8]) -> Result<usize> {
+    deflate_stream_into(data, dst, &Params::default())
+}
+
+/// Decompress gzip data directly into `dst`, and return the length of the decompressed data.
+/// Concatenated gzip members are decompressed one after another.
+/// Fail with `BufferTooSmall` if the decompressed data does not fit in `dst`; the content of `dst` is then unspecified.
+///
+/// Nothing is allocated: `dst` also holds the history that back references copy from,
+/// which makes this the fastest way to decompress when the decompressed size is known.
+pub fn decompress_into(data: &[u8], dst: &mut [u8]) -> Result<usize> {
+    let mut pos = 0;
+    let mut written = 0;
+
+    while pos < data.len() {
+        let (_, header_len) = parse_header(&data[pos..]).map_err(|err| err.offset_by(pos as u64))?;
+        pos += header_len;
+
+        let (len, deflate_len) = inflate_stream_into(&data[pos..], &mut dst[written..]).map_err(|err| err.offset_by(pos as u64))?;
+        pos += deflate_len;
+
+        let trailer = data.get(pos..pos + 8).ok_or(Error::TruncatedInput { bit_offset: data.len() as u64 * 8 })?;
+        let inflated = &dst[written..written + len];
+        check_trailer(trailer, crc32fast::hash(inflated), len as u64).map_err(|err| err.offset_by(pos as u64))?;
+        pos += 8;
+        written += len;
+    }
+
+    Ok(written)
+}
+
+/// Same as `decompress_into`, for a zlib stream. The data after the end of the stream is ignored.
+pub fn zlib_decompress_into(data: &[u8], dst: &mut [u8]) -> Result<usize> {
+    let pos = parse_zlib_header(data)?;
+    let (len, deflate_len) = inflate_stream_into(&data[pos..], dst).map_err(|err| err.offset_by(pos as u64))?;
+    let pos = pos + deflate_len;
+
+    let trailer = data.get(pos..pos + 4).ok_or(Error::TruncatedInput { bit_offset: data.len() as u64 * 8 })?;
+    check_zlib_trailer(trailer, adler32(&dst[..len])).map_err(|err| err.offset_by(pos as u64))?;
+    Ok(len)
+}
+
+/// Same as `decompress_into`, for a raw deflate stream. The data after the end of the stream is ignored.
+pub fn inflate_into(data: &[u8], dst: &mut [u8]) -> Result<usize> {
+    inflate_stream_into(data, dst).map(|(len, _)| len)
+}
+
+/// Decompress the gzip members of `data`, and return the decompressed data and the MTIME of the first member
+pub(crate) fn gunzip_members(data: &[u8], options: &DecompressOptions) -> Result<(Vec<u8>, Option<u32>)> {
+    let mut dst = Vec::new();
+    let mut header_mtime = None;
+    let mut pos = 0;
+    let mut members = 0;
+    let (max_output, output_limit) = options.output_budget(data.len() as u64);
+    let mut monitor = options.monitor();
+
+    while pos < data.len() {
+        members += 1;
+        if options.max_members.is_some_and(|max| members > max) {
+            return Err(Error::LimitExceeded(Limit::Members));
+        }
+
+        let (mtime, header_len) = parse_header_limited(&data[pos..], options.max_header_field()).map_err(|err| err.offset_by(pos as u64))?;
+        pos += header_len;
+        // the header of the first member describes the original file
+        header_mtime.get_or_insert(mtime);
+
+        let budget = usize::try_from(max_output - dst.len() as u64).unwrap_or(usize::MAX);
+        let on_block = |read, written| monitor.check((pos + read) as u64, (dst.len() + written) as u64);
+        let (inflated, deflate_len) = inflate_limited(&data[pos..], budget, on_block).map_err(|err| match err {
+            Error::LimitExceeded(Limit::OutputSize) => Error::LimitExceeded(output_limit),
+            err => err.offset_by(pos as u64)
+        })?;
+        pos += deflate_len;
+
+        let trailer = data.get(pos..pos + 8).ok_or(Error::TruncatedInput { bit_offset: data.len() as u64 * 8 })?;
+        check_trailer(trailer, crc32fast::hash(&inflated), inflated.len() as u64).map_err(|err| err.offset_by(pos as u64))?;
+        pos += 8;
+
+        if dst.is_empty() {
+            dst = inflated;
+        } else {
+            dst.extend_from_slice(&inflated);
+        }
+    }
+
+    monitor.finish(data.len() as u64, dst.len() as u64);
+    Ok((dst, header_mtime))
+}
+
+/// Decompress the raw deflate stream at `data[pos..]` within the output limits of `options`,
+/// and return the decompressed data and the length of the stream
+fn inflate_within(data: &[u8], pos: usize, options: &DecompressOptions) -> Result<(Vec<u8>, usize)> {
+    let (max_output, output_limit) = options.output_budget(data.len() as u64);
+    let mut monitor = options.monitor();
+    let on_block = |read, written| monitor.check((pos + read) as u64, written as u64);
+    let (dst, len) = inflate_limited(&data[pos..], usize::try_from(max_output).unwrap_or(usize::MAX), on_block).map_err(|err| match err {
+        Error::LimitExceeded(Limit::OutputSize) => Error::LimitExceeded(output_limit),
+        err => err.offset_by(pos as u64)
+    })?;
+    monitor.finish((pos + len) as u64, dst.len() as u64);
+    Ok((dst, len))
+}
+
+/// Compress `data` into a raw deflate stream as `options` tell, reporting the progress to the callback of `options`
+/// and recording the blocks in `stats` if it is given.
+/// Also return the CRC32 of `data` when it comes for free, i.e. from parallel compression.
+fn deflate_data(data: &[u8], options: &CompressOptions, stats: Option<&mut CompressionStats>) -> Result<(Vec<u8>, Option<u32>)> {
+    let params = options.deflate_params()?;
+    let mut monitor = options.monitor();
+    #[cfg(feature = "std")]
+    if options.threads > 1 && !options.rsyncable {
+        let (deflated, crc32) = crate::parallel::deflate_parallel(data, options.threads, &params, stats, &mut monitor)?;
+        monitor.finish(data.len() as u64, deflated.len() as u64);
+        return Ok((deflated, Some(crc32)));
+    }
+
+    let on_block = |read, written| monitor.check(read as u64, written as u64);
+    let deflated = if options.rsyncable {
+        deflate_rsyncable(data, &params, stats, on_block)?
+    } else {
+        deflate_with_progress(data, &params, stats, on_block)?
+    };
+    monitor.finish(data.len() as u64, deflated.len() as u64);
+    Ok((deflated, None))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+    use crate::stats::BlockKind;
+
+    fn sample() -> Vec<u8> {
+        (0..20_000u32).flat_map(|i| format!("{} ", i % 1234).into_bytes()).collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let raw = sample();
+        for level in [0, 1, 6, 9] {
+            let options = CompressOptions { level: Some(level), ..Default::default() };
+            let limits = DecompressOptions::default();
+            assert_eq!(gunzip_bytes(&gzip_bytes(&raw, &options).unwrap(), &limits).unwrap(), raw);
+            assert_eq!(zlib_decompress_bytes(&zlib_compress_bytes(&raw, &options).unwrap(), &limits).unwrap(), raw);
+            assert_eq!(inflate_bytes(&deflate_bytes(&raw, &options).unwrap(), &limits).unwrap(), raw);
+        }
+        assert_eq!(gunzip_bytes(&[], &DecompressOptions::default()).unwrap(), []);
+    }
+
+    #[test]
+    fn test_gzip_header() {
+        let options = CompressOptions {
+            name: Some(b"data.txt".to_vec()),
+            comment: Some(b"made in memory".to_vec()),
+            mtime: Some(0x12345678),
+            ..Default::default()
+        };
+        let gz = gzip_bytes(b"hello", &options).unwrap();
+        assert_eq!(gz[3], crate::member::FNAME | crate::member::FCOMMENT);
+        assert_eq!(gz[4..8], [0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(&gz[10..34], b"data.txt\0made in memory\0");
+        assert_eq!(gunzip_members(&gz, &DecompressOptions::default()).unwrap(), (b"hello".to_vec(), Some(0x12345678)));
+
+        // no_name drops the name
+        let gz = gzip_bytes(b"hello", &CompressOptions { no_name: true, ..options.clone() }).unwrap();
+        assert_eq!(gz[3], crate::member::FCOMMENT);
+
+        let options = CompressOptions { name: Some(vec![b'a', 0]), ..Default::default() };
+        assert!(matches!(gzip_bytes(b"hello", &options), Err(Error::InvalidOption("name"))));
+        let options = CompressOptions { level: Some(10), ..Default::default() };
+        assert!(matches!(gzip_bytes(b"hello", &options), Err(Error::InvalidOption("level"))));
+    }
+
+    #[test]
+    fn test_decompress_into() {
+        let raw = sample();
+        let options = CompressOptions::default();
+        let gz = gzip_bytes(&raw, &options).unwrap();
+        let mut dst = vec![0; raw.len() * 2];
+
+        // two members, back to back
+        assert_eq!(decompress_into(&[gz.as_slice(), &gz].concat(), &mut dst).unwrap(), raw.len() * 2);
+        assert_eq!(dst, [raw.as_slice(), &raw].concat());
+        assert!(matches!(decompress_into(&gz, &mut dst[..raw.len() - 1]), Err(Error::BufferTooSmall)));
+        assert_eq!(decompress_into(&[], &mut []).unwrap(), 0);
+
+        let zlib = zlib_compress_bytes(&raw, &options).unwrap();
+        assert_eq!(zlib_decompress_into(&zlib, &mut dst).unwrap(), raw.len());
+        assert_eq!(dst[..raw.len()], raw);
+        let deflated = deflate_bytes(&raw, &options).unwrap();
+        assert_eq!(inflate_into(&deflated, &mut dst[..raw.len()]).unwrap(), raw.len());
+
+        let mut corrupted = gz.clone();
+        let len = corrupted.len();
+        corrupted[len - 8] ^= 1;
+        assert!(matches!(decompress_into(&corrupted, &mut dst), Err(Error::DataCrc { offset, .. }) if offset == len as u64 - 8));
+    }
+
+    #[test]
+    fn test_compress_into() {
+        let random: Vec<u8> = (0..200_000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8).collect();
+        for raw in [Vec::new(), b"a".to_vec(), sample(), random] {
+            let bound = compress_bound(raw.len());
+            let mut dst = vec![0; bound];
+
+            let len = compress_into(&raw, &mut dst).unwrap();
+            assert!(len <= bound);
+            assert_eq!(dst[..len], gzip_bytes(&raw, &CompressOptions::default()).unwrap());
+            assert!(matches!(compress_into(&raw, &mut dst[..len - 1]), Err(Error::BufferTooSmall)));
+
+            let len = zlib_compress_into(&raw, &mut dst).unwrap();
+            assert_eq!(dst[..len], zlib_compress_bytes(&raw, &CompressOptions::default()).unwrap());
+            let len = deflate_into(&raw, &mut dst).unwrap();
+            assert_eq!(dst[..len], deflate_bytes(&raw, &CompressOptions::default()).unwrap());
+        }
+        assert!(matches!(compress_into(b"", &mut [0; 17]), Err(Error::BufferTooSmall)));
+    }
+
+    #[test]
+    fn test_progress() {
+        use crate::progress::{CancelToken, Progress};
+        use std::sync::{Arc, Mutex};
+
+        let raw: Vec<u8> = (0..150_000u32).flat_map(|i| format!("{} ", i % 54321).into_bytes()).collect();
+        for (threads, rsyncable) in [(1, false), (4, false), (1, true)] {
+            let calls = Arc::new(Mutex::new(Vec::new()));
+            let recorded = calls.clone();
+            let progress = Progress::new(50_000, move |read, written| recorded.lock().unwrap().push((read, written)));
+            let options = CompressOptions { threads, rsyncable, progress: Some(progress.clone()), ..Default::default() };
+            let gz = gzip_bytes(&raw, &options).unwrap();
+
+            let compressing = calls.lock().unwrap().split_off(0);
+            assert!(compressing.len() >= raw.len() / 200_000, "{:?}", compressing);
+            assert!(compressing.windows(2).all(|pair| pair[0].0 <= pair[1].0 && pair[0].1 <= pair[1].1), "{:?}", compressing);
+            assert_eq!(compressing.last(), Some(&(raw.len() as u64, gz.len() as u64 - 18)));
+
+            let limits = DecompressOptions { progress: Some(progress), ..Default::default() };
+            assert_eq!(gunzip_bytes(&gz, &limits).unwrap(), raw);
+            assert_eq!(calls.lock().unwrap().last(), Some(&(gz.len() as u64, raw.len() as u64)));
+        }
+
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        for threads in [1, 4] {
+            let options = CompressOptions { threads, cancel: Some(cancel.clone()), ..Default::default() };
+            assert!(matches!(gzip_bytes(&raw, &options), Err(Error::Cancelled)));
+        }
+        let gz = gzip_bytes(&raw, &CompressOptions::default()).unwrap();
+        let limits = DecompressOptions { cancel: Some(cancel), ..Default::default() };
+        assert!(matches!(gunzip_bytes(&gz, &limits), Err(Error::Cancelled)));
+        assert!(matches!(inflate_bytes(&gz[10..], &limits), Err(Error::Cancelled)));
+    }
+
+    #[test]
+    fn test_stats() {
+        let raw = sample();
+        let mut state = 1u32;
+        let random: Vec<u8> = (0..100_000).map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 23) as u8
+        }).collect();
+        for (data, level) in [(&raw, 6), (&raw, 1), (&raw, 0), (&random, 6)] {
+            let options = CompressOptions { level: Some(level), ..Default::default() };
+            let (gz, stats) = gzip_bytes_with_stats(data, &options).unwrap();
+            assert_eq!(gz, gzip_bytes(data, &options).unwrap());
+
+            assert_eq!(stats.blocks.iter().map(|block| block.input_bytes).sum::<u64>(), data.len() as u64);
+            assert_eq!(stats.blocks.iter().map(|block| block.output_bits).sum::<u64>().div_ceil(8), gz.len() as u64 - 18);
+            assert_eq!(stats.length_codes.iter().sum::<u64>(), stats.matches);
+            assert_eq!(stats.distance_codes.iter().sum::<u64>(), stats.matches);
+            let stored: u64 = stats.blocks.iter().filter(|block| block.kind == BlockKind::Stored).map(|block| block.input_bytes).sum();
+            assert_eq!(stats.literals + stats.match_bytes + stored, data.len() as u64);
+            if level == 0 || data == &random {
+                assert_eq!(stats.block_count(BlockKind::Stored), stats.blocks.len());
+            } else {
+                assert!(stats.average_match_length() > 3.0);
+            }
+        }
+
+        // the blocks of all the chunks, or between all the flush points
+        for options in [CompressOptions { threads: 4, ..Default::default() }, CompressOptions { rsyncable: true, ..Default::default() }] {
+            let (gz, stats) = gzip_bytes_with_stats(&raw, &options).unwrap();
+            assert_eq!(gz, gzip_bytes(&raw, &options).unwrap());
+            assert_eq!(stats.blocks.iter().map(|block| block.input_bytes).sum::<u64>(), raw.len() as u64);
+            assert_eq!(stats.literals + stats.match_bytes, raw.len() as u64);
+        }
+    }
+
+    #[test]
+    fn test_zlib_errors() {
+        let mut zlib = zlib_compress_bytes(b"hello", &CompressOptions::default()).unwrap();
+        let len = zlib.len();
+        zlib[len - 1] ^= 1;
+        let result = zlib_decompress_bytes(&zlib, &DecompressOptions::default());
+        assert!(matches!(result, Err(Error::DataAdler32 { offset, .. }) if offset == len as u64 - 4));
+        assert!(matches!(zlib_decompress_bytes(&zlib[..len - 2], &DecompressOptions::default()), Err(Error::TruncatedInput { .. })));
+
+        let limits = DecompressOptions { max_output: Some(4), ..Default::default() };
+        let zlib = zlib_compress_bytes(b"hello", &CompressOptions::default()).unwrap();
+        assert!(matches!(zlib_decompress_bytes(&zlib, &limits), Err(Error::LimitExceeded(Limit::OutputSize))));
+    }
+}
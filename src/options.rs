@@ -0,0 +1,130 @@
+use alloc::vec::Vec;
+use crate::deflate::{Params, MAX_LEVEL, MAX_MEM_LEVEL, MAX_WINDOW_BITS, MIN_MEM_LEVEL, MIN_WINDOW_BITS};
+use crate::error::{Error, Limit, Result};
+use crate::progress::{CancelToken, Monitor, Progress};
+
+/// How the compressor looks for matches and codes the blocks, like zlib's strategy parameter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Strategy {
+    /// Look for the longest matches, and pick dynamic or fixed huffman codes for each block, whichever is smaller
+    #[default]
+    Default,
+    /// Drop the short matches in favor of literals, for data made of small values with a somewhat random
+    /// distribution, such as filtered PNG rows
+    Filtered,
+    /// Do not look for matches at all: every byte is a literal, coded with the huffman codes of its block
+    HuffmanOnly,
+    /// Only look for runs of the previous byte (matches at distance 1), for image data and the like
+    Rle,
+    /// Always use the fixed huffman codes, which makes for small blocks when the data is short
+    Fixed,
+}
+
+/// Options for gzip compression.
+///
+/// The compressed data only depends on the input and these options, so two runs with identical options
+/// produce byte-identical output. With `no_name` set, the header doesn't depend on the source file either.
+/// The header fields only apply to gzip; zlib and raw deflate streams have no header fields.
+#[derive(Debug, Clone, Default)]
+pub struct CompressOptions {
+    /// The compression level, from 0 (no compression, stored blocks) to 9 (best compression). The default is 6.
+    pub level: Option<u8>,
+    /// Do not store the original file name (FNAME) in the header.
+    /// Unless `mtime` is given, the modification time is not stored either and MTIME is set to zero.
+    pub no_name: bool,
+    /// The file name (FNAME) to store in the header, instead of the name of the source file.
+    /// It is stored as is, and must not contain a zero byte.
+    pub name: Option<Vec<u8>>,
+    /// A comment (FCOMMENT) to store in the header. It must not contain a zero byte.
+    pub comment: Option<Vec<u8>>,
+    /// The modification time to store in the header, in seconds since the Unix epoch,
+    /// instead of the modification time of the source file (e.g. `SOURCE_DATE_EPOCH` for reproducible builds).
+    pub mtime: Option<u32>,
+    /// The number of threads to compress with. With more than one, the input is split into chunks that are
+    /// compressed in parallel, and the output is the same for any number of threads above one.
+    /// 0 and 1 compress on the calling thread without chunks, which gives a different (slightly smaller) output.
+    pub threads: usize,
+    /// Make the output rsync-friendly: the compressed data is reset at content-defined points of the input,
+    /// so a local change in the input only changes the output around it. The output is slightly larger,
+    /// and is compressed on the calling thread whatever `threads` is.
+    pub rsyncable: bool,
+    /// The base-2 logarithm of the window size, from 8 (256 bytes) to 15 (32 KiB, the default), like zlib's windowBits.
+    /// A smaller window compresses less, but the decoder needs less memory for the history.
+    pub window_bits: Option<u8>,
+    /// How much memory the compressor uses for finding matches, from 1 to 9 (the default is 8), like zlib's memLevel
+    pub mem_level: Option<u8>,
+    /// How to look for matches and code the blocks, like zlib's strategy (the default is `Strategy::Default`)
+    pub strategy: Strategy,
+    /// A callback told the number of bytes read and compressed so far, see `Progress`
+    pub progress: Option<Progress>,
+    /// A token to stop the compression with `Error::Cancelled`
+    pub cancel: Option<CancelToken>,
+}
+
+impl CompressOptions {
+    pub(crate) fn deflate_params(&self) -> Result<Params> {
+        let defaults = Params::default();
+        let level = self.level.unwrap_or(defaults.level);
+        let window_bits = self.window_bits.unwrap_or(defaults.window_bits);
+        let mem_level = self.mem_level.unwrap_or(defaults.mem_level);
+        if level > MAX_LEVEL {
+            return Err(Error::InvalidOption("level"));
+        }
+        if !(MIN_WINDOW_BITS..=MAX_WINDOW_BITS).contains(&window_bits) {
+            return Err(Error::InvalidOption("window_bits"));
+        }
+        if !(MIN_MEM_LEVEL..=MAX_MEM_LEVEL).contains(&mem_level) {
+            return Err(Error::InvalidOption("mem_level"));
+        }
+        Ok(Params { level, window_bits, mem_level, strategy: self.strategy })
+    }
+
+    pub(crate) fn monitor(&self) -> Monitor {
+        Monitor::new(self.progress.as_ref(), self.cancel.as_ref())
+    }
+}
+
+/// The longest gzip header field accepted when `DecompressOptions::max_header_field` is not set
+pub const DEFAULT_MAX_HEADER_FIELD: usize = 64 * 1024;
+
+/// Limits for decompressing untrusted data, to stop a small input from using up memory (a "decompression bomb").
+///
+/// Decoding fails with `Error::LimitExceeded` as soon as it would go past a limit, before the output is allocated.
+/// All limits but `max_header_field` are off by default. The options also take a progress callback and a cancel token for long decompressions.
+#[derive(Debug, Clone, Default)]
+pub struct DecompressOptions {
+    /// The most bytes of decompressed data, over all members
+    pub max_output: Option<u64>,
+    /// The most bytes of decompressed data per byte of compressed data
+    pub max_ratio: Option<u64>,
+    /// The most gzip members
+    pub max_members: Option<u64>,
+    /// The longest file name (FNAME), comment (FCOMMENT) or extra field (FEXTRA) of a gzip header, in bytes
+    /// (`DEFAULT_MAX_HEADER_FIELD` if not set)
+    pub max_header_field: Option<usize>,
+    /// A callback told the number of bytes of compressed data read and of decompressed data written so far, see `Progress`
+    pub progress: Option<Progress>,
+    /// A token to stop the decompression with `Error::Cancelled`
+    pub cancel: Option<CancelToken>,
+}
+
+impl DecompressOptions {
+    /// The most bytes that may be decompressed from `input_len` bytes, and the limit that sets it
+    pub(crate) fn output_budget(&self, input_len: u64) -> (u64, Limit) {
+        let by_size = self.max_output.unwrap_or(u64::MAX);
+        let by_ratio = self.max_ratio.map_or(u64::MAX, |ratio| ratio.saturating_mul(input_len));
+        if by_ratio < by_size {
+            (by_ratio, Limit::Ratio)
+        } else {
+            (by_size, Limit::OutputSize)
+        }
+    }
+
+    pub(crate) fn max_header_field(&self) -> usize {
+        self.max_header_field.unwrap_or(DEFAULT_MAX_HEADER_FIELD)
+    }
+
+    pub(crate) fn monitor(&self) -> Monitor {
+        Monitor::new(self.progress.as_ref(), self.cancel.as_ref())
+    }
+}
@@ -0,0 +1,111 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use crate::checksum::crc32_combine;
+use crate::deflate::{deflate_chunk, Params};
+use crate::error::Result;
+use crate::progress::Monitor;
+use crate::stats::CompressionStats;
+
+/// The input is compressed in chunks of this size, one chunk per job
+const CHUNK_SIZE: usize = 128 * 1024;
+
+/// Compress `src` into a raw deflate stream on `threads` worker threads, the way pigz does.
+/// Return the stream and the CRC32 of `src`.
+///
+/// Each chunk is compressed on its own with the window before it as a dictionary, and all but the last end with a sync flush,
+/// so the compressed chunks are simply concatenated. The CRC32 of each chunk is computed by its worker and the results are combined.
+/// The output does not depend on the number of threads.
+///
+/// The blocks of the chunks are recorded in `stats` if it is given.
+/// `monitor` is checked as the chunks come in, with the input and output of the chunks done so far.
+/// When it fails, the workers stop after their current chunk.
+pub(crate) fn deflate_parallel(src: &[u8], threads: usize, params: &Params, mut stats: Option<&mut CompressionStats>,
+                               monitor: &mut Monitor) -> Result<(Vec<u8>, u32)> {
+    let chunks = src.len().div_ceil(CHUNK_SIZE).max(1);
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+    let mut results: Vec<_> = (0..chunks).map(|_| None).collect();
+    let with_stats = stats.is_some();
+
+    thread::scope(|scope| {
+        for _ in 0..threads.min(chunks) {
+            let sender = sender.clone();
+            let next = &next;
+            scope.spawn(move || loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                if index >= chunks {
+                    break;
+                }
+                let start = index * CHUNK_SIZE;
+                let end = (start + CHUNK_SIZE).min(src.len());
+
+                let crc32 = crc32fast::hash(&src[start..end]);
+                let mut chunk_stats = with_stats.then(CompressionStats::default);
+                let result = deflate_chunk(src, start, end, params, chunk_stats.as_mut())
+                    .map(|data| (data, crc32, (end - start) as u64, chunk_stats));
+                if sender.send((index, result)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+
+        let mut read = 0;
+        let mut written = 0;
+        for (index, result) in receiver {
+            let checked = result.and_then(|result| {
+                read += result.2;
+                written += result.0.len() as u64;
+                monitor.check(read, written)?;
+                Ok(result)
+            });
+            match checked {
+                Ok(result) => results[index] = Some(result),
+                Err(err) => {
+                    // no more chunks are started
+                    next.store(chunks, Ordering::Relaxed);
+                    return Err(err);
+                }
+            }
+        }
+        Ok(())
+    })?;
+
+    let mut dst = Vec::new();
+    let mut crc32 = 0;
+    for (data, chunk_crc32, len, chunk_stats) in results.into_iter().map(Option::unwrap) {
+        dst.extend_from_slice(&data);
+        crc32 = crc32_combine(crc32, chunk_crc32, len);
+        if let (Some(stats), Some(chunk_stats)) = (stats.as_deref_mut(), chunk_stats) {
+            stats.append(chunk_stats);
+        }
+    }
+
+    Ok((dst, crc32))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deflate::{deflate, inflate};
+
+    #[test]
+    fn test_deflate_parallel() {
+        let raw: Vec<u8> = (0..CHUNK_SIZE as u32 * 3 + 1000).map(|i| ((i % 251) ^ (i / 4000)) as u8).collect();
+
+        let (dst, crc32) = deflate_parallel(&raw, 4, &Params::default(), None, &mut Monitor::default()).unwrap();
+        assert_eq!(crc32, crc32fast::hash(&raw));
+        assert_eq!(inflate(&dst).unwrap(), (raw.clone(), dst.len()));
+        // the same chunks whatever the number of threads
+        assert_eq!(deflate_parallel(&raw, 2, &Params::default(), None, &mut Monitor::default()).unwrap().0, dst);
+    }
+
+    #[test]
+    fn test_deflate_parallel_small() {
+        // a single chunk is the same as compressing on one thread
+        assert_eq!(deflate_parallel(b"abcabcabc", 8, &Params::default(), None, &mut Monitor::default()).unwrap(), (deflate(b"abcabcabc").unwrap(), crc32fast::hash(b"abcabcabc")));
+        assert_eq!(deflate_parallel(&[], 8, &Params::default(), None, &mut Monitor::default()).unwrap(), (deflate(&[]).unwrap(), 0));
+    }
+}
@@ -0,0 +1,122 @@
+//! Progress reports and cancellation of long compressions and decompressions
+
+use alloc::sync::Arc;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+use crate::error::{Error, Result};
+
+/// A callback told how far a compression or decompression got, with the number of bytes read and written so far.
+///
+/// It is called between blocks, once at least `interval` more bytes of input were read since the previous call,
+/// and once more at the end with the final counts. The counts never go down.
+#[derive(Clone)]
+pub struct Progress {
+    callback: Arc<dyn Fn(u64, u64) + Send + Sync>,
+    interval: u64,
+}
+
+impl Progress {
+    pub fn new<F: Fn(u64, u64) + Send + Sync + 'static>(interval: u64, callback: F) -> Self {
+        Progress { callback: Arc::new(callback), interval: interval.max(1) }
+    }
+}
+
+impl fmt::Debug for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Progress").field("interval", &self.interval).finish_non_exhaustive()
+    }
+}
+
+/// A flag to stop a compression or decompression from elsewhere, e.g. another thread or a signal handler.
+///
+/// The clones of a token share the flag. The work checks it between blocks, and fails with `Error::Cancelled`
+/// once it is set, without writing any output file.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// The progress callback and the cancel token of a single run, checked between blocks
+#[derive(Default)]
+pub(crate) struct Monitor {
+    progress: Option<Progress>,
+    cancel: Option<CancelToken>,
+    /// The counts of the last report
+    reported: Option<(u64, u64)>,
+}
+
+impl Monitor {
+    /// A monitor sharing the callback and the flag of the token
+    pub fn new(progress: Option<&Progress>, cancel: Option<&CancelToken>) -> Self {
+        Monitor { progress: progress.cloned(), cancel: cancel.cloned(), reported: None }
+    }
+
+    /// Start a new run, whose counts start over from zero
+    pub fn restart(&mut self) {
+        self.reported = None;
+    }
+
+    /// Fail with `Cancelled` if the token was set, and report the counts if the interval has passed since the last report
+    pub fn check(&mut self, read: u64, written: u64) -> Result<()> {
+        if self.cancel.as_ref().is_some_and(CancelToken::is_cancelled) {
+            return Err(Error::Cancelled);
+        }
+        if let Some(progress) = &self.progress {
+            if read >= self.reported.map_or(0, |(read, _)| read) + progress.interval {
+                (progress.callback)(read, written);
+                self.reported = Some((read, written));
+            }
+        }
+        Ok(())
+    }
+
+    /// Report the final counts, unless they were just reported
+    pub fn finish(&mut self, read: u64, written: u64) {
+        if let Some(progress) = &self.progress {
+            if self.reported != Some((read, written)) {
+                (progress.callback)(read, written);
+                self.reported = Some((read, written));
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_monitor() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let recorded = calls.clone();
+        let progress = Progress::new(100, move |read, written| recorded.lock().unwrap().push((read, written)));
+        let cancel = CancelToken::new();
+
+        let mut monitor = Monitor::new(Some(&progress), Some(&cancel));
+        for read in (0..=350).step_by(50) {
+            monitor.check(read, read / 2).unwrap();
+        }
+        monitor.finish(360, 180);
+        monitor.finish(360, 180);
+        assert_eq!(*calls.lock().unwrap(), [(100, 50), (200, 100), (300, 150), (360, 180)]);
+
+        cancel.clone().cancel();
+        assert!(matches!(monitor.check(400, 200), Err(Error::Cancelled)));
+        assert!(Monitor::default().check(400, 200).is_ok());
+    }
+}
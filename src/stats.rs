@@ -0,0 +1,156 @@
+//! Statistics of a compression, to tune the level and the strategy for some kind of data
+
+use alloc::vec::Vec;
+use core::time::Duration;
+use crate::deflate::{match_codes, Symbol};
+
+/// How a block of the deflate stream is coded (BTYPE)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockKind {
+    Stored,
+    Fixed,
+    Dynamic,
+}
+
+/// A block of the deflate stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockStats {
+    pub kind: BlockKind,
+    /// The number of bytes of input the block holds
+    pub input_bytes: u64,
+    /// The size of the block in bits, header included. Only stored blocks start at a byte boundary.
+    pub output_bits: u64,
+}
+
+/// What the compressor did with the input, see `gzip_bytes_with_stats`.
+///
+/// The literals and the matches are those of the huffman blocks: a stored block holds its input as is.
+/// The empty blocks that end flushes are not counted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompressionStats {
+    /// The blocks, in the order of the stream
+    pub blocks: Vec<BlockStats>,
+    pub literals: u64,
+    pub matches: u64,
+    /// The total length of the matches
+    pub match_bytes: u64,
+    /// The number of matches by length code: `length_codes[i]` counts the lengths coded with symbol 257 + i
+    pub length_codes: [u64; 29],
+    /// The number of matches by distance code, 0 to 29
+    pub distance_codes: [u64; 30],
+    /// The time spent finding the matches, summed over the threads. Always zero without the `std` feature, which has the clock.
+    pub lz77_time: Duration,
+    /// The time spent choosing the codes and writing the blocks, summed over the threads
+    pub huffman_time: Duration,
+}
+
+impl CompressionStats {
+    /// The number of blocks coded as `kind`
+    pub fn block_count(&self, kind: BlockKind) -> usize {
+        self.blocks.iter().filter(|block| block.kind == kind).count()
+    }
+
+    /// The average length of the matches, or 0 if there are none
+    pub fn average_match_length(&self) -> f64 {
+        if self.matches == 0 {
+            0.0
+        } else {
+            self.match_bytes as f64 / self.matches as f64
+        }
+    }
+
+    /// Record a block, and the symbols it codes if it is a huffman block
+    pub(crate) fn add_block(&mut self, block: BlockStats, symbols: &[Symbol], lz77_time: Duration, huffman_time: Duration) {
+        if block.kind != BlockKind::Stored {
+            for symbol in symbols {
+                match *symbol {
+                    Symbol::Literal(_) => self.literals += 1,
+                    Symbol::Pointer { length, distance } => {
+                        let (length_code, distance_code) = match_codes(length, distance);
+                        self.matches += 1;
+                        self.match_bytes += length as u64 + 3;
+                        self.length_codes[length_code] += 1;
+                        self.distance_codes[distance_code] += 1;
+                    }
+                }
+            }
+        }
+        self.blocks.push(block);
+        self.lz77_time += lz77_time;
+        self.huffman_time += huffman_time;
+    }
+
+    /// Add the statistics of the input that follows
+    pub(crate) fn append(&mut self, other: CompressionStats) {
+        self.blocks.extend(other.blocks);
+        self.literals += other.literals;
+        self.matches += other.matches;
+        self.match_bytes += other.match_bytes;
+        for (count, other) in self.length_codes.iter_mut().zip(other.length_codes) {
+            *count += other;
+        }
+        for (count, other) in self.distance_codes.iter_mut().zip(other.distance_codes) {
+            *count += other;
+        }
+        self.lz77_time += other.lz77_time;
+        self.huffman_time += other.huffman_time;
+    }
+}
+
+/// Measures the time of a stage for `CompressionStats`. Without the `std` feature there is no clock, and it measures zero.
+pub(crate) struct Stopwatch {
+    #[cfg(feature = "std")]
+    start: std::time::Instant,
+}
+
+impl Stopwatch {
+    pub fn start() -> Self {
+        Stopwatch {
+            #[cfg(feature = "std")]
+            start: std::time::Instant::now(),
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        #[cfg(feature = "std")]
+        return self.start.elapsed();
+        #[cfg(not(feature = "std"))]
+        Duration::ZERO
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_block() {
+        let symbols = [
+            Symbol::Literal(b'a'),
+            Symbol::Pointer { length: 0, distance: 1 },
+            Symbol::Pointer { length: 255, distance: 32768 },
+            Symbol::Pointer { length: 8, distance: 5 },
+        ];
+        let block = BlockStats { kind: BlockKind::Dynamic, input_bytes: 273, output_bits: 100 };
+        let mut stats = CompressionStats::default();
+        stats.add_block(block, &symbols, Duration::from_millis(2), Duration::from_millis(1));
+
+        assert_eq!((stats.literals, stats.matches, stats.match_bytes), (1, 3, 3 + 258 + 11));
+        // lengths 3, 258 and 11, distances 1, 32768 and 5
+        assert_eq!((stats.length_codes[0], stats.length_codes[28], stats.length_codes[8]), (1, 1, 1));
+        assert_eq!((stats.distance_codes[0], stats.distance_codes[29], stats.distance_codes[4]), (1, 1, 1));
+        assert_eq!(stats.average_match_length(), 272.0 / 3.0);
+
+        // the symbols of a stored block are not coded
+        let stored = BlockStats { kind: BlockKind::Stored, input_bytes: 273, output_bits: 2220 };
+        let mut other = CompressionStats::default();
+        other.add_block(stored, &symbols, Duration::ZERO, Duration::ZERO);
+        assert_eq!(other.literals + other.matches, 0);
+
+        stats.append(other);
+        assert_eq!(stats.blocks, [block, stored]);
+        assert_eq!((stats.block_count(BlockKind::Dynamic), stats.block_count(BlockKind::Stored), stats.block_count(BlockKind::Fixed)), (1, 1, 0));
+        assert_eq!((stats.matches, stats.lz77_time), (3, Duration::from_millis(2)));
+    }
+}
@@ -0,0 +1,739 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use crc32fast::Hasher;
+use crate::checksum::{adler32, adler32_combine};
+use crate::deflate::{self, Deflater, Inflater, Params};
+use crate::error::{Error, Limit, Result};
+use crate::member::{check_trailer, encode_header, encode_trailer, parse_header_limited, Header, OS_UNIX};
+use crate::options::{CompressOptions, DecompressOptions};
+use crate::progress::Monitor;
+use crate::zlib::{check_zlib_trailer, parse_zlib_header, zlib_header};
+
+/// The container around the deflate stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// A bare deflate stream (RFC 1951)
+    Raw,
+    /// A zlib stream (RFC 1950): a 2-byte header, and the Adler-32 of the data in the trailer
+    Zlib,
+    /// A single gzip member (RFC 1952): a header, and the CRC32 and the length of the data in the trailer
+    #[default]
+    Gzip,
+}
+
+impl Format {
+    fn trailer_size(self) -> usize {
+        match self {
+            Format::Raw => 0,
+            Format::Zlib => 4,
+            Format::Gzip => 8,
+        }
+    }
+}
+
+/// What `Compressor::compress` does after taking the input, like the flush parameter of zlib's `deflate`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Flush {
+    /// Keep the input to compress it with more data. The output may lag behind the input.
+    #[default]
+    None,
+    /// Push out all the input so far, except possibly its last few bits, with an empty fixed huffman block
+    Partial,
+    /// Push out all the input so far and pad the output to a byte boundary, with an empty stored block
+    Sync,
+    /// Same as `Sync`, and also forget the history, so that decoding can start from scratch after this point
+    Full,
+    /// End the stream once all the input is taken. It must be passed again until `State::StreamEnd` is returned.
+    Finish,
+}
+
+/// The input is written to the deflater this many bytes at a time, checking in between whether the output is full
+const INPUT_PIECE: usize = 16 * 1024;
+
+/// The least input added to an incomplete header before parsing it again
+const MIN_HEADER_PIECE: usize = 64;
+
+/// How a call to `compress` or `decompress` ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// Some progress was made, and the call should be repeated with more input or more output space
+    Ok,
+    /// No progress was possible: the input is empty and nothing is waiting to be written, or the output has no space
+    BufError,
+    /// The end of the stream was written or decoded, and all the output was handed out
+    StreamEnd,
+}
+
+/// The result of a call to `compress` or `decompress`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Status {
+    /// The number of bytes taken from the input
+    pub consumed: usize,
+    /// The number of bytes written to the output
+    pub produced: usize,
+    pub state: State,
+}
+
+impl Status {
+    fn new(consumed: usize, produced: usize, stream_end: bool) -> Self {
+        let state = if stream_end {
+            State::StreamEnd
+        } else if consumed == 0 && produced == 0 {
+            State::BufError
+        } else {
+            State::Ok
+        };
+        Status { consumed, produced, state }
+    }
+}
+
+/// A compressor driven by the caller with input and output buffers, in the style of zlib's `z_stream`.
+///
+/// `compress` never blocks: it takes the input it can, writes the output that fits, and tells how far it got,
+/// so it can be driven from an event loop. It stops taking input once the output is full, and the compressed data
+/// that does not fit, about a block at most, is kept and written by the next calls, which take no more input until it is all out.
+///
+/// The compressor owns all its buffers and tables, and `reset` starts a new stream with them,
+/// so compressing many small inputs does not allocate for each of them.
+pub struct Compressor {
+    format: Format,
+    params: Params,
+    deflater: Deflater,
+    crc32: Hasher,
+    adler32: u32,
+    len: u64,
+    /// The number of bytes written to the output so far, for the progress reports
+    written: u64,
+    monitor: Monitor,
+    /// The compressed data not written to the output yet, from `pending_pos`
+    pending: Vec<u8>,
+    pending_pos: usize,
+    header_written: bool,
+    finished: bool,
+}
+
+impl Compressor {
+    pub fn new(format: Format) -> Self {
+        Compressor::with_params(format, Params::default(), Monitor::default())
+    }
+
+    /// Compress with the window size, memory level and strategy of `options`, reporting to its progress callback
+    /// and stopping with `Error::Cancelled` once its cancel token is set. Both are checked between pieces of the input,
+    /// with the bytes taken and written so far. The other options are about files, and do not apply.
+    pub fn with_options(format: Format, options: &CompressOptions) -> Result<Self> {
+        Ok(Compressor::with_params(format, options.deflate_params()?, options.monitor()))
+    }
+
+    fn with_params(format: Format, params: Params, monitor: Monitor) -> Self {
+        Compressor {
+            format,
+            params,
+            deflater: Deflater::with_params(&params),
+            crc32: Hasher::new(),
+            adler32: 1,
+            len: 0,
+            written: 0,
+            monitor,
+            pending: Vec::new(),
+            pending_pos: 0,
+            header_written: false,
+            finished: false,
+        }
+    }
+
+    /// Start a new stream with the same format and settings, keeping the buffers and tables.
+    /// The compressed data of the current stream that was not written out yet is dropped.
+    pub fn reset(&mut self) {
+        self.deflater.reset();
+        self.crc32.reset();
+        self.adler32 = 1;
+        self.len = 0;
+        self.written = 0;
+        self.monitor.restart();
+        self.pending.clear();
+        self.pending_pos = 0;
+        self.header_written = false;
+        self.finished = false;
+    }
+
+    /// The checksum of the input so far: the CRC-32 for gzip, the Adler-32 for zlib, and 1 for raw deflate
+    pub fn checksum(&self) -> u32 {
+        match self.format {
+            Format::Gzip => self.crc32.clone().finalize(),
+            _ => self.adler32,
+        }
+    }
+
+    /// Take the input, compress it as `flush` tells, and write as much of the compressed data as fits in the output.
+    /// Once the output is full, the rest of the input is not taken, and the flush waits until it is passed again with it.
+    pub fn compress(&mut self, input: &[u8], output: &mut [u8], flush: Flush) -> Result<Status> {
+        let mut produced = self.drain(output);
+        if self.pending_pos < self.pending.len() || self.finished {
+            return Ok(self.status(0, produced));
+        }
+        self.pending.clear();
+        self.pending_pos = 0;
+
+        if !self.header_written {
+            match self.format {
+                Format::Raw => {},
+                Format::Zlib => self.pending.extend_from_slice(&zlib_header(self.params.window_bits, self.params.level)),
+                Format::Gzip => encode_header(&Header { mtime: 0, extra: None, name: None, comment: None, os: OS_UNIX }, &mut self.pending),
+            }
+            self.header_written = true;
+        }
+
+        // take the input a piece at a time, and stop once the output is full,
+        // so that no more than about a block of compressed data is kept for the next calls
+        let mut consumed = 0;
+        loop {
+            produced += self.drain(&mut output[produced..]);
+            if consumed == input.len() || self.pending_pos < self.pending.len() {
+                break;
+            }
+            self.pending.clear();
+            self.pending_pos = 0;
+
+            self.monitor.check(self.len, self.written + produced as u64)?;
+            let piece = &input[consumed..(consumed + INPUT_PIECE).min(input.len())];
+            self.deflater.write(piece)?;
+            match self.format {
+                Format::Raw => {},
+                Format::Zlib => self.adler32 = adler32_combine(self.adler32, adler32(piece), piece.len() as u64),
+                Format::Gzip => self.crc32.update(piece),
+            }
+            self.len += piece.len() as u64;
+            consumed += piece.len();
+            self.deflater.move_output_to(&mut self.pending);
+        }
+
+        // the flush applies once all the input is taken, the caller passes it again with the rest
+        if consumed == input.len() {
+            match flush {
+                Flush::None => {},
+                Flush::Finish => {
+                    self.deflater.finish_to(&mut self.pending)?;
+                    match self.format {
+                        Format::Raw => {},
+                        Format::Zlib => self.pending.extend_from_slice(&self.adler32.to_be_bytes()),
+                        Format::Gzip => self.pending.extend_from_slice(&encode_trailer(self.crc32.clone().finalize(), self.len)),
+                    }
+                    self.finished = true;
+                }
+                Flush::Partial => self.deflater.flush(deflate::Flush::Partial)?,
+                Flush::Sync => self.deflater.flush(deflate::Flush::Sync)?,
+                Flush::Full => self.deflater.flush(deflate::Flush::Full)?,
+            }
+            self.deflater.move_output_to(&mut self.pending);
+            produced += self.drain(&mut output[produced..]);
+        }
+        Ok(self.status(consumed, produced))
+    }
+
+    /// The status of a call, counting its output and reporting the final counts once the stream is out
+    fn status(&mut self, consumed: usize, produced: usize) -> Status {
+        self.written += produced as u64;
+        if self.is_done() {
+            self.monitor.finish(self.len, self.written);
+        }
+        Status::new(consumed, produced, self.is_done())
+    }
+
+    fn drain(&mut self, output: &mut [u8]) -> usize {
+        let pending = &self.pending[self.pending_pos..];
+        let len = pending.len().min(output.len());
+        output[..len].copy_from_slice(&pending[..len]);
+        self.pending_pos += len;
+        len
+    }
+
+    fn is_done(&self) -> bool {
+        self.finished && self.pending_pos == self.pending.len()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Header,
+    Body,
+    Trailer,
+    Done,
+}
+
+/// A decompressor driven by the caller with input and output buffers, the counterpart of `Compressor`.
+///
+/// `decompress` never blocks: it decodes the input straight into the output, and stops when the input runs out
+/// or the output is full. Only the header is buffered until it is complete; in the body, a code or a match
+/// that is cut short is kept in the state of the decoder, so the input is decoded once whatever the size of the pieces.
+/// The stream ends after the trailer, and the input after it is not consumed, e.g. the next member of a gzip file.
+/// Like `Compressor`, it can be `reset` to decode another stream with the same buffers.
+pub struct Decompressor {
+    format: Format,
+    limits: DecompressOptions,
+    /// The number of bytes taken from the input so far, for the ratio limit
+    taken: u64,
+    stage: Stage,
+    /// The input while the header is incomplete
+    header: Vec<u8>,
+    /// Boxed, as the state of the decoder with its tables is much larger than the rest
+    inflater: Box<Inflater>,
+    /// The trailer, while it is incomplete
+    trailer: Vec<u8>,
+    crc32: Hasher,
+    adler32: u32,
+    len: u64,
+    monitor: Monitor,
+}
+
+impl Decompressor {
+    pub fn new(format: Format) -> Self {
+        Decompressor::with_options(format, &DecompressOptions::default())
+    }
+
+    /// Decompress within the limits of `options`. The ratio limit is checked against the input taken so far.
+    /// `max_members` does not apply, as the stream is a single member.
+    /// The progress callback and the cancel token of `options` are checked at each call to `decompress`,
+    /// with the bytes taken and decompressed so far.
+    pub fn with_options(format: Format, options: &DecompressOptions) -> Self {
+        Decompressor {
+            format,
+            limits: options.clone(),
+            taken: 0,
+            stage: if format == Format::Raw { Stage::Body } else { Stage::Header },
+            header: Vec::new(),
+            inflater: Box::new(Inflater::new()),
+            trailer: Vec::new(),
+            crc32: Hasher::new(),
+            adler32: 1,
+            len: 0,
+            monitor: options.monitor(),
+        }
+    }
+
+    /// Start decoding a new stream with the same format and limits, keeping the buffers.
+    pub fn reset(&mut self) {
+        self.taken = 0;
+        self.stage = if self.format == Format::Raw { Stage::Body } else { Stage::Header };
+        self.header.clear();
+        self.inflater.reset(0, &[]);
+        self.trailer.clear();
+        self.crc32.reset();
+        self.adler32 = 1;
+        self.len = 0;
+        self.monitor.restart();
+    }
+
+    /// The checksum of the output so far: the CRC-32 for gzip, the Adler-32 for zlib, and 1 for raw deflate
+    pub fn checksum(&self) -> u32 {
+        match self.format {
+            Format::Gzip => self.crc32.clone().finalize(),
+            _ => self.adler32,
+        }
+    }
+
+    /// Parse the header at the start of `data`, and return its length
+    fn parse_header(&self, data: &[u8]) -> Result<usize> {
+        match self.format {
+            Format::Zlib => parse_zlib_header(data),
+            _ => parse_header_limited(data, self.limits.max_header_field()).map(|(_, len)| len),
+        }
+    }
+
+    /// Take the input, and write as much of the decompressed data as fits in the output.
+    /// `Flush::Finish` tells that the input ends here, so a stream that is cut short is an error; the other modes are the same.
+    pub fn decompress(&mut self, input: &[u8], output: &mut [u8], flush: Flush) -> Result<Status> {
+        let mut consumed = 0;
+        let mut produced = 0;
+        if self.stage != Stage::Done {
+            self.monitor.check(self.taken, self.len)?;
+        }
+
+        loop {
+            match self.stage {
+                Stage::Header => {
+                    // the input is parsed in place, and only buffered while the header is cut short
+                    let buffered = self.header.len();
+                    let parsed = if buffered == 0 {
+                        self.parse_header(input)
+                    } else {
+                        // add as much again as is buffered at a time, so that the header is not copied with all the input after it
+                        loop {
+                            let added = self.header.len() - buffered;
+                            let len = self.header.len().max(MIN_HEADER_PIECE).min(input.len() - added);
+                            self.header.extend_from_slice(&input[added..added + len]);
+                            let parsed = self.parse_header(&self.header);
+                            if !matches!(parsed, Err(Error::TruncatedInput { .. })) || added + len == input.len() {
+                                break parsed;
+                            }
+                        }
+                    };
+                    match parsed {
+                        Ok(len) => {
+                            // the header was incomplete without this input, so it ends in it
+                            consumed = len - buffered;
+                            self.inflater.reset(len as u64 * 8, &[]);
+                            self.header.clear();
+                            self.stage = Stage::Body;
+                        }
+                        Err(Error::TruncatedInput { .. }) if flush != Flush::Finish => {
+                            if buffered == 0 {
+                                self.header.extend_from_slice(input);
+                            }
+                            consumed = input.len();
+                            break;
+                        }
+                        Err(err) => return Err(err),
+                    }
+                    self.taken += consumed as u64;
+                }
+                Stage::Body => {
+                    // the ratio limit counts the input of this call, which is all taken unless the output fills up
+                    let (max_output, output_limit) = self.limits.output_budget(self.taken + (input.len() - consumed) as u64);
+                    self.inflater.set_output_limit(max_output);
+                    let (taken, written) = self.inflater.inflate(&input[consumed..], &mut output[produced..], false).map_err(|err| match err {
+                        Error::LimitExceeded(Limit::OutputSize) => Error::LimitExceeded(output_limit),
+                        err => err
+                    })?;
+                    consumed += taken;
+                    self.taken += taken as u64;
+
+                    let decoded = &output[produced..produced + written];
+                    match self.format {
+                        Format::Raw => {},
+                        Format::Zlib => self.adler32 = adler32_combine(self.adler32, adler32(decoded), decoded.len() as u64),
+                        Format::Gzip => self.crc32.update(decoded),
+                    }
+                    self.len += written as u64;
+                    produced += written;
+
+                    if self.inflater.is_done() {
+                        self.stage = Stage::Trailer;
+                    } else {
+                        // with room left in the output, decoding stopped because the input ran out
+                        if flush == Flush::Finish && produced < output.len() {
+                            return Err(Error::TruncatedInput { bit_offset: self.inflater.input_offset() * 8 });
+                        }
+                        break;
+                    }
+                }
+                Stage::Trailer => {
+                    let size = self.format.trailer_size();
+                    let missing = (size - self.trailer.len()).min(input.len() - consumed);
+                    self.trailer.extend_from_slice(&input[consumed..consumed + missing]);
+                    consumed += missing;
+                    self.taken += missing as u64;
+
+                    let end = self.inflater.bit_offset() / 8;
+                    if self.trailer.len() < size {
+                        if flush == Flush::Finish {
+                            return Err(Error::TruncatedInput { bit_offset: (end + self.trailer.len() as u64) * 8 });
+                        }
+                        break;
+                    }
+                    match self.format {
+                        Format::Raw => Ok(()),
+                        Format::Zlib => check_zlib_trailer(&self.trailer, self.adler32),
+                        Format::Gzip => check_trailer(&self.trailer, self.crc32.clone().finalize(), self.len),
+                    }.map_err(|err| err.offset_by(end))?;
+                    self.stage = Stage::Done;
+                    self.monitor.finish(self.taken, self.len);
+                }
+                Stage::Done => break,
+            }
+        }
+
+        Ok(Status::new(consumed, produced, self.stage == Stage::Done))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Vec<u8> {
+        (0..50_000u32).flat_map(|i| format!("{} ", i % 1234).into_bytes()).collect()
+    }
+
+    /// Compress `raw` with `piece`-byte input and output buffers
+    fn compress_in_pieces(format: Format, raw: &[u8], piece: usize) -> Vec<u8> {
+        let mut compressor = Compressor::new(format);
+        let mut dst = Vec::new();
+        let mut buf = vec![0; piece];
+        let mut pos = 0;
+
+        loop {
+            let input = &raw[pos..(pos + piece).min(raw.len())];
+            let flush = if pos + input.len() == raw.len() { Flush::Finish } else { Flush::None };
+            let status = compressor.compress(input, &mut buf, flush).unwrap();
+            pos += status.consumed;
+            dst.extend_from_slice(&buf[..status.produced]);
+            if status.state == State::StreamEnd {
+                return dst;
+            }
+        }
+    }
+
+    /// Decompress `src` with `piece`-byte input and output buffers, and return the output and the input consumed
+    fn decompress_in_pieces(format: Format, src: &[u8], piece: usize) -> (Vec<u8>, usize) {
+        let mut decompressor = Decompressor::new(format);
+        let mut dst = Vec::new();
+        let mut buf = vec![0; piece];
+        let mut pos = 0;
+
+        loop {
+            let input = &src[pos..(pos + piece).min(src.len())];
+            let status = decompressor.decompress(input, &mut buf, Flush::None).unwrap();
+            pos += status.consumed;
+            dst.extend_from_slice(&buf[..status.produced]);
+            match status.state {
+                State::StreamEnd => return (dst, pos),
+                State::BufError => panic!("no progress at {}", pos),
+                State::Ok => {}
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let raw = sample();
+        for format in [Format::Raw, Format::Zlib, Format::Gzip] {
+            for piece in [1000, 100_000] {
+                let src = compress_in_pieces(format, &raw, piece);
+                // followed by data that is not part of the stream
+                let padded = [src.as_slice(), b"garbage"].concat();
+                assert_eq!(decompress_in_pieces(format, &padded, piece), (raw.clone(), src.len()), "{:?} {}", format, piece);
+            }
+        }
+
+        let gz = compress_in_pieces(Format::Gzip, &raw, 4096);
+        assert_eq!(crate::deflate::inflate(&gz[10..]).unwrap().0, raw);
+        let zlib = compress_in_pieces(Format::Zlib, &raw, 4096);
+        assert_eq!(zlib[zlib.len() - 4..], adler32(&raw).to_be_bytes());
+    }
+
+    #[test]
+    fn test_compress_flush() {
+        let mut compressor = Compressor::new(Format::Raw);
+        let mut buf = vec![0; 1000];
+
+        let status = compressor.compress(b"Hello, world!", &mut buf, Flush::Sync).unwrap();
+        assert_eq!(status.state, State::Ok);
+        assert_eq!(buf[status.produced - 4..status.produced], [0x00, 0x00, 0xff, 0xff]);
+
+        let mut decompressor = Decompressor::new(Format::Raw);
+        let mut out = vec![0; 100];
+        let decoded = decompressor.decompress(&buf[..status.produced], &mut out, Flush::None).unwrap();
+        assert_eq!(&out[..decoded.produced], b"Hello, world!");
+        assert_eq!(decoded.state, State::Ok);
+
+        // nothing new to flush
+        let status = compressor.compress(&[], &mut buf, Flush::Sync).unwrap();
+        assert_eq!(status, Status { consumed: 0, produced: 0, state: State::BufError });
+    }
+
+    #[test]
+    fn test_small_output() {
+        // with a small output, the input is taken a little at a time instead of being compressed all at once
+        let raw = sample();
+        let mut compressor = Compressor::new(Format::Gzip);
+        let mut dst = Vec::new();
+        let mut buf = vec![0; 100];
+        let mut pos = 0;
+
+        loop {
+            let status = compressor.compress(&raw[pos..], &mut buf, Flush::Finish).unwrap();
+            assert!(status.consumed < raw.len());
+            assert!(compressor.pending.len() < 2 * INPUT_PIECE);
+            pos += status.consumed;
+            dst.extend_from_slice(&buf[..status.produced]);
+            if status.state == State::StreamEnd {
+                break;
+            }
+        }
+        assert_eq!(pos, raw.len());
+        assert_eq!(dst, compress_in_pieces(Format::Gzip, &raw, 1 << 20));
+    }
+
+    #[test]
+    fn test_buf_error() {
+        let src = compress_in_pieces(Format::Gzip, &sample(), 4096);
+        let mut decompressor = Decompressor::new(Format::Gzip);
+        let mut out = vec![0; 100];
+
+        let status = decompressor.decompress(&src[..5], &mut out, Flush::None).unwrap();
+        assert_eq!(status, Status { consumed: 5, produced: 0, state: State::Ok });
+        let status = decompressor.decompress(&[], &mut out, Flush::None).unwrap();
+        assert_eq!(status.state, State::BufError);
+        let status = decompressor.decompress(&src[5..], &mut [], Flush::None).unwrap();
+        assert_eq!(status.produced, 0);
+        // the rest of the header was taken without copying all the input
+        assert!(decompressor.header.capacity() < 1000);
+        let status = decompressor.decompress(&[], &mut [], Flush::None).unwrap();
+        assert_eq!(status.state, State::BufError);
+
+        // the input is cut short
+        let mut decompressor = Decompressor::new(Format::Gzip);
+        let mut out = vec![0; 1 << 20];
+        assert!(matches!(decompressor.decompress(&src[..src.len() - 2], &mut out, Flush::Finish), Err(Error::TruncatedInput { .. })));
+    }
+
+    #[test]
+    fn test_decompress_errors() {
+        let mut src = compress_in_pieces(Format::Zlib, b"Hello, world!", 100);
+        let len = src.len();
+        src[len - 1] ^= 1;
+        let mut out = vec![0; 100];
+        let result = Decompressor::new(Format::Zlib).decompress(&src, &mut out, Flush::Finish);
+        assert!(matches!(result, Err(Error::DataAdler32 { offset, .. }) if offset == len as u64 - 4));
+
+        let result = Decompressor::new(Format::Gzip).decompress(&src, &mut out, Flush::Finish);
+        assert!(matches!(result, Err(Error::BadMagic { offset: 0 })));
+    }
+
+    #[test]
+    fn test_decompress_limits() {
+        let raw = vec![0; 1 << 20];
+        let src = compress_in_pieces(Format::Gzip, &raw, 1 << 20);
+        let mut out = vec![0; 1 << 20];
+
+        let options = DecompressOptions { max_output: Some(raw.len() as u64), ..Default::default() };
+        let status = Decompressor::with_options(Format::Gzip, &options).decompress(&src, &mut out, Flush::Finish).unwrap();
+        assert_eq!(status.state, State::StreamEnd);
+
+        let options = DecompressOptions { max_output: Some(1000), ..Default::default() };
+        let result = Decompressor::with_options(Format::Gzip, &options).decompress(&src, &mut out, Flush::Finish);
+        assert!(matches!(result, Err(Error::LimitExceeded(Limit::OutputSize))));
+
+        let options = DecompressOptions { max_ratio: Some(10), ..Default::default() };
+        let result = Decompressor::with_options(Format::Gzip, &options).decompress(&src, &mut out, Flush::Finish);
+        assert!(matches!(result, Err(Error::LimitExceeded(Limit::Ratio))));
+    }
+
+    #[test]
+    fn test_endless_name() {
+        // a file name that never ends fails at the default limit instead of being buffered forever
+        let mut decompressor = Decompressor::new(Format::Gzip);
+        let mut out = [0; 64];
+        let status = decompressor.decompress(&[0x1f, 0x8b, 0x08, 0x08, 0, 0, 0, 0, 0, 3], &mut out, Flush::None).unwrap();
+        assert_eq!(status.consumed, 10);
+        let piece = [b'a'; 1000];
+        let result = (0..1000).try_for_each(|_| decompressor.decompress(&piece, &mut out, Flush::None).map(|_| ()));
+        assert!(matches!(result, Err(Error::LimitExceeded(Limit::NameLength))));
+    }
+
+    #[test]
+    fn test_reset() {
+        let payloads: Vec<Vec<u8>> = (0..20).map(|i| format!("{{\"seq\":{},\"payload\":\"{}\"}}", i, "ab".repeat(i * 10)).into_bytes())
+            .chain([sample()]).collect();
+
+        for format in [Format::Raw, Format::Zlib, Format::Gzip] {
+            let mut compressor = Compressor::new(format);
+            let mut decompressor = Decompressor::new(format);
+            let mut buf = vec![0; 1 << 20];
+            let mut out = vec![0; 1 << 20];
+
+            for raw in &payloads {
+                let status = compressor.compress(raw, &mut buf, Flush::Finish).unwrap();
+                assert_eq!(status.state, State::StreamEnd);
+                let src = &buf[..status.produced];
+                assert_eq!(src, compress_in_pieces(format, raw, 1 << 20), "{:?}", format);
+                compressor.reset();
+
+                let status = decompressor.decompress(src, &mut out, Flush::Finish).unwrap();
+                assert_eq!(status, Status { consumed: src.len(), produced: raw.len(), state: State::StreamEnd });
+                assert_eq!(out[..raw.len()], raw[..]);
+                decompressor.reset();
+            }
+        }
+
+        // reset in the middle of a stream, with output not written yet
+        let raw = sample();
+        let src = compress_in_pieces(Format::Gzip, &raw, 4096);
+        let mut compressor = Compressor::new(Format::Gzip);
+        compressor.compress(b"dropped", &mut [0; 4], Flush::Sync).unwrap();
+        compressor.reset();
+        let mut buf = vec![0; src.len()];
+        assert_eq!(compressor.compress(&raw, &mut buf, Flush::Finish).unwrap().state, State::StreamEnd);
+        assert_eq!(buf, src);
+
+        let mut decompressor = Decompressor::new(Format::Gzip);
+        decompressor.decompress(&src[..src.len() / 2], &mut [0; 10], Flush::None).unwrap();
+        decompressor.reset();
+        let mut out = vec![0; raw.len()];
+        assert_eq!(decompressor.decompress(&src, &mut out, Flush::Finish).unwrap().state, State::StreamEnd);
+        assert_eq!(out, raw);
+    }
+
+    #[test]
+    fn test_progress() {
+        use crate::progress::{CancelToken, Progress};
+        use std::sync::{Arc, Mutex};
+
+        let raw = sample();
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let recorded = calls.clone();
+        let progress = Progress::new(50_000, move |read, written| recorded.lock().unwrap().push((read, written)));
+
+        let options = CompressOptions { progress: Some(progress.clone()), ..Default::default() };
+        let mut compressor = Compressor::with_options(Format::Gzip, &options).unwrap();
+        let mut src = Vec::new();
+        let mut buf = vec![0; 1000];
+        let mut pos = 0;
+        loop {
+            let status = compressor.compress(&raw[pos..], &mut buf, Flush::Finish).unwrap();
+            pos += status.consumed;
+            src.extend_from_slice(&buf[..status.produced]);
+            if status.state == State::StreamEnd {
+                break;
+            }
+        }
+        let compressing = calls.lock().unwrap().split_off(0);
+        assert!(compressing.len() >= 3, "{:?}", compressing);
+        assert_eq!(compressing.last(), Some(&(raw.len() as u64, src.len() as u64)));
+
+        // the compressed data is much shorter, so it is reported more often
+        let recorded = calls.clone();
+        let progress = Progress::new(1000, move |read, written| recorded.lock().unwrap().push((read, written)));
+        let limits = DecompressOptions { progress: Some(progress), ..Default::default() };
+        let mut decompressor = Decompressor::with_options(Format::Gzip, &limits);
+        let mut out = vec![0; raw.len()];
+        let mut produced = 0;
+        for piece in src.chunks(500) {
+            produced += decompressor.decompress(piece, &mut out[produced..], Flush::None).unwrap().produced;
+        }
+        assert_eq!(out, raw);
+        let decompressing = calls.lock().unwrap().split_off(0);
+        assert!(decompressing.len() >= 2, "{:?}", decompressing);
+        assert_eq!(decompressing.last(), Some(&(src.len() as u64, raw.len() as u64)));
+
+        let cancel = CancelToken::new();
+        let options = CompressOptions { cancel: Some(cancel.clone()), ..Default::default() };
+        let mut compressor = Compressor::with_options(Format::Gzip, &options).unwrap();
+        let limits = DecompressOptions { cancel: Some(cancel.clone()), ..Default::default() };
+        let mut decompressor = Decompressor::with_options(Format::Gzip, &limits);
+        assert!(compressor.compress(&raw[..1000], &mut buf, Flush::None).is_ok());
+        assert!(decompressor.decompress(&src[..1000], &mut out, Flush::None).is_ok());
+        cancel.cancel();
+        assert!(matches!(compressor.compress(&raw[1000..], &mut buf, Flush::None), Err(Error::Cancelled)));
+        assert!(matches!(decompressor.decompress(&src[1000..], &mut out, Flush::None), Err(Error::Cancelled)));
+    }
+
+    #[test]
+    fn test_window_bits() {
+        let raw = sample();
+        let options = CompressOptions { window_bits: Some(9), mem_level: Some(2), ..Default::default() };
+        let mut compressor = Compressor::with_options(Format::Zlib, &options).unwrap();
+        let mut dst = vec![0; raw.len()];
+        let status = compressor.compress(&raw, &mut dst, Flush::Finish).unwrap();
+        assert_eq!(status.state, State::StreamEnd);
+        // CINFO = 1, a 512-byte window
+        assert_eq!(dst[0], 0x18);
+        assert_eq!(decompress_in_pieces(Format::Zlib, &dst[..status.produced], 4096), (raw, status.produced));
+
+        let options = CompressOptions { window_bits: Some(16), ..Default::default() };
+        assert!(matches!(Compressor::with_options(Format::Zlib, &options), Err(Error::InvalidOption("window_bits"))));
+        let options = CompressOptions { mem_level: Some(0), ..Default::default() };
+        assert!(matches!(Compressor::with_options(Format::Raw, &options), Err(Error::InvalidOption("mem_level"))));
+    }
+}
@@ -0,0 +1,77 @@
+use crate::error::{Error, Result};
+
+/// The compression method of the CMF byte: deflate
+const CM_DEFLATE: u8 = 8;
+
+/// The zlib header written by the compressor: deflate with a `1 << window_bits` window, the compression level,
+/// and the check bits. With a 32 KiB window and the default level, it is the usual 78 9c.
+pub(crate) fn zlib_header(window_bits: u8, level: u8) -> [u8; 2] {
+    // CINFO is the base-2 logarithm of the window size minus 8
+    let cmf = ((window_bits - 8) << 4) | CM_DEFLATE;
+    // FLEVEL: fastest, fast, default or maximum compression, as zlib maps its levels
+    let flevel = match level {
+        0..=1 => 0,
+        2..=5 => 1,
+        6 => 2,
+        _ => 3
+    } << 6;
+    let check = 31 - (cmf as u16 * 256 + flevel as u16) % 31;
+    [cmf, flevel | (check % 31) as u8]
+}
+
+/// The preset dictionary flag of the FLG byte
+const FDICT: u8 = 0x20;
+
+/// Parse the 2-byte zlib header (See RFC 1950, Section 2.2) at the start of the data, and return its length
+pub(crate) fn parse_zlib_header(data: &[u8]) -> Result<usize> {
+    let header = data.get(..2).ok_or(Error::TruncatedInput { bit_offset: data.len() as u64 * 8 })?;
+    let (cmf, flg) = (header[0], header[1]);
+
+    if cmf & 0x0f != CM_DEFLATE {
+        return Err(Error::UnsupportedMethod { method: cmf & 0x0f, offset: 0 });
+    }
+    // CINFO is the base-2 logarithm of the window size minus 8
+    if cmf >> 4 > 7 || !(cmf as u16 * 256 + flg as u16).is_multiple_of(31) || flg & FDICT != 0 {
+        return Err(Error::BadZlibHeader { offset: 0 });
+    }
+    Ok(2)
+}
+
+/// Check the 4-byte trailer of a zlib stream, the Adler-32 of the decompressed data in big endian
+pub(crate) fn check_zlib_trailer(trailer: &[u8], actual_adler32: u32) -> Result<()> {
+    let adler32 = u32::from_be_bytes(trailer[0..4].try_into().unwrap());
+    if adler32 != actual_adler32 {
+        return Err(Error::DataAdler32 { expected: adler32, actual: actual_adler32, offset: 0 });
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_zlib_header() {
+        // the headers zlib writes
+        assert_eq!(zlib_header(15, 6), [0x78, 0x9c]);
+        assert_eq!(zlib_header(15, 1), [0x78, 0x01]);
+        assert_eq!(zlib_header(15, 9), [0x78, 0xda]);
+        assert_eq!(zlib_header(8, 6), [0x08, 0x99]);
+        for window_bits in 8..=15 {
+            for level in 0..=9 {
+                assert_eq!(parse_zlib_header(&zlib_header(window_bits, level)).unwrap(), 2);
+            }
+        }
+        // the headers written by zlib at levels 1 and 9, and with a 256-byte window
+        assert_eq!(parse_zlib_header(&[0x78, 0x01]).unwrap(), 2);
+        assert_eq!(parse_zlib_header(&[0x78, 0xda]).unwrap(), 2);
+        assert_eq!(parse_zlib_header(&[0x08, 0x1d]).unwrap(), 2);
+
+        assert!(matches!(parse_zlib_header(&[0x78]), Err(Error::TruncatedInput { bit_offset: 8 })));
+        assert!(matches!(parse_zlib_header(&[0x78, 0x9d]), Err(Error::BadZlibHeader { offset: 0 })));
+        assert!(matches!(parse_zlib_header(&[0x79, 0x9c]), Err(Error::UnsupportedMethod { method: 9, offset: 0 })));
+        // a preset dictionary
+        assert!(matches!(parse_zlib_header(&[0x78, 0xbb]), Err(Error::BadZlibHeader { offset: 0 })));
+    }
+}
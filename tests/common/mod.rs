@@ -0,0 +1,38 @@
+//! Helpers shared by the integration tests
+
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// A path in the temporary directory, removed with whatever was written there once the test is done
+pub struct TempPath(PathBuf);
+
+impl Deref for TempPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempPath {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        // nothing may have been written, and the test may have made a directory
+        if self.0.is_dir() {
+            let _ = fs::remove_dir_all(&self.0);
+        } else {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+}
+
+/// A unique path for `name`, so that tests running at the same time do not clash
+pub fn temp_path(name: &str) -> TempPath {
+    TempPath(std::env::temp_dir().join(format!("rustgzip_test_{}_{}", std::process::id(), name)))
+}
@@ -0,0 +1,28 @@
+#![cfg(feature = "std")]
+
+mod common;
+
+use rustgzip::{decompress_from_gzip, BgzfReader, BgzfWriter};
+use common::temp_path;
+use std::fs::{self, File};
+use std::io::{BufReader, Read, Write};
+
+#[test]
+fn test_bgzf_file() {
+    let data = fs::read("examples/stdio.h").unwrap().repeat(5);
+    let path = temp_path("stdio.h.bgz");
+
+    let mut writer = BgzfWriter::new(File::create(&path).unwrap());
+    writer.write_all(&data).unwrap();
+    writer.finish().unwrap();
+
+    // BGZF is also plain multi-member gzip
+    let restored = temp_path("stdio.h.bgz.out");
+    decompress_from_gzip(&path, &restored).unwrap();
+    assert_eq!(fs::read(&restored).unwrap(), data);
+
+    let mut reader = BgzfReader::new(BufReader::new(File::open(&path).unwrap()));
+    let mut restored = Vec::new();
+    reader.read_to_end(&mut restored).unwrap();
+    assert_eq!(restored, data);
+}
@@ -1,3 +1,4 @@
+#![allow(clippy::unusual_byte_groupings)] // the groups follow the bit fields being written
 use bitstream_io::{BitWriter, LittleEndian, BitWrite, BigEndian};
 
 #[test]
@@ -0,0 +1,26 @@
+#![cfg(feature = "std")]
+
+mod common;
+
+use rustgzip::{decompress_from_gzip, GzEncoder};
+use common::temp_path;
+use std::fs::{self, File};
+use std::io::Write;
+
+#[test]
+fn test_encoder_file() {
+    let data = fs::read("examples/stdio.h").unwrap();
+    let path = temp_path("stdio.h.encoder.gz");
+
+    // one flush per line, as a message boundary
+    let mut encoder = GzEncoder::new(File::create(&path).unwrap());
+    for line in data.split_inclusive(|&byte| byte == b'\n') {
+        encoder.write_all(line).unwrap();
+        encoder.flush().unwrap();
+    }
+    encoder.finish().unwrap();
+
+    let restored = temp_path("stdio.h.encoder.out");
+    decompress_from_gzip(&path, &restored).unwrap();
+    assert_eq!(fs::read(&restored).unwrap(), data);
+}
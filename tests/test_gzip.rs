@@ -1,8 +1,199 @@
-use rustgzip::compress_to_gzip;
+#![cfg(feature = "std")]
 
+mod common;
+
+use rustgzip::{compress_to_gzip, compress_to_gzip_with_options, decompress_from_gzip, decompress_from_gzip_with_options};
+use rustgzip::{compress_bound, compress_into, decompress_into, gzip_bytes, gunzip_bytes, zlib_compress_bytes, zlib_decompress_bytes};
+use rustgzip::{CompressOptions, DecompressOptions, Error, Limit};
+use common::temp_path;
+use std::fs::{self, File, FileTimes};
+use std::time::{Duration, SystemTime};
 
 #[test]
 fn test_gzip() {
-    compress_to_gzip("tests/example1", "tests/example1.gz").unwrap();
-    compress_to_gzip("tests/stdio.h", "tests/stdio.h.gz").unwrap();
-}
\ No newline at end of file
+    for name in ["example1", "stdio.h"] {
+        let gz = temp_path(&format!("{}.gz", name));
+        let restored = temp_path(name);
+
+        compress_to_gzip(format!("examples/{}", name), gz.to_str().unwrap()).unwrap();
+        decompress_from_gzip(gz.to_str().unwrap(), restored.to_str().unwrap()).unwrap();
+
+        assert_eq!(fs::read(&restored).unwrap(), fs::read(format!("examples/{}", name)).unwrap());
+    }
+}
+
+#[test]
+fn test_gunzip_reference() {
+    let restored = temp_path("reference_stdio.h");
+    decompress_from_gzip("examples/stdio.h.gz", restored.to_str().unwrap()).unwrap();
+    assert_eq!(fs::read(&restored).unwrap(), fs::read("examples/stdio.h").unwrap());
+}
+
+#[test]
+fn test_preserve_metadata() {
+    let src = temp_path("metadata");
+    let gz = temp_path("metadata.gz");
+    let restored = temp_path("metadata.out");
+
+    fs::write(&src, "metadata test\n").unwrap();
+    let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    let atime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_100_000_000);
+    File::options().write(true).open(&src).unwrap()
+        .set_times(FileTimes::new().set_modified(mtime).set_accessed(atime)).unwrap();
+    let mut permissions = fs::metadata(&src).unwrap().permissions();
+    permissions.set_readonly(true);
+    fs::set_permissions(&src, permissions.clone()).unwrap();
+
+    compress_to_gzip(src.to_str().unwrap(), gz.to_str().unwrap()).unwrap();
+    let gz_metadata = fs::metadata(&gz).unwrap();
+    assert_eq!(gz_metadata.modified().unwrap(), mtime);
+    assert_eq!(gz_metadata.accessed().unwrap(), atime);
+    assert_eq!(gz_metadata.permissions(), permissions);
+
+    // the restored file gets the header mtime even if the .gz has been touched since
+    File::open(&gz).unwrap().set_times(FileTimes::new().set_modified(SystemTime::now())).unwrap();
+
+    decompress_from_gzip(gz.to_str().unwrap(), restored.to_str().unwrap()).unwrap();
+    assert_eq!(fs::metadata(&restored).unwrap().modified().unwrap(), mtime);
+    assert_eq!(fs::metadata(&restored).unwrap().permissions(), permissions);
+    assert_eq!(fs::read(&restored).unwrap(), b"metadata test\n");
+}
+
+#[test]
+fn test_in_memory() {
+    let raw = fs::read("examples/stdio.h").unwrap();
+    assert_eq!(gunzip_bytes(&fs::read("examples/stdio.h.gz").unwrap(), &DecompressOptions::default()).unwrap(), raw);
+    let mut buf = vec![0; raw.len()];
+    assert_eq!(decompress_into(&fs::read("examples/stdio.h.gz").unwrap(), &mut buf).unwrap(), raw.len());
+    assert_eq!(buf, raw);
+
+    let mut frame = vec![0; compress_bound(raw.len())];
+    let len = compress_into(&raw, &mut frame).unwrap();
+    assert_eq!(gunzip_bytes(&frame[..len], &DecompressOptions::default()).unwrap(), raw);
+
+    // the file function is the in-memory one with the name and time stamp of the file
+    let src = temp_path("in_memory.h");
+    let gz = temp_path("in_memory.h.gz");
+    fs::write(&src, &raw).unwrap();
+    File::options().write(true).open(&src).unwrap()
+        .set_times(FileTimes::new().set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(1234))).unwrap();
+    assert!(!compress_to_gzip(&src, &gz).unwrap().mtime_out_of_range);
+    let name = src.file_name().unwrap().to_str().unwrap().as_bytes().to_vec();
+    let options = CompressOptions { name: Some(name), mtime: Some(1234), ..Default::default() };
+    assert_eq!(fs::read(&gz).unwrap(), gzip_bytes(&raw, &options).unwrap());
+
+    // a name in the options replaces the name of the file
+    let options = CompressOptions { name: Some(b"renamed.h".to_vec()), comment: Some(b"a comment".to_vec()), ..Default::default() };
+    compress_to_gzip_with_options(&src, &gz, &options).unwrap();
+    assert_eq!(&fs::read(&gz).unwrap()[10..30], b"renamed.h\0a comment\0");
+
+    let zlib = zlib_compress_bytes(&raw, &CompressOptions { level: Some(9), ..Default::default() }).unwrap();
+    assert_eq!(zlib[..2], [0x78, 0xda]);
+    assert_eq!(zlib_decompress_bytes(&zlib, &DecompressOptions::default()).unwrap(), raw);
+}
+
+#[test]
+fn test_reproducible() {
+    let a = temp_path("reproducible_a");
+    let b = temp_path("reproducible_b");
+    let a_gz = temp_path("reproducible_a.gz");
+    let b_gz = temp_path("reproducible_b.gz");
+    fs::write(&a, fs::read("examples/stdio.h").unwrap()).unwrap();
+    fs::write(&b, fs::read("examples/stdio.h").unwrap()).unwrap();
+    File::options().write(true).open(&b).unwrap()
+        .set_times(FileTimes::new().set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(1234))).unwrap();
+
+    let options = CompressOptions { no_name: true, ..Default::default() };
+    compress_to_gzip_with_options(a.to_str().unwrap(), a_gz.to_str().unwrap(), &options).unwrap();
+    compress_to_gzip_with_options(b.to_str().unwrap(), b_gz.to_str().unwrap(), &options).unwrap();
+
+    let gz = fs::read(&a_gz).unwrap();
+    assert_eq!(gz, fs::read(&b_gz).unwrap());
+    assert_eq!(gz[3], 0); // no FNAME
+    assert_eq!(gz[4..8], [0, 0, 0, 0]);
+
+    let options = CompressOptions { no_name: true, mtime: Some(1_700_000_000), ..Default::default() };
+    compress_to_gzip_with_options(a.to_str().unwrap(), a_gz.to_str().unwrap(), &options).unwrap();
+    let gz = fs::read(&a_gz).unwrap();
+    assert_eq!(gz[4..8], 1_700_000_000_u32.to_le_bytes());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_non_utf8_name() {
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = temp_path("non_utf8");
+    fs::create_dir_all(&dir).unwrap();
+    let src = dir.join(std::ffi::OsStr::from_bytes(b"name\xff"));
+    fs::write(&src, "abc").unwrap();
+    File::options().write(true).open(&src).unwrap()
+        .set_times(FileTimes::new().set_modified(SystemTime::UNIX_EPOCH - Duration::from_secs(86400))).unwrap();
+
+    let gz = dir.join("out.gz");
+    assert!(compress_to_gzip(&src, &gz).unwrap().mtime_out_of_range);
+
+    let data = fs::read(&gz).unwrap();
+    assert_eq!(data[4..8], [0, 0, 0, 0]); // pre-epoch mtime
+    assert_eq!(&data[10..16], b"name\xff\0");
+}
+
+#[test]
+fn test_parallel() {
+    let src = temp_path("parallel");
+    let gz = temp_path("parallel.gz");
+    let restored = temp_path("parallel.out");
+
+    // several chunks of 128 KiB
+    let data = fs::read("examples/stdio.h").unwrap().repeat(20);
+    fs::write(&src, &data).unwrap();
+
+    let options = CompressOptions { threads: 4, ..Default::default() };
+    compress_to_gzip_with_options(&src, &gz, &options).unwrap();
+    decompress_from_gzip(&gz, &restored).unwrap();
+
+    assert_eq!(fs::read(&restored).unwrap(), data);
+}
+
+#[test]
+fn test_rsyncable() {
+    let src = temp_path("rsyncable");
+    let gz = temp_path("rsyncable.gz");
+    let restored = temp_path("rsyncable.out");
+
+    let data = fs::read("examples/stdio.h").unwrap().repeat(4);
+    fs::write(&src, &data).unwrap();
+
+    let options = CompressOptions { no_name: true, rsyncable: true, ..Default::default() };
+    compress_to_gzip_with_options(&src, &gz, &options).unwrap();
+    decompress_from_gzip(&gz, &restored).unwrap();
+
+    assert_eq!(fs::read(&restored).unwrap(), data);
+}
+
+#[test]
+fn test_decompress_limits() {
+    let src = temp_path("bomb");
+    let gz = temp_path("bomb.gz");
+    let restored = temp_path("bomb.out");
+
+    // 4 MiB of zeros, about a thousand times smaller compressed, followed by a second member
+    fs::write(&src, vec![0; 4 << 20]).unwrap();
+    compress_to_gzip(&src, &gz).unwrap();
+    let member = fs::read(&gz).unwrap();
+    fs::write(&gz, [member.as_slice(), &member].concat()).unwrap();
+
+    let limit = |options: DecompressOptions| match decompress_from_gzip_with_options(&gz, &restored, &options) {
+        Err(Error::LimitExceeded(limit)) => Some(limit),
+        Err(err) => panic!("{}", err),
+        Ok(()) => None
+    };
+    assert_eq!(limit(DecompressOptions { max_output: Some(8 << 20), max_members: Some(2), ..Default::default() }), None);
+    assert_eq!(fs::metadata(&restored).unwrap().len(), 8 << 20);
+
+    assert_eq!(limit(DecompressOptions { max_output: Some(1 << 20), ..Default::default() }), Some(Limit::OutputSize));
+    assert_eq!(limit(DecompressOptions { max_ratio: Some(100), ..Default::default() }), Some(Limit::Ratio));
+    assert_eq!(limit(DecompressOptions { max_members: Some(1), ..Default::default() }), Some(Limit::Members));
+    // the header holds the name of the source file
+    assert_eq!(limit(DecompressOptions { max_header_field: Some(4), ..Default::default() }), Some(Limit::NameLength));
+}
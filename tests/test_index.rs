@@ -0,0 +1,25 @@
+#![cfg(feature = "std")]
+
+use rustgzip::{GzIndex, GzSeekableReader};
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+
+#[test]
+fn test_seek_reference() {
+    // compressed by gzip, with dynamic blocks
+    let data = fs::read("examples/stdio.h").unwrap();
+    let index = GzIndex::build(File::open("examples/stdio.h.gz").unwrap(), 4096).unwrap();
+    assert_eq!(index.uncompressed_len(), data.len() as u64);
+
+    let mut serialized = Vec::new();
+    index.write_to(&mut serialized).unwrap();
+    let index = GzIndex::read_from(serialized.as_slice()).unwrap();
+
+    let mut reader = GzSeekableReader::new(File::open("examples/stdio.h.gz").unwrap(), index).unwrap();
+    for offset in [30_000, 100, 20_000, 20_001, 0] {
+        reader.seek(SeekFrom::Start(offset)).unwrap();
+        let mut buf = [0; 500];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, data[offset as usize..offset as usize + 500]);
+    }
+}